@@ -0,0 +1,4 @@
+use criterion::{criterion_group, criterion_main};
+
+criterion_group!(benches, fast_set::bench::benches);
+criterion_main!(benches);
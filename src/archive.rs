@@ -0,0 +1,591 @@
+//! Zero-copy [rkyv] archives of [`FlatSetIndex`], [`OneIndex`] and [`Tree`].
+//!
+//! Archives are flat, sorted arrays queried by binary search, so a validated
+//! byte slice (for instance a memory-mapped file) can be read in place without
+//! a deserialization pass.
+
+use crate::{FlatSetIndex, Tree, U32SetExt, one_index::OneIndex, strict::try_key};
+use rkyv::{
+    Archive, Portable, Serialize,
+    bytecheck::CheckBytes,
+    rancor::{Error, Source, Strategy},
+    rend::{u32_le, u64_le},
+    ser::allocator::ArenaHandle,
+    util::AlignedVec,
+    validation::{Validator, archive::ArchiveValidator, shared::SharedValidator},
+};
+use std::{fmt, marker::PhantomData};
+
+type HighSerializer<'a> = rkyv::api::high::HighSerializer<AlignedVec, ArenaHandle<'a>, Error>;
+type HighValidator<'a> = Strategy<Validator<ArchiveValidator<'a>, SharedValidator>, Error>;
+
+/// Validates `bytes` and returns the archived root without copying.
+fn access<T>(bytes: &[u8]) -> Result<&T, Error>
+where
+    T: Portable + for<'a> CheckBytes<HighValidator<'a>>,
+{
+    rkyv::access::<T, Error>(bytes)
+}
+
+/// An archive whose arrays are well formed but do not fit together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidArchive(&'static str);
+
+impl fmt::Display for InvalidArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid archive: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidArchive {}
+
+#[inline]
+fn ensure(ok: bool, what: &'static str) -> Result<(), Error> {
+    if ok {
+        Ok(())
+    } else {
+        Err(Error::new(InvalidArchive(what)))
+    }
+}
+
+/// Whether `values` is strictly ascending, as [`search`] requires.
+#[inline]
+fn is_ascending(values: &[u32_le]) -> bool {
+    values.is_sorted_by(|a, b| a.to_native() < b.to_native())
+}
+
+/// Whether `offsets` slices `len` ranges out of an array of `total` values.
+fn is_offsets(offsets: &[u64_le], len: usize, total: usize) -> bool {
+    offsets.len() == len + 1
+        && offsets.first().is_some_and(|o| o.to_native() == 0)
+        && offsets.is_sorted_by(|a, b| a.to_native() <= b.to_native())
+        && offsets
+            .last()
+            .is_some_and(|o| o.to_native() == total as u64)
+}
+
+/// Whether each range that `offsets` slices out of `values` is strictly
+/// ascending. `offsets` must have passed [`is_offsets`].
+fn are_ranges_ascending(offsets: &[u64_le], values: &[u32_le]) -> bool {
+    offsets.windows(2).all(|w| {
+        let start = w[0].to_native() as usize;
+        let end = w[1].to_native() as usize;
+        is_ascending(&values[start..end])
+    })
+}
+
+/// Sorted slice of archived values, read as a set of `V`.
+pub struct ArchivedIntSet<'a, V> {
+    values: &'a [u32_le],
+    _v: PhantomData<V>,
+}
+
+impl<'a, V> ArchivedIntSet<'a, V> {
+    #[inline]
+    fn new(values: &'a [u32_le]) -> Self {
+        Self {
+            values,
+            _v: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, value: V) -> bool
+    where
+        V: Into<u32>,
+    {
+        search(self.values, value.into()).is_ok()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Values in ascending order.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = V> + 'a
+    where
+        V: TryFrom<u32>,
+    {
+        self.values.iter().filter_map(|v| try_key(v.to_native()))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+impl<V> Clone for ArchivedIntSet<'_, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<V> Copy for ArchivedIntSet<'_, V> {}
+
+/// Serializable form of a [`FlatSetIndex`], stored as sorted keys with the
+/// sorted values of each key packed in one array.
+#[derive(Archive, Serialize)]
+#[rkyv(archived = ArchivedFlatSetIndex)]
+pub struct FlatSetIndexArchive<K, V> {
+    keys: Vec<u32>,
+    none: Vec<u32>,
+    offsets: Vec<u64>,
+    values: Vec<u32>,
+    _kv: PhantomData<(K, V)>,
+}
+
+impl<K, V> From<&FlatSetIndex<K, V>> for FlatSetIndexArchive<K, V> {
+    fn from(index: &FlatSetIndex<K, V>) -> Self {
+        let mut keys = index.inner.keys().copied().collect::<Vec<_>>();
+        keys.sort_unstable();
+
+        let mut offsets = Vec::with_capacity(keys.len() + 1);
+        let mut values = Vec::new();
+
+        offsets.push(0);
+
+        for k in &keys {
+            values.extend(index.inner.get(k).as_set().sorted());
+            offsets.push(values.len() as u64);
+        }
+
+        Self {
+            keys,
+            none: index.inner.none().as_set().sorted(),
+            offsets,
+            values,
+            _kv: PhantomData,
+        }
+    }
+}
+
+impl<K, V> FlatSetIndex<K, V> {
+    /// Serializes the index into an archive readable with
+    /// [`ArchivedFlatSetIndex::access`].
+    pub fn to_rkyv_bytes(&self) -> Result<AlignedVec, Error> {
+        rkyv::to_bytes::<Error>(&FlatSetIndexArchive::from(self))
+    }
+}
+
+impl<K, V> ArchivedFlatSetIndex<K, V> {
+    /// Validates `bytes` and reads the archive in place. Besides the rkyv
+    /// checks, the keys must be ascending, the offsets must cover the values
+    /// and the values of each key, like `none`, must be ascending.
+    pub fn access(bytes: &[u8]) -> Result<&Self, Error> {
+        let index = access::<Self>(bytes)?;
+
+        ensure(is_ascending(&index.keys), "unsorted keys")?;
+        ensure(
+            is_offsets(&index.offsets, index.keys.len(), index.values.len()),
+            "bad offsets",
+        )?;
+        ensure(
+            are_ranges_ascending(&index.offsets, &index.values),
+            "unsorted values",
+        )?;
+        ensure(is_ascending(&index.none), "unsorted none values")?;
+
+        Ok(index)
+    }
+
+    #[inline]
+    pub fn contains(&self, key: K, value: V) -> bool
+    where
+        K: Into<u32>,
+        V: Into<u32>,
+    {
+        self.get(key).contains(value)
+    }
+
+    pub fn get(&self, key: K) -> ArchivedIntSet<'_, V>
+    where
+        K: Into<u32>,
+    {
+        match search(&self.keys, key.into()) {
+            Ok(i) => {
+                let start = self.offsets[i].to_native() as usize;
+                let end = self.offsets[i + 1].to_native() as usize;
+                ArchivedIntSet::new(&self.values[start..end])
+            }
+            Err(_) => ArchivedIntSet::new(&[]),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.none.is_empty()
+    }
+
+    /// Keys in ascending order.
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_
+    where
+        K: TryFrom<u32>,
+    {
+        self.keys.iter().filter_map(|k| try_key(k.to_native()))
+    }
+
+    #[inline]
+    pub fn none(&self) -> ArchivedIntSet<'_, V> {
+        ArchivedIntSet::new(&self.none)
+    }
+}
+
+/// Serializable form of a [`OneIndex`], stored as sorted keys and their values.
+#[derive(Archive, Serialize)]
+#[rkyv(archived = ArchivedOneIndex)]
+pub struct OneIndexArchive<K, V> {
+    keys: Vec<u32>,
+    values: Vec<V>,
+    _k: PhantomData<K>,
+}
+
+impl<K, V: Clone> From<&OneIndex<K, V>> for OneIndexArchive<K, V> {
+    fn from(index: &OneIndex<K, V>) -> Self {
+        let mut entries = index.index.iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(k, _)| *k);
+
+        let (keys, values) = entries.into_iter().map(|(k, v)| (k, v.clone())).unzip();
+
+        Self {
+            keys,
+            values,
+            _k: PhantomData,
+        }
+    }
+}
+
+impl<K, V> OneIndex<K, V> {
+    /// Serializes the index into an archive readable with
+    /// [`ArchivedOneIndex::access`].
+    pub fn to_rkyv_bytes(&self) -> Result<AlignedVec, Error>
+    where
+        V: Clone,
+        OneIndexArchive<K, V>: for<'a> Serialize<HighSerializer<'a>>,
+    {
+        rkyv::to_bytes::<Error>(&OneIndexArchive::from(self))
+    }
+}
+
+impl<K, V: Archive> ArchivedOneIndex<K, V> {
+    /// Validates `bytes` and reads the archive in place. Besides the rkyv
+    /// checks, the keys must be ascending and match the values one to one.
+    pub fn access(bytes: &[u8]) -> Result<&Self, Error>
+    where
+        Self: for<'a> CheckBytes<HighValidator<'a>>,
+    {
+        let index = access::<Self>(bytes)?;
+
+        ensure(is_ascending(&index.keys), "unsorted keys")?;
+        ensure(
+            index.keys.len() == index.values.len(),
+            "keys without values",
+        )?;
+        Ok(index)
+    }
+
+    #[inline]
+    pub fn contains_key(&self, key: K) -> bool
+    where
+        K: Into<u32>,
+    {
+        search(&self.keys, key.into()).is_ok()
+    }
+
+    pub fn get(&self, key: K) -> Option<&V::Archived>
+    where
+        K: Into<u32>,
+    {
+        let i = search(&self.keys, key.into()).ok()?;
+        Some(&self.values[i])
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Entries in ascending key order.
+    pub fn iter(&self) -> impl Iterator<Item = (K, &V::Archived)> + '_
+    where
+        K: TryFrom<u32>,
+    {
+        self.keys
+            .iter()
+            .zip(self.values.iter())
+            .filter_map(|(k, v)| Some((try_key(k.to_native())?, v)))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// Serializable form of a [`Tree`], stored as sorted nodes with their parent
+/// and their sorted children packed in one array.
+#[derive(Archive, Serialize)]
+#[rkyv(archived = ArchivedTree)]
+pub struct TreeArchive<K> {
+    children: Vec<u32>,
+    nodes: Vec<u32>,
+    offsets: Vec<u64>,
+    parents: Vec<Option<u32>>,
+    _k: PhantomData<K>,
+}
+
+impl<K> From<&Tree<K>> for TreeArchive<K> {
+    fn from(tree: &Tree<K>) -> Self {
+        let tree = &tree.erased;
+        let nodes = tree.all_nodes().sorted();
+        let mut children = Vec::new();
+        let mut offsets = Vec::with_capacity(nodes.len() + 1);
+
+        offsets.push(0);
+
+        for n in &nodes {
+            children.extend(tree.children(*n).sorted());
+            offsets.push(children.len() as u64);
+        }
+
+        Self {
+            children,
+            parents: nodes.iter().map(|n| tree.parent(*n)).collect(),
+            nodes,
+            offsets,
+            _k: PhantomData,
+        }
+    }
+}
+
+impl<K> Tree<K> {
+    /// Serializes the tree into an archive readable with
+    /// [`ArchivedTree::access`].
+    pub fn to_rkyv_bytes(&self) -> Result<AlignedVec, Error> {
+        rkyv::to_bytes::<Error>(&TreeArchive::from(self))
+    }
+}
+
+impl<K> ArchivedTree<K> {
+    /// Validates `bytes` and reads the archive in place. Besides the rkyv
+    /// checks, the nodes must be ascending, each with a parent, the offsets
+    /// must cover the children and the children of each node must be ascending.
+    pub fn access(bytes: &[u8]) -> Result<&Self, Error> {
+        let tree = access::<Self>(bytes)?;
+
+        ensure(is_ascending(&tree.nodes), "unsorted nodes")?;
+        ensure(
+            tree.parents.len() == tree.nodes.len(),
+            "nodes without parents",
+        )?;
+        ensure(
+            is_offsets(&tree.offsets, tree.nodes.len(), tree.children.len()),
+            "bad offsets",
+        )?;
+        ensure(
+            are_ranges_ascending(&tree.offsets, &tree.children),
+            "unsorted children",
+        )?;
+
+        Ok(tree)
+    }
+
+    /// Ancestors of `child`, nearest first. Stops after visiting every node,
+    /// so a cycle cannot loop forever.
+    pub fn ancestors(&self, child: K) -> impl Iterator<Item = K> + '_
+    where
+        K: Into<u32> + TryFrom<u32>,
+    {
+        let mut next = self.parent_u32(child.into());
+
+        std::iter::from_fn(move || {
+            let node = next?;
+            next = self.parent_u32(node);
+            Some(node)
+        })
+        .take(self.nodes.len())
+        .filter_map(try_key)
+    }
+
+    /// Nodes in ascending order.
+    #[inline]
+    pub fn all_nodes(&self) -> ArchivedIntSet<'_, K> {
+        ArchivedIntSet::new(&self.nodes)
+    }
+
+    pub fn children(&self, parent: K) -> ArchivedIntSet<'_, K>
+    where
+        K: Into<u32>,
+    {
+        match search(&self.nodes, parent.into()) {
+            Ok(i) => {
+                let start = self.offsets[i].to_native() as usize;
+                let end = self.offsets[i + 1].to_native() as usize;
+                ArchivedIntSet::new(&self.children[start..end])
+            }
+            Err(_) => ArchivedIntSet::new(&[]),
+        }
+    }
+
+    #[inline]
+    pub fn parent(&self, child: K) -> Option<K>
+    where
+        K: Into<u32> + TryFrom<u32>,
+    {
+        self.parent_u32(child.into()).and_then(try_key)
+    }
+
+    fn parent_u32(&self, child: u32) -> Option<u32> {
+        let i = search(&self.nodes, child).ok()?;
+        self.parents[i].as_ref().map(|p| p.to_native())
+    }
+}
+
+#[inline]
+fn search(values: &[u32_le], value: u32) -> Result<usize, usize> {
+    values.binary_search_by_key(&value, |v| v.to_native())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlatSetIndexLog, TreeIndexLog};
+
+    #[test]
+    fn flat_set_index_round_trip() {
+        let mut index = FlatSetIndex::<u32, u32>::new();
+        let mut log = FlatSetIndexLog::new();
+
+        log.insert(&index, 2, 20);
+        log.insert(&index, 2, 10);
+        log.insert(&index, 5, 50);
+        log.insert_none(&index, 7);
+        index.apply(log);
+
+        let bytes = index.to_rkyv_bytes().unwrap();
+        let archived = ArchivedFlatSetIndex::<u32, u32>::access(&bytes).unwrap();
+
+        assert!(archived.contains(2, 10));
+        assert!(!archived.contains(2, 50));
+        assert!(!archived.contains(3, 10));
+        assert_eq!(archived.get(2).iter().collect::<Vec<_>>(), vec![10, 20]);
+        assert!(archived.get(9).is_empty());
+        assert_eq!(archived.keys().collect::<Vec<_>>(), vec![2, 5]);
+        assert!(archived.none().contains(7));
+    }
+
+    #[test]
+    fn one_index_round_trip() {
+        let index = OneIndex::<u32, u64>::from_iter([(3, 30), (1, 10)]);
+
+        let bytes = index.to_rkyv_bytes().unwrap();
+        let archived = ArchivedOneIndex::<u32, u64>::access(&bytes).unwrap();
+
+        assert_eq!(archived.len(), 2);
+        assert_eq!(archived.get(3).map(|v| v.to_native()), Some(30));
+        assert!(archived.get(2).is_none());
+        assert_eq!(
+            archived
+                .iter()
+                .map(|(k, v)| (k, v.to_native()))
+                .collect::<Vec<_>>(),
+            vec![(1, 10), (3, 30)]
+        );
+    }
+
+    #[test]
+    fn tree_round_trip() {
+        let mut tree = Tree::<u32>::from_iter([(1, None), (2, Some(1)), (3, Some(2))]);
+        let mut log = TreeIndexLog::new();
+
+        log.insert(&tree, Some(1), 4);
+        tree.apply(log);
+
+        let bytes = tree.to_rkyv_bytes().unwrap();
+        let archived = ArchivedTree::<u32>::access(&bytes).unwrap();
+
+        assert_eq!(archived.parent(3), Some(2));
+        assert_eq!(archived.parent(1), None);
+        assert_eq!(archived.ancestors(3).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(archived.children(1).iter().collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(archived.all_nodes().len(), 4);
+    }
+
+    #[test]
+    fn access_rejects_truncated_bytes() {
+        let tree = Tree::<u32>::from_iter([(1, None), (2, Some(1))]);
+        let bytes = tree.to_rkyv_bytes().unwrap();
+
+        assert!(ArchivedTree::<u32>::access(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn access_rejects_inconsistent_arrays() {
+        let bad = FlatSetIndexArchive::<u32, u32> {
+            keys: vec![1, 2],
+            none: Vec::new(),
+            offsets: vec![0, 1],
+            values: vec![10],
+            _kv: PhantomData,
+        };
+
+        let bytes = rkyv::to_bytes::<Error>(&bad).unwrap();
+        assert!(ArchivedFlatSetIndex::<u32, u32>::access(&bytes).is_err());
+
+        let bad = OneIndexArchive::<u32, u64> {
+            keys: vec![2, 1],
+            values: vec![20, 10],
+            _k: PhantomData,
+        };
+
+        let bytes = rkyv::to_bytes::<Error>(&bad).unwrap();
+        assert!(ArchivedOneIndex::<u32, u64>::access(&bytes).is_err());
+
+        let bad = TreeArchive::<u32> {
+            children: vec![2],
+            nodes: vec![1, 2],
+            offsets: vec![0, 1, 1],
+            parents: vec![None],
+            _k: PhantomData,
+        };
+
+        let bytes = rkyv::to_bytes::<Error>(&bad).unwrap();
+        assert!(ArchivedTree::<u32>::access(&bytes).is_err());
+    }
+
+    #[test]
+    fn access_rejects_unsorted_ranges() {
+        let flat = |none: Vec<u32>, values: Vec<u32>| {
+            let archive = FlatSetIndexArchive::<u32, u32> {
+                keys: vec![1, 2],
+                none,
+                offsets: vec![0, 2, 3],
+                values,
+                _kv: PhantomData,
+            };
+
+            let bytes = rkyv::to_bytes::<Error>(&archive).unwrap();
+            ArchivedFlatSetIndex::<u32, u32>::access(&bytes).map(|_| ())
+        };
+
+        assert!(flat(vec![3, 4], vec![10, 20, 5]).is_ok());
+        assert!(flat(vec![3, 4], vec![20, 10, 5]).is_err());
+        assert!(flat(vec![3, 4], vec![10, 10, 5]).is_err());
+        assert!(flat(vec![4, 3], vec![10, 20, 5]).is_err());
+
+        let bad = TreeArchive::<u32> {
+            children: vec![3, 2],
+            nodes: vec![1, 2, 3],
+            offsets: vec![0, 2, 2, 2],
+            parents: vec![None, Some(1), Some(1)],
+            _k: PhantomData,
+        };
+
+        let bytes = rkyv::to_bytes::<Error>(&bad).unwrap();
+        assert!(ArchivedTree::<u32>::access(&bytes).is_err());
+    }
+}
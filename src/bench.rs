@@ -0,0 +1,274 @@
+//! Deterministic datasets and criterion benches for the build, apply and query
+//! paths.
+//!
+//! The generators only depend on their seed, so numbers can be compared across
+//! machines and commits. Run the benches with
+//! `cargo bench --features bench`, or register [`benches`] in your own harness.
+
+use crate::{
+    FlatSetIndex, FlatSetIndexBuilder, FlatSetIndexLog, NodeSetIndex, NodeSetIndexLog, Tree,
+    TreeIndexLog,
+};
+use criterion::{BatchSize, Criterion};
+use std::hint::black_box;
+
+/// Small splitmix64 generator; the same seed always yields the same stream.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    #[inline]
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Uniform in `0..n`. `n` must not be 0.
+    #[inline]
+    pub fn below(&mut self, n: u32) -> u32 {
+        (((self.next_u64() >> 32) * u64::from(n)) >> 32) as u32
+    }
+
+    #[inline]
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[inline]
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Zipf distribution over `0..n`: rank `r` is drawn with a weight of
+/// `1 / (r + 1)^s`, so `0` is the most frequent value.
+#[derive(Clone, Debug)]
+pub struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    /// `n` must not be 0.
+    pub fn new(n: u32, s: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf = (1..=n)
+            .map(|r| {
+                total += 1.0 / f64::from(r).powf(s);
+                total
+            })
+            .collect::<Vec<_>>();
+
+        for c in &mut cdf {
+            *c /= total;
+        }
+
+        Self { cdf }
+    }
+
+    #[inline]
+    pub fn sample(&self, rng: &mut Rng) -> u32 {
+        let x = rng.next_f64();
+        self.cdf.partition_point(|c| *c < x).min(self.cdf.len() - 1) as u32
+    }
+}
+
+/// `(node, parent)` entries of a forest of `nodes` nodes made of chains of
+/// `depth` nodes, each chain hanging from a random node of an earlier one.
+/// Node `0` is the only root.
+pub fn deep_tree(nodes: u32, depth: u32, seed: u64) -> Vec<(u32, Option<u32>)> {
+    let depth = depth.max(1);
+    let mut rng = Rng::new(seed);
+
+    (0..nodes)
+        .map(|n| {
+            let parent = match n {
+                0 => None,
+                n if n % depth == 0 => Some(rng.below(n)),
+                n => Some(n - 1),
+            };
+
+            (n, parent)
+        })
+        .collect()
+}
+
+/// `len` `(key, value)` pairs with keys in `0..keys` and values in `0..values`,
+/// both zipf distributed with exponent `s`. Pairs may repeat.
+pub fn zipf_pairs(len: usize, keys: u32, values: u32, s: f64, seed: u64) -> Vec<(u32, u32)> {
+    let mut rng = Rng::new(seed);
+    let keys = Zipf::new(keys, s);
+    let values = Zipf::new(values, s);
+
+    (0..len)
+        .map(|_| (keys.sample(&mut rng), values.sample(&mut rng)))
+        .collect()
+}
+
+/// Registers the build, apply and query benches on `c`.
+pub fn benches(c: &mut Criterion) {
+    bench_build(c);
+    bench_apply(c);
+    bench_query(c);
+}
+
+const NODES: u32 = 20_000;
+const DEPTH: u32 = 64;
+const PAIRS: usize = 100_000;
+const KEYS: u32 = 5_000;
+const VALUES: u32 = 50_000;
+const S: f64 = 1.1;
+
+fn flat_set_index(pairs: &[(u32, u32)]) -> FlatSetIndex<u32, u32> {
+    let mut builder = FlatSetIndexBuilder::new();
+
+    for (k, v) in pairs {
+        builder.insert(*k, *v);
+    }
+
+    builder.build()
+}
+
+fn node_set_index(tree: &Tree<u32>, pairs: &[(u32, u32)]) -> NodeSetIndex<u32, u32> {
+    let mut index = NodeSetIndex::new();
+    let mut log = NodeSetIndexLog::new();
+    let tree_log = TreeIndexLog::new();
+
+    for (n, i) in pairs {
+        log.insert(&index, tree, &tree_log, n % NODES, *i);
+    }
+
+    index.apply(log);
+    index
+}
+
+fn bench_build(c: &mut Criterion) {
+    let entries = deep_tree(NODES, DEPTH, 1);
+    let pairs = zipf_pairs(PAIRS, KEYS, VALUES, S, 2);
+    let tree = Tree::from_iter(entries.iter().copied());
+    let mut group = c.benchmark_group("build");
+
+    group.bench_function("tree", |b| {
+        b.iter(|| Tree::<u32>::from_iter(black_box(&entries).iter().copied()))
+    });
+
+    group.bench_function("flat_set_index", |b| {
+        b.iter(|| flat_set_index(black_box(&pairs)))
+    });
+
+    group.bench_function("node_set_index", |b| {
+        b.iter(|| node_set_index(&tree, black_box(&pairs)))
+    });
+
+    group.finish();
+}
+
+fn bench_apply(c: &mut Criterion) {
+    let tree = Tree::from_iter(deep_tree(NODES, DEPTH, 1));
+    let index = flat_set_index(&zipf_pairs(PAIRS, KEYS, VALUES, S, 2));
+    let updates = zipf_pairs(PAIRS / 10, KEYS, VALUES, S, 3);
+    let mut moves = Rng::new(4);
+    let moves = (0..1_000)
+        .map(|_| (moves.below(NODES - 1) + 1, moves.below(NODES)))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("apply");
+
+    group.bench_function("flat_set_index", |b| {
+        b.iter_batched(
+            || {
+                let mut log = FlatSetIndexLog::new();
+
+                for (k, v) in &updates {
+                    if !log.remove(&index, *k, *v) {
+                        log.insert(&index, *k, *v);
+                    }
+                }
+
+                (index.clone(), log)
+            },
+            |(mut index, log)| index.apply(log),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("tree", |b| {
+        b.iter_batched(
+            || {
+                let mut log = TreeIndexLog::new();
+
+                for (node, parent) in &moves {
+                    log.insert(&tree, Some(*parent), *node);
+                }
+
+                (tree.clone(), log)
+            },
+            |(mut tree, log)| tree.apply(log),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn bench_query(c: &mut Criterion) {
+    let tree = Tree::from_iter(deep_tree(NODES, DEPTH, 1));
+    let pairs = zipf_pairs(PAIRS, KEYS, VALUES, S, 2);
+    let flat = flat_set_index(&pairs);
+    let nodes = node_set_index(&tree, &pairs);
+    let mut group = c.benchmark_group("query");
+
+    group.bench_function("flat_set_index_contains", |b| {
+        b.iter(|| {
+            pairs
+                .iter()
+                .filter(|(k, v)| flat.contains(black_box(*k), *v))
+                .count()
+        })
+    });
+
+    group.bench_function("tree_ancestors", |b| {
+        b.iter(|| {
+            (0..NODES)
+                .map(|n| tree.ancestors(black_box(n)).count())
+                .sum::<usize>()
+        })
+    });
+
+    group.bench_function("node_set_index_subtree_items", |b| {
+        b.iter(|| {
+            (0..NODES)
+                .map(|n| nodes.subtree_items(black_box(n)).len())
+                .sum::<usize>()
+        })
+    });
+
+    group.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_are_deterministic() {
+        assert_eq!(deep_tree(1_000, 10, 7), deep_tree(1_000, 10, 7));
+        assert_eq!(
+            zipf_pairs(1_000, 50, 50, S, 7),
+            zipf_pairs(1_000, 50, 50, S, 7)
+        );
+
+        let tree = Tree::from_iter(deep_tree(1_000, 10, 7));
+        assert_eq!(tree.depth(9).ok(), Some(10));
+        assert_eq!(tree.cycles().count(), 0);
+        assert_eq!(tree.descendants(0).len(), 999);
+
+        let pairs = zipf_pairs(10_000, 50, 50, S, 7);
+        let zeros = pairs.iter().filter(|(k, _)| *k == 0).count();
+        let last = pairs.iter().filter(|(k, _)| *k == 49).count();
+        assert!(zeros > last * 10);
+    }
+}
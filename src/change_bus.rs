@@ -0,0 +1,285 @@
+use crate::{
+    Apply, FlatSetIndex, NodeSetIndex, Tree,
+    one_index::{OneIndex, OneIndexChange},
+};
+use std::sync::{
+    Arc, PoisonError, RwLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+/// One effective change made by an `apply`, in erased `u32` ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The set of a [`FlatSetIndex`] key changed.
+    KeyChanged(u32),
+    /// The `none` set of a [`FlatSetIndex`] changed.
+    NoneChanged,
+    /// A [`Tree`] node was attached or moved under `parent`, or detached.
+    NodeReparented { node: u32, parent: Option<u32> },
+    /// The direct items of a [`NodeSetIndex`] node changed.
+    DirectItemsChanged(u32),
+    /// The subtree items of a [`NodeSetIndex`] node changed.
+    SubtreeItemsChanged(u32),
+    /// A [`OneIndex`] slot was inserted, replaced or removed.
+    SlotUpdated(u32, OneIndexChange),
+}
+
+/// A [`Change`] tagged with the position of its index in the applied tuple,
+/// `0` for a single index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub change: Change,
+    pub source: usize,
+}
+
+impl ChangeEvent {
+    #[inline]
+    fn new(change: Change) -> Self {
+        Self { change, source: 0 }
+    }
+}
+
+/// An index whose `apply` can report the changes it made.
+pub trait Observe: Apply {
+    /// Applies the log, passing each effective change to `emit`. Returns
+    /// `true` if anything changed.
+    fn apply_observed(&mut self, log: Self::Log, emit: &mut dyn FnMut(ChangeEvent)) -> bool;
+}
+
+impl<T: Observe> Observe for &mut T {
+    #[inline]
+    fn apply_observed(&mut self, log: Self::Log, emit: &mut dyn FnMut(ChangeEvent)) -> bool {
+        T::apply_observed(self, log, emit)
+    }
+}
+
+impl<K, V> Observe for FlatSetIndex<K, V> {
+    fn apply_observed(&mut self, log: Self::Log, emit: &mut dyn FnMut(ChangeEvent)) -> bool {
+        let (keys, none) = self.inner.apply_report(log.inner);
+
+        if none {
+            emit(ChangeEvent::new(Change::NoneChanged));
+        }
+
+        for k in &keys {
+            emit(ChangeEvent::new(Change::KeyChanged(*k)));
+        }
+
+        none || !keys.is_empty()
+    }
+}
+
+impl<N, I> Observe for NodeSetIndex<N, I> {
+    fn apply_observed(&mut self, log: Self::Log, emit: &mut dyn FnMut(ChangeEvent)) -> bool {
+        let report = self.erased.apply_report(log.erased);
+
+        for n in &report.direct {
+            emit(ChangeEvent::new(Change::DirectItemsChanged(*n)));
+        }
+
+        for n in &report.subtree {
+            emit(ChangeEvent::new(Change::SubtreeItemsChanged(*n)));
+        }
+
+        !report.is_empty()
+    }
+}
+
+impl<K, V: PartialEq> Observe for OneIndex<K, V> {
+    fn apply_observed(&mut self, log: Self::Log, emit: &mut dyn FnMut(ChangeEvent)) -> bool {
+        let report = self.index.apply_report(log.log);
+
+        for (k, c) in &report {
+            emit(ChangeEvent::new(Change::SlotUpdated(*k, *c)));
+        }
+
+        !report.is_empty()
+    }
+}
+
+impl<K> Observe for Tree<K> {
+    fn apply_observed(&mut self, log: Self::Log, emit: &mut dyn FnMut(ChangeEvent)) -> bool {
+        let moved = self.erased.reparented(&log.erased);
+        let changed = self.erased.apply(log.erased);
+
+        for (node, parent) in &moved {
+            emit(ChangeEvent::new(Change::NodeReparented {
+                node: *node,
+                parent: *parent,
+            }));
+        }
+
+        changed
+    }
+}
+
+macro_rules! observe_tuple {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: Observe),+> Observe for ($($t,)+) {
+            fn apply_observed(
+                &mut self,
+                log: Self::Log,
+                emit: &mut dyn FnMut(ChangeEvent),
+            ) -> bool {
+                let mut changed = false;
+
+                $(
+                    changed |= self.$i.apply_observed(log.$i, &mut |e| {
+                        emit(ChangeEvent { source: $i, ..e })
+                    });
+                )+
+
+                changed
+            }
+        }
+    };
+}
+
+observe_tuple!(A 0, B 1);
+observe_tuple!(A 0, B 1, C 2);
+observe_tuple!(A 0, B 1, C 2, D 3);
+observe_tuple!(A 0, B 1, C 2, D 3, E 4);
+observe_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+type Subscriber = Arc<dyn Fn(&[ChangeEvent]) + Send + Sync>;
+
+/// Identifies a subscription to a [`ChangeBus`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// Broadcasts the changes made by [`apply`](Self::apply) to its subscribers.
+///
+/// Each subscriber gets the events of one apply as a single batch, after the
+/// index was updated and outside of the bus lock, so it may subscribe or
+/// unsubscribe from the callback.
+#[derive(Default)]
+pub struct ChangeBus {
+    next_id: AtomicU64,
+    subscribers: RwLock<Vec<(SubscriptionId, Subscriber)>>,
+}
+
+impl ChangeBus {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `log` to `target` and publishes the resulting events, if any.
+    pub fn apply<T: Observe>(&self, target: &mut T, log: T::Log) -> bool {
+        let mut events = Vec::new();
+        let changed = target.apply_observed(log, &mut |e| events.push(e));
+
+        self.publish(&events);
+        changed
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.subscribers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    /// Sends `events` to every subscriber. Does nothing when `events` is empty.
+    pub fn publish(&self, events: &[ChangeEvent]) {
+        if events.is_empty() {
+            return;
+        }
+
+        let subscribers = self
+            .subscribers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(_, s)| Arc::clone(s))
+            .collect::<Vec<_>>();
+
+        for s in subscribers {
+            s(events);
+        }
+    }
+
+    pub fn subscribe<F>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(&[ChangeEvent]) + Send + Sync + 'static,
+    {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+
+        self.subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, Arc::new(f)));
+
+        id
+    }
+
+    /// Returns `false` if `id` was not subscribed.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribers = self
+            .subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let len = subscribers.len();
+        subscribers.retain(|(s, _)| *s != id);
+        subscribers.len() != len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FastSetTrx, FlatSetIndex};
+    use std::sync::Mutex;
+
+    #[test]
+    fn subscribers_receive_batched_events() {
+        let bus = ChangeBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let id = bus.subscribe(move |events| sink.lock().unwrap().extend_from_slice(events));
+
+        let mut set = (
+            Tree::<u32>::from_iter([(1, None), (2, Some(1))]),
+            FlatSetIndex::<u32, u32>::new(),
+            OneIndex::<u32, u32>::new(),
+        );
+
+        let mut trx = FastSetTrx::new(&set);
+        let (base, log) = trx.parts_mut();
+        log.0.insert(&base.0, Some(2), 3);
+        log.1.insert(&base.1, 7, 70);
+        log.1.insert(&base.1, 8, 80);
+        log.1.remove(&base.1, 8, 80);
+        log.2.insert(&base.2, 4, 40);
+
+        let log = trx.into_log();
+        assert!(bus.apply(&mut set, log));
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ChangeEvent {
+                    change: Change::NodeReparented {
+                        node: 3,
+                        parent: Some(2)
+                    },
+                    source: 0,
+                },
+                ChangeEvent {
+                    change: Change::KeyChanged(7),
+                    source: 1,
+                },
+                ChangeEvent {
+                    change: Change::SlotUpdated(4, OneIndexChange::Inserted),
+                    source: 2,
+                },
+            ]
+        );
+
+        assert!(bus.unsubscribe(id));
+        assert!(!bus.unsubscribe(id));
+        assert!(bus.is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{U32Set, U32SetExt};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    hash::{BuildHasher, Hash},
+};
+
+/// Appends `set` as a varint length followed by varint deltas of the sorted values.
+pub fn encode_sorted(set: &U32Set, out: &mut Vec<u8>) {
+    write_varint(out, set.len() as u32);
+
+    let mut prev = 0;
+
+    for v in set.sorted() {
+        write_varint(out, v - prev);
+        prev = v;
+    }
+}
+
+/// Reads a set written by [`encode_sorted`], returning it with the remaining bytes.
+/// A zero delta after the first value would repeat a value and is rejected.
+pub fn decode_sorted(mut bytes: &[u8]) -> Result<(U32Set, &[u8]), DecodeError> {
+    let len = read_varint(&mut bytes)? as usize;
+    let mut set = U32Set::with_capacity_and_hasher(len.min(bytes.len()), Default::default());
+    let mut prev = 0u32;
+
+    for i in 0..len {
+        let delta = read_varint(&mut bytes)?;
+
+        if delta == 0 && i > 0 {
+            return Err(DecodeError::Invalid);
+        }
+
+        prev = prev.checked_add(delta).ok_or(DecodeError::Overflow)?;
+
+        set.insert(prev);
+    }
+
+    Ok((set, bytes))
+}
+
+pub fn write_varint(out: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+
+    out.push(v as u8);
+}
+
+pub fn read_varint(bytes: &mut &[u8]) -> Result<u32, DecodeError> {
+    let mut v = 0u32;
+
+    for shift in (0..35).step_by(7) {
+        let (&b, rest) = bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        *bytes = rest;
+
+        v |= u32::from(b & 0x7f)
+            .checked_shl(shift)
+            .filter(|_| shift < 28 || b & 0x7f < 0x10)
+            .ok_or(DecodeError::Overflow)?;
+
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+
+    Err(DecodeError::Overflow)
+}
+
+/// Value of a [`OneIndex`](crate::one_index::OneIndex) written to a snapshot or
+/// a write-ahead log record.
+pub trait CodecValue: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+impl CodecValue for bool {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    #[inline]
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(bytes)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+impl CodecValue for u64 {
+    #[inline]
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(out, *self as u32);
+        write_varint(out, (*self >> 32) as u32);
+    }
+
+    #[inline]
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        let lo = read_varint(bytes)?;
+        let hi = read_varint(bytes)?;
+        Ok(u64::from(hi) << 32 | u64::from(lo))
+    }
+}
+
+macro_rules! snapshot_value_varint {
+    ($($t:ty),+) => {
+        $(
+            impl CodecValue for $t {
+                #[inline]
+                fn encode(&self, out: &mut Vec<u8>) {
+                    write_varint(out, u32::from(*self));
+                }
+
+                #[inline]
+                fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+                    <$t>::try_from(read_varint(bytes)?).map_err(|_| DecodeError::Overflow)
+                }
+            }
+        )+
+    };
+}
+
+snapshot_value_varint!(u8, u16, u32);
+
+/// Appends the entries of `map` in key order, each key followed by its set.
+pub(crate) fn encode_set_map<S>(map: &HashMap<u32, U32Set, S>, out: &mut Vec<u8>) {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(k, _)| **k);
+
+    write_varint(out, entries.len() as u32);
+
+    for (k, set) in entries {
+        write_varint(out, *k);
+        encode_sorted(set, out);
+    }
+}
+
+/// Reads a map written by [`encode_set_map`].
+pub(crate) fn decode_set_map<K, S>(bytes: &mut &[u8]) -> Result<HashMap<K, U32Set, S>, DecodeError>
+where
+    K: Eq + Hash + From<u32>,
+    S: BuildHasher + Default,
+{
+    let len = read_varint(bytes)? as usize;
+    let mut map = HashMap::with_capacity_and_hasher(len.min(bytes.len()), S::default());
+
+    for _ in 0..len {
+        let k = read_varint(bytes)?;
+        let (set, rest) = decode_sorted(bytes)?;
+
+        map.insert(K::from(k), set);
+        *bytes = rest;
+    }
+
+    Ok(map)
+}
+
+pub(crate) fn encode_option_set(set: Option<&U32Set>, out: &mut Vec<u8>) {
+    match set {
+        Some(set) => {
+            out.push(1);
+            encode_sorted(set, out);
+        }
+        None => out.push(0),
+    }
+}
+
+pub(crate) fn decode_option_set(bytes: &mut &[u8]) -> Result<Option<U32Set>, DecodeError> {
+    match read_varint(bytes)? {
+        0 => Ok(None),
+        1 => {
+            let (set, rest) = decode_sorted(bytes)?;
+            *bytes = rest;
+            Ok(Some(set))
+        }
+        _ => Err(DecodeError::Invalid),
+    }
+}
+
+pub(crate) fn encode_option_u32(v: Option<u32>, out: &mut Vec<u8>) {
+    match v {
+        Some(v) => {
+            out.push(1);
+            write_varint(out, v);
+        }
+        None => out.push(0),
+    }
+}
+
+pub(crate) fn decode_option_u32(bytes: &mut &[u8]) -> Result<Option<u32>, DecodeError> {
+    match read_varint(bytes)? {
+        0 => Ok(None),
+        1 => read_varint(bytes).map(Some),
+        _ => Err(DecodeError::Invalid),
+    }
+}
+
+/// Fails unless every byte was consumed.
+#[inline]
+pub(crate) fn expect_end(bytes: &[u8]) -> Result<(), DecodeError> {
+    if bytes.is_empty() {
+        Ok(())
+    } else {
+        Err(DecodeError::TrailingBytes)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A flag, tag or delta outside of the values the encoding produces.
+    Invalid,
+    Overflow,
+    TrailingBytes,
+    UnexpectedEnd,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid => f.write_str("invalid encoded value"),
+            Self::Overflow => f.write_str("encoded value overflows u32"),
+            Self::TrailingBytes => f.write_str("trailing bytes after encoded value"),
+            Self::UnexpectedEnd => f.write_str("unexpected end of input"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// `#[serde(with = "fast_set::codec::u32_set")]` for [`U32Set`] fields.
+///
+/// Binary formats get the [`encode_sorted`] blob, human-readable ones a sorted list.
+#[cfg(feature = "serde")]
+pub mod u32_set {
+    use super::{decode_sorted, encode_sorted};
+    use crate::{U32Set, U32SetExt};
+    use serde::{
+        Deserializer, Serializer,
+        de::{self, SeqAccess, Visitor},
+    };
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(set: &U32Set, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(set.sorted())
+        } else {
+            let mut out = Vec::new();
+            encode_sorted(set, &mut out);
+            serializer.serialize_bytes(&out)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U32Set, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_seq(ListVisitor)
+        } else {
+            deserializer.deserialize_bytes(BlobVisitor)
+        }
+    }
+
+    /// Caps a length hint read from the input at 1 MiB worth of `T`, as serde
+    /// does for its own collections, so a forged hint cannot force a huge
+    /// allocation.
+    fn cautious<T>(hint: Option<usize>) -> usize {
+        const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+
+        hint.unwrap_or(0)
+            .min(MAX_PREALLOC_BYTES / std::mem::size_of::<T>().max(1))
+    }
+
+    struct ListVisitor;
+
+    impl<'de> Visitor<'de> for ListVisitor {
+        type Value = U32Set;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of u32")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<U32Set, A::Error> {
+            let mut set = U32Set::default();
+            set.reserve(cautious::<u32>(seq.size_hint()));
+
+            while let Some(v) = seq.next_element()? {
+                set.insert(v);
+            }
+
+            Ok(set)
+        }
+    }
+
+    struct BlobVisitor;
+
+    impl<'de> Visitor<'de> for BlobVisitor {
+        type Value = U32Set;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a delta-varint encoded set")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<U32Set, E> {
+            match decode_sorted(v) {
+                Ok((set, [])) => Ok(set),
+                Ok(_) => Err(E::custom("trailing bytes after set")),
+                Err(e) => Err(E::custom(e)),
+            }
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<U32Set, A::Error> {
+            let mut bytes = Vec::with_capacity(cautious::<u8>(seq.size_hint()));
+
+            while let Some(b) = seq.next_element::<u8>()? {
+                bytes.push(b);
+            }
+
+            self.visit_bytes(&bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        for v in [0, 1, 127, 128, 300, u32::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, v);
+            assert_eq!(read_varint(&mut out.as_slice()), Ok(v));
+        }
+
+        assert_eq!(
+            read_varint(&mut [0xff, 0xff, 0xff, 0xff, 0x1f].as_slice()),
+            Err(DecodeError::Overflow)
+        );
+    }
+
+    #[test]
+    fn sorted_round_trip() {
+        let set = U32Set::from_iter([1_000_000, 3, 1_000_001, 0, u32::MAX]);
+        let mut out = Vec::new();
+        encode_sorted(&set, &mut out);
+
+        let (decoded, rest) = decode_sorted(&out).unwrap();
+        assert_eq!(decoded, set);
+        assert!(rest.is_empty());
+        assert_eq!(decode_sorted(&out[..3]), Err(DecodeError::UnexpectedEnd));
+
+        assert_eq!(decode_sorted(&[2, 0, 0]), Err(DecodeError::Invalid));
+        assert_eq!(decode_sorted(&[2, 5, 0]), Err(DecodeError::Invalid));
+        assert_eq!(
+            decode_sorted(&[2, 0, 5]).map(|(set, _)| set),
+            Ok(U32Set::from_iter([0, 5]))
+        );
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        assert_eq!(bool::decode(&mut [2].as_slice()), Err(DecodeError::Invalid));
+        assert_eq!(
+            decode_option_u32(&mut [2, 1].as_slice()),
+            Err(DecodeError::Invalid)
+        );
+        assert_eq!(
+            decode_option_set(&mut [2, 0].as_slice()),
+            Err(DecodeError::Invalid)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn int_set_serde_formats() {
+        use crate::IntSet;
+
+        let set = IntSet::<u32>::from_iter([10, 2, 7]);
+
+        let json = serde_json::to_string(&set).unwrap();
+        assert_eq!(json, "[2,7,10]");
+        assert!(serde_json::from_str::<IntSet<u32>>(&json).unwrap() == set);
+
+        let bin = bincode::serialize(&set).unwrap();
+        assert!(bin.len() < bincode::serialize(&vec![2u32, 7, 10]).unwrap().len());
+        assert!(bincode::deserialize::<IntSet<u32>>(&bin).unwrap() == set);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    FlatSetIndex, FlatSetIndexLog, HashFlatSetIndex, HashFlatSetIndexLog, HashFlatSetIndexTrx,
+    NodeCountIndex, NodeCountIndexLog, NodeSetIndex, NodeSetIndexLog, NodeSetIndexTrx, Tree,
+    TreeIndexLog,
+    flat_set_index::FlatSetIndexTrx,
+    one_index::{OneIndex, OneIndexLog, OneIndexTrx},
+    tree::TreeTrx,
+};
+use std::hash::{BuildHasher, Hash};
+
+/// An index updated by applying a staged log.
+pub trait Apply {
+    type Log: Default;
+
+    /// Applies the log, returning `true` if anything changed.
+    fn apply(&mut self, log: Self::Log) -> bool;
+}
+
+impl<T: Apply> Apply for &mut T {
+    type Log = T::Log;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        T::apply(self, log)
+    }
+}
+
+impl<K, V> Apply for FlatSetIndex<K, V> {
+    type Log = FlatSetIndexLog<K, V>;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        FlatSetIndex::apply(self, log)
+    }
+}
+
+impl<K, V, S> Apply for HashFlatSetIndex<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Log = HashFlatSetIndexLog<K, V, S>;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        HashFlatSetIndex::apply(self, log)
+    }
+}
+
+impl<N, I> Apply for NodeCountIndex<N, I> {
+    type Log = NodeCountIndexLog<N, I>;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        NodeCountIndex::apply(self, log)
+    }
+}
+
+impl<N, I> Apply for NodeSetIndex<N, I> {
+    type Log = NodeSetIndexLog<N, I>;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        NodeSetIndex::apply(self, log)
+    }
+}
+
+impl<K, V: PartialEq> Apply for OneIndex<K, V> {
+    type Log = OneIndexLog<K, V>;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        OneIndex::apply(self, log)
+    }
+}
+
+impl<K> Apply for Tree<K> {
+    type Log = TreeIndexLog<K>;
+
+    #[inline]
+    fn apply(&mut self, log: Self::Log) -> bool {
+        Tree::apply(self, log)
+    }
+}
+
+/// An index with a read trx over its base and a staged log.
+pub trait View: Apply {
+    type View<'a>
+    where
+        Self: 'a;
+
+    fn view<'a>(&'a self, log: &'a Self::Log) -> Self::View<'a>;
+}
+
+impl<K, V> View for FlatSetIndex<K, V> {
+    type View<'a>
+        = FlatSetIndexTrx<'a, K, V>
+    where
+        Self: 'a;
+
+    #[inline]
+    fn view<'a>(&'a self, log: &'a Self::Log) -> Self::View<'a> {
+        FlatSetIndexTrx::new(self, log)
+    }
+}
+
+impl<K, V, S> View for HashFlatSetIndex<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type View<'a>
+        = HashFlatSetIndexTrx<'a, K, V, S>
+    where
+        Self: 'a;
+
+    #[inline]
+    fn view<'a>(&'a self, log: &'a Self::Log) -> Self::View<'a> {
+        HashFlatSetIndexTrx::new(self, log)
+    }
+}
+
+impl<N, I> View for NodeSetIndex<N, I> {
+    type View<'a>
+        = NodeSetIndexTrx<'a, N, I>
+    where
+        Self: 'a;
+
+    #[inline]
+    fn view<'a>(&'a self, log: &'a Self::Log) -> Self::View<'a> {
+        NodeSetIndexTrx::new(self, log)
+    }
+}
+
+impl<K, V: PartialEq> View for OneIndex<K, V> {
+    type View<'a>
+        = OneIndexTrx<'a, K, V>
+    where
+        Self: 'a;
+
+    #[inline]
+    fn view<'a>(&'a self, log: &'a Self::Log) -> Self::View<'a> {
+        OneIndexTrx::new(self, log)
+    }
+}
+
+impl<K> View for Tree<K> {
+    type View<'a>
+        = TreeTrx<'a, K>
+    where
+        Self: 'a;
+
+    #[inline]
+    fn view<'a>(&'a self, log: &'a Self::Log) -> Self::View<'a> {
+        TreeTrx::new(self, log)
+    }
+}
+
+/// The `I`-th index of a group, with its log.
+pub trait Part<const I: usize>: Apply {
+    type Part: Apply;
+
+    fn part(&self) -> &Self::Part;
+
+    fn part_log(log: &Self::Log) -> &<Self::Part as Apply>::Log;
+}
+
+macro_rules! tuple_part {
+    ($i:tt $p:ident; $($t:ident),+) => {
+        impl<$($t: Apply),+> Part<$i> for ($($t,)+) {
+            type Part = $p;
+
+            #[inline]
+            fn part(&self) -> &$p {
+                &self.$i
+            }
+
+            #[inline]
+            fn part_log(log: &Self::Log) -> &$p::Log {
+                &log.$i
+            }
+        }
+    };
+}
+
+tuple_part!(0 A; A, B);
+tuple_part!(1 B; A, B);
+tuple_part!(0 A; A, B, C);
+tuple_part!(1 B; A, B, C);
+tuple_part!(2 C; A, B, C);
+tuple_part!(0 A; A, B, C, D);
+tuple_part!(1 B; A, B, C, D);
+tuple_part!(2 C; A, B, C, D);
+tuple_part!(3 D; A, B, C, D);
+tuple_part!(0 A; A, B, C, D, E);
+tuple_part!(1 B; A, B, C, D, E);
+tuple_part!(2 C; A, B, C, D, E);
+tuple_part!(3 D; A, B, C, D, E);
+tuple_part!(4 E; A, B, C, D, E);
+tuple_part!(0 A; A, B, C, D, E, F);
+tuple_part!(1 B; A, B, C, D, E, F);
+tuple_part!(2 C; A, B, C, D, E, F);
+tuple_part!(3 D; A, B, C, D, E, F);
+tuple_part!(4 E; A, B, C, D, E, F);
+tuple_part!(5 F; A, B, C, D, E, F);
+
+macro_rules! apply_tuple {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: Apply),+> Apply for ($($t,)+) {
+            type Log = ($($t::Log,)+);
+
+            #[inline]
+            fn apply(&mut self, log: Self::Log) -> bool {
+                let mut changed = false;
+                $(changed |= self.$i.apply(log.$i);)+
+                changed
+            }
+        }
+    };
+}
+
+apply_tuple!(A 0, B 1);
+apply_tuple!(A 0, B 1, C 2);
+apply_tuple!(A 0, B 1, C 2, D 3);
+apply_tuple!(A 0, B 1, C 2, D 3, E 4);
+apply_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Stages changes to a group of indexes, usually a tuple such as
+/// `(Tree<N>, NodeSetIndex<N, I>, OneIndex<K, V>)`, behind one set of logs.
+///
+/// Reads go through each log with [`base`](Self::base) as the overlay source;
+/// [`view`](Self::view) wraps one index and its log in the index's read trx.
+/// The logs are applied together with [`commit`]; since applying a log cannot
+/// fail, either every index sees its changes or, if the trx is dropped, none.
+pub struct FastSetTrx<'a, T: Apply> {
+    base: &'a T,
+    log: T::Log,
+}
+
+impl<'a, T: Apply> FastSetTrx<'a, T> {
+    #[inline]
+    pub fn new(base: &'a T) -> Self {
+        Self {
+            base,
+            log: Default::default(),
+        }
+    }
+
+    #[inline]
+    pub fn base(&self) -> &'a T {
+        self.base
+    }
+
+    #[inline]
+    pub fn into_log(self) -> T::Log {
+        self.log
+    }
+
+    #[inline]
+    pub fn log(&self) -> &T::Log {
+        &self.log
+    }
+
+    /// Read trx of the `I`-th index through its staged log, such as the
+    /// [`TreeTrx`] of `trx.view::<0>()` for a `(Tree<N>, ..)` group.
+    #[inline]
+    pub fn view<const I: usize>(&self) -> <T::Part as View>::View<'_>
+    where
+        T: Part<I>,
+        T::Part: View,
+    {
+        T::part(self.base).view(T::part_log(&self.log))
+    }
+
+    /// The base together with the mutable logs, for staging calls that read
+    /// the former while writing the latter.
+    #[inline]
+    pub fn parts_mut(&mut self) -> (&'a T, &mut T::Log) {
+        (self.base, &mut self.log)
+    }
+}
+
+/// Applies the logs taken from [`FastSetTrx::into_log`] to `target`.
+#[inline]
+pub fn commit<T: Apply>(target: &mut T, log: T::Log) -> bool {
+    target.apply(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_and_commits_several_indexes() {
+        let mut set = (
+            Tree::<u32>::from_iter([(1, None), (2, Some(1))]),
+            NodeSetIndex::<u32, u32>::new(),
+            FlatSetIndex::<u32, u32>::new(),
+        );
+
+        let mut trx = FastSetTrx::new(&set);
+        let (base, log) = trx.parts_mut();
+
+        log.0.insert(&base.0, Some(2), 3);
+        log.1.insert(&base.1, &base.0, &log.0, 3, 10);
+        log.2.insert(&base.2, 7, 10);
+
+        assert!(trx.log().1.subtree_items(&trx.base().1, 1).contains(10));
+        assert!(trx.base().1.subtree_items(1).is_empty());
+        assert_eq!(trx.view::<0>().parent(3), Some(2));
+        assert!(trx.view::<1>().subtree_items(1).contains(10));
+        assert!(trx.view::<2>().contains(7, 10));
+
+        let log = trx.into_log();
+        assert!(commit(&mut set, log));
+
+        assert_eq!(set.0.parent(3), Some(2));
+        assert!(set.1.subtree_items(1).contains(10));
+        assert!(set.2.contains(7, 10));
+    }
+}
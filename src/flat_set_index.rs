@@ -1,9 +1,14 @@
-use crate::{IntSet, U32Set, u32based};
-use std::{hash::Hash, marker::PhantomData};
+use crate::{IntSet, U32Set, codec::DecodeError, strict::try_key, u32based};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug},
+    hash::Hash,
+    marker::PhantomData,
+};
 
 #[repr(transparent)]
 pub struct FlatSetIndex<K, V> {
-    inner: u32based::U32FlatSetIndex,
+    pub(crate) inner: u32based::U32FlatSetIndex,
     _kv: PhantomData<(K, V)>,
 }
 
@@ -32,6 +37,17 @@ impl<K, V> FlatSetIndex<K, V> {
         self.inner.apply(log.inner)
     }
 
+    /// Like [`apply`](Self::apply), but returns the keys whose set changed and
+    /// whether the `none` set changed.
+    #[inline]
+    pub fn apply_report(&mut self, log: FlatSetIndexLog<K, V>) -> (Vec<K>, bool)
+    where
+        K: TryFrom<u32>,
+    {
+        let (keys, none) = self.inner.apply_report(log.inner);
+        (keys.into_iter().filter_map(try_key).collect(), none)
+    }
+
     #[inline]
     pub fn contains(&self, key: K, value: V) -> bool
     where
@@ -64,9 +80,7 @@ impl<K, V> FlatSetIndex<K, V> {
         V: Into<u32>,
     {
         self.inner.iter().filter_map(|(k, v)| {
-            Some((K::try_from(*k).ok()?, unsafe {
-                IntSet::from_u32set_ref(v.as_set())
-            }))
+            Some((try_key(*k)?, unsafe { IntSet::from_u32set_ref(v.as_set()) }))
         })
     }
 
@@ -75,7 +89,7 @@ impl<K, V> FlatSetIndex<K, V> {
     where
         K: TryFrom<u32>,
     {
-        self.inner.keys().filter_map(|k| K::try_from(*k).ok())
+        self.inner.keys().filter_map(|k| try_key(*k))
     }
 
     #[inline]
@@ -87,6 +101,20 @@ impl<K, V> FlatSetIndex<K, V> {
     pub fn values(&self) -> IntSet<V> {
         unsafe { IntSet::from_set(self.inner.values()) }
     }
+
+    /// See [`u32based::FlatSetIndex::par_apply`].
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_apply(&mut self, log: FlatSetIndexLog<K, V>) -> bool {
+        self.inner.par_apply(log.inner)
+    }
+
+    /// [`values`](Self::values), merged on the rayon pool.
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_union_values(&self) -> IntSet<V> {
+        unsafe { IntSet::from_set(self.inner.par_union_values()) }
+    }
 }
 
 impl<K, V> Clone for FlatSetIndex<K, V> {
@@ -99,6 +127,25 @@ impl<K, V> Clone for FlatSetIndex<K, V> {
     }
 }
 
+/// Shows the sets in erased ids, sorted.
+impl<K, V> Debug for FlatSetIndex<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sorted = |s: &U32Set| s.iter().copied().collect::<BTreeSet<_>>();
+
+        f.debug_struct("FlatSetIndex")
+            .field(
+                "keys",
+                &self
+                    .inner
+                    .iter()
+                    .map(|(k, v)| (*k, sorted(v.as_set())))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+            .field("none", &sorted(self.inner.none().as_set()))
+            .finish()
+    }
+}
+
 impl<K, V> Default for FlatSetIndex<K, V> {
     #[inline]
     fn default() -> Self {
@@ -223,7 +270,7 @@ impl<K, V> Default for FlatSetIndexBuilder<K, V> {
 
 #[repr(transparent)]
 pub struct FlatSetIndexLog<K, V> {
-    inner: u32based::U32FlatSetIndexLog,
+    pub(crate) inner: u32based::U32FlatSetIndexLog,
     _kv: PhantomData<(K, V)>,
 }
 
@@ -233,6 +280,22 @@ impl<K, V> FlatSetIndexLog<K, V> {
         Default::default()
     }
 
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    #[inline]
+    pub fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            inner: u32based::U32FlatSetIndexLog::from_wal_bytes(bytes)?,
+            _kv: PhantomData,
+        })
+    }
+
+    /// Encodes the log as a write-ahead log record, to be replayed in order
+    /// against the same base.
+    #[inline]
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        self.inner.to_wal_bytes()
+    }
+
     #[inline]
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
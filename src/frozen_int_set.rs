@@ -0,0 +1,165 @@
+use crate::{IntSet, U32SetExt, strict::try_key};
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
+
+/// Immutable [`IntSet`] stored as a sorted slice, for read-mostly data where
+/// cache locality and footprint matter more than mutation.
+pub struct FrozenIntSet<K>(Box<[u32]>, PhantomData<K>);
+
+impl<K> FrozenIntSet<K> {
+    #[inline]
+    pub fn as_slice(&self) -> &[u32] {
+        &self.0
+    }
+
+    #[inline]
+    pub fn contains(&self, key: K) -> bool
+    where
+        K: Into<u32>,
+    {
+        self.0.binary_search(&key.into()).is_ok()
+    }
+
+    /// Intersects by galloping through the larger set from each value of the
+    /// smaller one.
+    pub fn intersection(&self, other: &Self) -> Self {
+        let (small, large) = if self.0.len() <= other.0.len() {
+            (&*self.0, &*other.0)
+        } else {
+            (&*other.0, &*self.0)
+        };
+
+        let mut out = Vec::with_capacity(small.len());
+        let mut rest = large;
+
+        for &v in small {
+            let i = gallop(rest, v);
+
+            if rest.get(i) == Some(&v) {
+                out.push(v);
+            }
+
+            rest = &rest[i..];
+
+            if rest.is_empty() {
+                break;
+            }
+        }
+
+        Self(out.into_boxed_slice(), PhantomData)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Keys in ascending order.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = K> + '_
+    where
+        K: TryFrom<u32>,
+    {
+        self.0.iter().copied().filter_map(try_key)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn to_int_set(&self) -> IntSet<K> {
+        // SAFETY: the values were taken from an `IntSet<K>`.
+        unsafe { IntSet::from_set(self.0.iter().copied().collect()) }
+    }
+}
+
+/// Index of the first value of `s` not less than `v`.
+fn gallop(s: &[u32], v: u32) -> usize {
+    let mut hi = 1;
+
+    while hi < s.len() && s[hi] < v {
+        hi *= 2;
+    }
+
+    let lo = hi / 2;
+    let hi = hi.min(s.len());
+
+    lo + s[lo..hi].partition_point(|&x| x < v)
+}
+
+impl<K> Clone for FrozenIntSet<K> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData)
+    }
+}
+
+impl<K> Debug for FrozenIntSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.iter()).finish()
+    }
+}
+
+impl<K> Default for FrozenIntSet<K> {
+    #[inline]
+    fn default() -> Self {
+        Self(Box::default(), PhantomData)
+    }
+}
+
+impl<K> Eq for FrozenIntSet<K> {}
+
+impl<K> From<&IntSet<K>> for FrozenIntSet<K> {
+    #[inline]
+    fn from(set: &IntSet<K>) -> Self {
+        Self(set.as_set().sorted().into_boxed_slice(), PhantomData)
+    }
+}
+
+impl<K> From<IntSet<K>> for FrozenIntSet<K> {
+    #[inline]
+    fn from(set: IntSet<K>) -> Self {
+        Self::from(&set)
+    }
+}
+
+impl<K> PartialEq for FrozenIntSet<K> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gallop_finds_lower_bound() {
+        let s = [1, 3, 5, 7, 9, 11];
+
+        assert_eq!(gallop(&s, 0), 0);
+        assert_eq!(gallop(&s, 1), 0);
+        assert_eq!(gallop(&s, 6), 3);
+        assert_eq!(gallop(&s, 11), 5);
+        assert_eq!(gallop(&s, 12), 6);
+        assert_eq!(gallop(&[], 1), 0);
+    }
+
+    #[test]
+    fn frozen_matches_int_set() {
+        let a = IntSet::<u32>::from_iter([9, 1, 5, 100, 7]);
+        let b = IntSet::<u32>::from_iter([5, 100, 2, 1]);
+        let fa = FrozenIntSet::from(&a);
+
+        assert!(fa.contains(100));
+        assert!(!fa.contains(2));
+        assert_eq!(fa.iter().collect::<Vec<_>>(), vec![1, 5, 7, 9, 100]);
+        assert_eq!(fa.intersection(&b.clone().into()).to_int_set(), &a & &b);
+        assert_eq!(fa.to_int_set(), a);
+    }
+}
@@ -0,0 +1,323 @@
+//! [`arbitrary`] support for fuzzing.
+//!
+//! The logs implement [`Arbitrary`] by staging arbitrary edits on an empty
+//! base. [`FuzzOps`] drives a [`Tree`] and a [`NodeSetIndex`] through
+//! [`execute`], which checks their invariants along the way:
+//!
+//! ```ignore
+//! fuzz_target!(|ops: FuzzOps| {
+//!     fast_set::fuzz::execute(&ops).unwrap();
+//! });
+//! ```
+
+use crate::{
+    FlatSetIndex, FlatSetIndexLog, HierarchyTrx, NodeSetIndex, NodeSetIndexLog, Tree, TreeIndexLog,
+    node_set_index::InvariantViolation,
+    one_index::{OneIndex, OneIndexLog},
+};
+use arbitrary::{Arbitrary, Result, Unstructured};
+use std::{error::Error, fmt, mem::take};
+
+impl<'a, K, V> Arbitrary<'a> for FlatSetIndexLog<K, V>
+where
+    K: Arbitrary<'a> + Into<u32>,
+    V: Arbitrary<'a> + Into<u32>,
+{
+    /// Stages inserts and removes, `None` keys going to the `none` set.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let base = FlatSetIndex::new();
+        let mut log = Self::new();
+
+        for op in u.arbitrary_iter::<(bool, Option<K>, V)>()? {
+            match op? {
+                (true, Some(k), v) => log.insert(&base, k, v),
+                (true, None, v) => log.insert_none(&base, v),
+                (false, Some(k), v) => log.remove(&base, k, v),
+                (false, None, v) => log.remove_none(&base, v),
+            };
+        }
+
+        Ok(log)
+    }
+}
+
+impl<'a, N, I> Arbitrary<'a> for NodeSetIndexLog<N, I>
+where
+    N: Arbitrary<'a> + Into<u32>,
+    I: Arbitrary<'a> + Into<u32>,
+{
+    /// Stages inserts and removes of items on nodes of an empty tree.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let base = NodeSetIndex::new();
+        let tree = Tree::new();
+        let tree_log = TreeIndexLog::new();
+        let mut log = Self::new();
+
+        for op in u.arbitrary_iter::<(bool, N, I)>()? {
+            match op? {
+                (true, n, i) => log.insert(&base, &tree, &tree_log, n, i),
+                (false, n, i) => log.remove(&base, &tree, &tree_log, n, i),
+            }
+        }
+
+        Ok(log)
+    }
+}
+
+/// Highest key staged by the [`OneIndexLog`] impl. A [`OneIndex`] holds a slot
+/// for every key up to its largest, so keys spanning all of `u32` would
+/// exhaust memory.
+pub const ONE_INDEX_MAX_KEY: u32 = u16::MAX as u32;
+
+impl<'a, K, V> Arbitrary<'a> for OneIndexLog<K, V>
+where
+    K: TryFrom<u32> + Into<u32>,
+    V: Arbitrary<'a> + PartialEq,
+{
+    /// Stages inserts, and removes for `None` values, on keys up to
+    /// [`ONE_INDEX_MAX_KEY`]. Keys that do not fit in `K` are skipped.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let base = OneIndex::new();
+        let mut log = Self::new();
+
+        for _ in 0..u.arbitrary_len::<(u32, Option<V>)>()? {
+            let key = u.int_in_range(0..=ONE_INDEX_MAX_KEY)?;
+            let value = Option::<V>::arbitrary(u)?;
+
+            let Ok(k) = K::try_from(key) else {
+                continue;
+            };
+
+            match value {
+                Some(v) => log.insert(&base, k, v),
+                None => log.remove(&base, k),
+            }
+        }
+
+        Ok(log)
+    }
+}
+
+impl<'a, K> Arbitrary<'a> for TreeIndexLog<K>
+where
+    K: Arbitrary<'a> + Into<u32>,
+{
+    /// Stages inserts, cycles included.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let base = Tree::new();
+        let mut log = Self::new();
+
+        for op in u.arbitrary_iter::<(Option<K>, K)>()? {
+            let (parent, child) = op?;
+            log.insert(&base, parent, child);
+        }
+
+        Ok(log)
+    }
+}
+
+/// One step of [`execute`]. Ids are `u8` so that ops collide often.
+#[derive(Arbitrary, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FuzzOp {
+    /// Attaches `node` under `parent`, carrying its items along.
+    InsertNode {
+        parent: Option<u8>,
+        node: u8,
+    },
+    InsertItem {
+        node: u8,
+        item: u8,
+    },
+    RemoveItem {
+        node: u8,
+        item: u8,
+    },
+    ClearNode(u8),
+    /// Applies the staged logs.
+    Apply,
+}
+
+#[derive(Arbitrary, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FuzzOps(pub Vec<FuzzOp>);
+
+/// An invariant broken while running [`FuzzOps`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FuzzFailure {
+    /// The descendants of `node` are not its children and their descendants.
+    Descendants {
+        node: u32,
+        expected: Vec<u32>,
+        actual: Vec<u32>,
+    },
+    /// Subtree items disagree with the direct items below them.
+    SubtreeItems(Vec<InvariantViolation>),
+}
+
+impl fmt::Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Descendants {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "descendants of {node}: expected {expected:?}, got {actual:?}"
+            ),
+            Self::SubtreeItems(violations) => {
+                f.write_str("subtree items")?;
+
+                for v in violations {
+                    write!(f, "; {v}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for FuzzFailure {}
+
+/// Runs `ops` on an empty tree and index, then applies what is left staged.
+///
+/// Subtree items are verified after every op, through the staged logs, and
+/// the descendants closure after every apply. Moves that would close a cycle
+/// are refused by [`HierarchyTrx::insert_node`].
+pub fn execute(
+    ops: &FuzzOps,
+) -> std::result::Result<(Tree<u32>, NodeSetIndex<u32, u32>), FuzzFailure> {
+    let mut tree = Tree::new();
+    let mut tree_log = TreeIndexLog::new();
+    let mut index = NodeSetIndex::new();
+    let mut index_log = NodeSetIndexLog::new();
+
+    for op in &ops.0 {
+        let mut trx = HierarchyTrx::new(&tree, &mut tree_log, &index, &mut index_log);
+
+        match *op {
+            FuzzOp::InsertNode { parent, node } => {
+                trx.insert_node(parent.map(u32::from), node.into());
+            }
+            FuzzOp::InsertItem { node, item } => trx.insert_item(node.into(), item.into()),
+            FuzzOp::RemoveItem { node, item } => trx.remove_item(node.into(), item.into()),
+            FuzzOp::ClearNode(node) => trx.clear_node(node.into()),
+            FuzzOp::Apply => {
+                tree.apply(take(&mut tree_log));
+                index.apply(take(&mut index_log));
+                check_descendants(&tree)?;
+            }
+        }
+
+        index
+            .verify(&tree, &tree_log, &index_log)
+            .map_err(FuzzFailure::SubtreeItems)?;
+    }
+
+    tree.apply(tree_log);
+    index.apply(index_log);
+    check_descendants(&tree)?;
+
+    index
+        .verify(&tree, &TreeIndexLog::new(), &NodeSetIndexLog::new())
+        .map_err(FuzzFailure::SubtreeItems)?;
+
+    Ok((tree, index))
+}
+
+fn check_descendants(tree: &Tree<u32>) -> std::result::Result<(), FuzzFailure> {
+    for node in tree.all_nodes() {
+        if tree.has_cycle(node) {
+            continue;
+        }
+
+        let mut expected = tree
+            .children(node)
+            .iter()
+            .flat_map(|c| tree.descendants_with_self(c))
+            .collect::<Vec<_>>();
+
+        let mut actual = tree.descendants(node).iter().collect::<Vec<_>>();
+
+        expected.sort_unstable();
+        expected.dedup();
+        actual.sort_unstable();
+
+        if expected != actual {
+            return Err(FuzzFailure::Descendants {
+                node,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_keeps_invariants() {
+        let ops = FuzzOps(vec![
+            FuzzOp::InsertNode {
+                parent: None,
+                node: 1,
+            },
+            FuzzOp::InsertNode {
+                parent: Some(1),
+                node: 2,
+            },
+            FuzzOp::InsertItem { node: 2, item: 20 },
+            FuzzOp::Apply,
+            FuzzOp::InsertNode {
+                parent: Some(2),
+                node: 1,
+            },
+            FuzzOp::InsertNode {
+                parent: Some(3),
+                node: 2,
+            },
+            FuzzOp::ClearNode(1),
+        ]);
+
+        let (tree, index) = execute(&ops).unwrap();
+        assert_eq!(tree.parent(2), Some(3));
+        assert_eq!(tree.parent(1), None);
+        assert!(index.subtree_items(3).contains(20));
+        assert!(!index.subtree_items(1).contains(20));
+
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+
+        for _ in 0..200 {
+            let bytes = (0..256)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                })
+                .collect::<Vec<_>>();
+
+            let ops = FuzzOps::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            execute(&ops).unwrap();
+        }
+    }
+
+    #[test]
+    fn arbitrary_logs_apply() {
+        let bytes = (0..=255u8).collect::<Vec<_>>();
+        let mut u = Unstructured::new(&bytes);
+
+        let mut tree = Tree::<u8>::new();
+        tree.apply(u.arbitrary().unwrap());
+
+        let mut index = FlatSetIndex::<u8, u8>::new();
+        index.apply(u.arbitrary().unwrap());
+
+        let mut one = OneIndex::<u16, u16>::new();
+        one.apply(u.arbitrary().unwrap());
+    }
+}
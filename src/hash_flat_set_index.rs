@@ -1,10 +1,18 @@
-use crate::{IU32HashSet, IntSet, U32Set, u32based};
+use crate::{IU32HashSet, IntSet, U32Set, str_pool, u32based};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use rustc_hash::FxBuildHasher;
-use std::{borrow::Borrow, collections::hash_map, hash::Hash, marker::PhantomData};
+use std::{
+    borrow::Borrow,
+    collections::hash_map,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    sync::Arc,
+};
 
 #[repr(transparent)]
-pub struct HashFlatSetIndex<K, V> {
-    inner: u32based::FlatSetIndex<K, FxBuildHasher>,
+pub struct HashFlatSetIndex<K, V, S = FxBuildHasher> {
+    inner: u32based::FlatSetIndex<K, S>,
     _kv: PhantomData<(K, V)>,
 }
 
@@ -16,16 +24,43 @@ impl<K, V> HashFlatSetIndex<K, V> {
 
     #[inline]
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, Default::default())
+    }
+
+    /// Creates an index that maintains a value -> keys map, see [`keys_of`](Self::keys_of).
+    #[inline]
+    pub fn with_inverse() -> Self
+    where
+        K: Clone + Eq + Hash,
+    {
+        let mut index = Self::new();
+        index.enable_inverse();
+        index
+    }
+}
+
+impl<K, V, S> HashFlatSetIndex<K, V, S> {
+    #[inline]
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            inner: u32based::FlatSetIndex::with_capacity_and_hasher(capacity, hasher),
+            _kv: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn with_hasher(hasher: S) -> Self {
         Self {
-            inner: u32based::FlatSetIndex::with_capacity_and_hasher(capacity, Default::default()),
+            inner: u32based::FlatSetIndex::with_hasher(hasher),
             _kv: PhantomData,
         }
     }
 
     #[inline]
-    pub fn apply(&mut self, log: HashFlatSetIndexLog<K, V>) -> bool
+    pub fn apply(&mut self, log: HashFlatSetIndexLog<K, V, S>) -> bool
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.inner.apply(log.inner)
     }
@@ -35,6 +70,7 @@ impl<K, V> HashFlatSetIndex<K, V> {
     where
         K: Borrow<Q> + Eq + Hash,
         Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.inner.contains(k, value.into())
@@ -48,15 +84,70 @@ impl<K, V> HashFlatSetIndex<K, V> {
         self.inner.contains_none(value.into())
     }
 
+    /// Produces a log that, once applied to `self`, yields `other`.
+    #[inline]
+    pub fn diff(&self, other: &Self) -> HashFlatSetIndexLog<K, V, S>
+    where
+        K: Clone + Eq + Hash,
+        S: BuildHasher + Clone,
+    {
+        HashFlatSetIndexLog {
+            inner: self.inner.diff(&other.inner),
+            _v: PhantomData,
+        }
+    }
+
+    /// Removes from every key the values `other` holds for that same key.
+    #[inline]
+    pub fn difference_with(&mut self, other: &Self) -> bool
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.difference_with(&other.inner)
+    }
+
+    #[inline]
+    pub fn enable_inverse(&mut self)
+    where
+        K: Clone + Eq + Hash,
+    {
+        self.inner.enable_inverse()
+    }
+
+    /// Removes every key for which `f` returns `true` and returns them with their set.
+    pub fn extract_if<F>(&mut self, mut f: F) -> Vec<(K, IntSet<V>)>
+    where
+        F: FnMut(&K, &IntSet<V>) -> bool,
+        K: Eq + Hash,
+    {
+        self.inner
+            .extract_if(|k, v| f(k, unsafe { IntSet::from_u32set_ref(v) }))
+            .into_iter()
+            .map(|(k, v)| (k, unsafe { IntSet::from_set(v) }))
+            .collect()
+    }
+
     #[inline]
     pub fn get<Q>(&self, k: &Q) -> &IntSet<V>
     where
         K: Borrow<Q> + Eq + Hash,
         Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
     {
         unsafe { IntSet::from_u32set_ref(self.inner.get(k).as_set()) }
     }
 
+    #[inline]
+    pub fn has_inverse(&self) -> bool {
+        self.inner.has_inverse()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = (&K, &IntSet<V>)>
     where
@@ -72,18 +163,86 @@ impl<K, V> HashFlatSetIndex<K, V> {
         self.inner.keys()
     }
 
+    #[inline]
+    pub fn key_cardinality<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.key_cardinality(k)
+    }
+
+    /// Keys whose set contains `value`. Without [`enable_inverse`](Self::enable_inverse)
+    /// this scans every key.
+    #[inline]
+    pub fn keys_of(&self, value: V) -> impl Iterator<Item = &K>
+    where
+        V: Into<u32>,
+    {
+        self.inner.keys_of(value.into())
+    }
+
+    /// Number of keys holding at least one value.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
     #[inline]
     pub fn none(&self) -> &IntSet<V> {
         unsafe { IntSet::from_u32set_ref(self.inner.none().as_set()) }
     }
 
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (&K, &IntSet<V>)>
+    where
+        K: Eq + Hash + Sync,
+        S: BuildHasher + Sync,
+        V: Sync,
+    {
+        self.inner
+            .par_iter()
+            .map(|(k, v)| (k, unsafe { IntSet::from_u32set_ref(v.as_set()) }))
+    }
+
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_values(&self) -> impl ParallelIterator<Item = &IntSet<V>>
+    where
+        K: Eq + Hash + Sync,
+        S: BuildHasher + Sync,
+        V: Sync,
+    {
+        self.inner
+            .par_values()
+            .map(|v| unsafe { IntSet::from_u32set_ref(v.as_set()) })
+    }
+
+    /// Number of `(key, value)` pairs, excluding the `none` set.
+    #[inline]
+    pub fn value_count(&self) -> usize {
+        self.inner.value_count()
+    }
+
+    /// Adds to every key the values `other` holds for that same key.
+    #[inline]
+    pub fn union_with(&mut self, other: &Self) -> bool
+    where
+        K: Clone + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.union_with(&other.inner)
+    }
+
     #[inline]
     pub fn values(&self) -> IntSet<V> {
         unsafe { IntSet::from_set(self.inner.values()) }
     }
 }
 
-impl<K: Clone, V> Clone for HashFlatSetIndex<K, V> {
+impl<K: Clone, V, S: Clone> Clone for HashFlatSetIndex<K, V, S> {
     #[inline]
     fn clone(&self) -> Self {
         Self {
@@ -93,7 +252,7 @@ impl<K: Clone, V> Clone for HashFlatSetIndex<K, V> {
     }
 }
 
-impl<K, V> Default for HashFlatSetIndex<K, V> {
+impl<K, V, S: Default> Default for HashFlatSetIndex<K, V, S> {
     #[inline]
     fn default() -> Self {
         Self {
@@ -103,9 +262,9 @@ impl<K, V> Default for HashFlatSetIndex<K, V> {
     }
 }
 
-pub struct HashFlatSetIndexBuilder<K, V> {
-    base: HashFlatSetIndex<K, V>,
-    log: HashFlatSetIndexLog<K, V>,
+pub struct HashFlatSetIndexBuilder<K, V, S = FxBuildHasher> {
+    base: HashFlatSetIndex<K, V, S>,
+    log: HashFlatSetIndexLog<K, V, S>,
 }
 
 impl<K, V> HashFlatSetIndexBuilder<K, V> {
@@ -116,16 +275,38 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
 
     #[inline]
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, Default::default())
+    }
+}
+
+impl<K, V, S> HashFlatSetIndexBuilder<K, V, S> {
+    #[inline]
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self
+    where
+        S: Clone,
+    {
         Self {
-            base: HashFlatSetIndex::new(),
-            log: HashFlatSetIndexLog::with_capacity(capacity),
+            base: HashFlatSetIndex::with_hasher(hasher.clone()),
+            log: HashFlatSetIndexLog::with_capacity_and_hasher(capacity, hasher),
         }
     }
 
     #[inline]
-    pub fn build(mut self) -> HashFlatSetIndex<K, V>
+    pub fn with_hasher(hasher: S) -> Self
+    where
+        S: Clone,
+    {
+        Self {
+            base: HashFlatSetIndex::with_hasher(hasher.clone()),
+            log: HashFlatSetIndexLog::with_hasher(hasher),
+        }
+    }
+
+    #[inline]
+    pub fn build(mut self) -> HashFlatSetIndex<K, V, S>
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.base.apply(self.log);
         self.base
@@ -135,6 +316,7 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
     pub fn difference(&mut self, key: K, rhs: &IntSet<V>)
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.log.difference(&self.base, key, rhs.as_set());
     }
@@ -148,11 +330,22 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
     pub fn insert(&mut self, key: K, value: V) -> bool
     where
         K: Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.log.insert(&self.base, key, value)
     }
 
+    /// Adds every value of `set` to `key`, same as [`union`](Self::union).
+    #[inline]
+    pub fn insert_set(&mut self, key: K, set: &IntSet<V>)
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.log.union(&self.base, key, set.as_set());
+    }
+
     #[inline]
     pub fn insert_none(&mut self, value: V) -> bool
     where
@@ -165,6 +358,7 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
     pub fn intersection(&mut self, key: K, rhs: &IntSet<V>)
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.log.intersection(&self.base, key, rhs.as_set());
     }
@@ -178,6 +372,7 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
     pub fn remove(&mut self, key: K, value: V) -> bool
     where
         K: Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.log.remove(&self.base, key, value)
@@ -195,6 +390,7 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
     pub fn union(&mut self, key: K, rhs: &IntSet<V>)
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.log.union(&self.base, key, rhs.as_set());
     }
@@ -205,7 +401,40 @@ impl<K, V> HashFlatSetIndexBuilder<K, V> {
     }
 }
 
-impl<K, V> Default for HashFlatSetIndexBuilder<K, V> {
+impl<V, S: BuildHasher> HashFlatSetIndexBuilder<Arc<str>, V, S> {
+    /// Inserts using a key taken from the process-wide [`str_pool`](crate::str_pool::str_pool).
+    #[inline]
+    pub fn insert_str(&mut self, key: &str, value: V) -> bool
+    where
+        V: Into<u32>,
+    {
+        self.log.insert_str(&self.base, key, value)
+    }
+
+    #[inline]
+    pub fn remove_str(&mut self, key: &str, value: V) -> bool
+    where
+        V: Into<u32>,
+    {
+        self.log.remove_str(&self.base, key, value)
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for HashFlatSetIndexBuilder<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    V: Into<u32>,
+{
+    #[inline]
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K, V, S: Default> Default for HashFlatSetIndexBuilder<K, V, S> {
     #[inline]
     fn default() -> Self {
         Self {
@@ -215,8 +444,8 @@ impl<K, V> Default for HashFlatSetIndexBuilder<K, V> {
     }
 }
 
-pub struct HashFlatSetIndexLog<K, V> {
-    inner: u32based::FlatSetIndexLog<K, FxBuildHasher>,
+pub struct HashFlatSetIndexLog<K, V, S = FxBuildHasher> {
+    inner: u32based::FlatSetIndexLog<K, S>,
     _v: PhantomData<V>,
 }
 
@@ -228,124 +457,245 @@ impl<K, V> HashFlatSetIndexLog<K, V> {
 
     #[inline]
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, Default::default())
+    }
+}
+
+impl<K, V, S> HashFlatSetIndexLog<K, V, S> {
+    #[inline]
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         Self {
-            inner: u32based::FlatSetIndexLog::with_capacity_and_hasher(
-                capacity,
-                Default::default(),
-            ),
+            inner: u32based::FlatSetIndexLog::with_capacity_and_hasher(capacity, hasher),
             _v: PhantomData,
         }
     }
 
     #[inline]
-    pub fn contains<Q>(&self, base: &HashFlatSetIndex<K, V>, k: &Q, value: V) -> bool
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            inner: u32based::FlatSetIndexLog::with_hasher(hasher),
+            _v: PhantomData,
+        }
+    }
+
+    /// Drops every staged change while keeping the allocated capacity.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    #[inline]
+    pub fn contains<Q>(&self, base: &HashFlatSetIndex<K, V, S>, k: &Q, value: V) -> bool
     where
         Q: ?Sized + Eq + Hash,
         K: Borrow<Q> + Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.inner.contains(&base.inner, k, value.into())
     }
 
     #[inline]
-    pub fn contains_none(&self, base: &HashFlatSetIndex<K, V>, value: V) -> bool
+    pub fn contains_none(&self, base: &HashFlatSetIndex<K, V, S>, value: V) -> bool
     where
         u32: From<V>,
     {
         self.inner.contains_none(&base.inner, value.into())
     }
 
+    /// Drops the staged changes of a single key. Returns `true` if the key was staged.
+    #[inline]
+    pub fn discard_key<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.discard_key(k)
+    }
+
     #[inline]
-    pub fn get<'a, Q>(&'a self, base: &'a HashFlatSetIndex<K, V>, k: &Q) -> &'a IntSet<V>
+    pub fn discard_none(&mut self) -> bool {
+        self.inner.discard_none()
+    }
+
+    #[inline]
+    pub fn get<'a, Q>(&'a self, base: &'a HashFlatSetIndex<K, V, S>, k: &Q) -> &'a IntSet<V>
     where
         Q: ?Sized + Eq + Hash,
         K: Borrow<Q> + Eq + Hash,
+        S: BuildHasher,
     {
         unsafe { IntSet::from_u32set_ref(self.inner.get(&base.inner, k)) }
     }
 
     #[inline]
-    pub fn none<'a>(&'a self, base: &'a HashFlatSetIndex<K, V>) -> &'a IntSet<V> {
+    pub fn none<'a>(&'a self, base: &'a HashFlatSetIndex<K, V, S>) -> &'a IntSet<V> {
         unsafe { IntSet::from_u32set_ref(self.inner.none(&base.inner)) }
     }
 
     #[inline]
-    pub fn insert(&mut self, base: &HashFlatSetIndex<K, V>, key: K, value: V) -> bool
+    pub fn insert(&mut self, base: &HashFlatSetIndex<K, V, S>, key: K, value: V) -> bool
     where
         K: Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.inner.insert(&base.inner, key, value.into())
     }
 
     #[inline]
-    pub fn insert_none(&mut self, base: &HashFlatSetIndex<K, V>, value: V) -> bool
+    pub fn insert_none(&mut self, base: &HashFlatSetIndex<K, V, S>, value: V) -> bool
     where
         V: Into<u32>,
     {
         self.inner.insert_none(&base.inner, value.into())
     }
 
+    /// `true` when nothing is staged.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     #[inline]
-    pub fn remove(&mut self, base: &HashFlatSetIndex<K, V>, key: K, value: V) -> bool
+    pub fn key_cardinality<Q>(&self, base: &HashFlatSetIndex<K, V, S>, k: &Q) -> usize
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.key_cardinality(&base.inner, k)
+    }
+
+    /// Number of keys holding at least one value once this log is applied to `base`.
+    #[inline]
+    pub fn len(&self, base: &HashFlatSetIndex<K, V, S>) -> usize
     where
         K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.len(&base.inner)
+    }
+
+    #[inline]
+    pub fn remove(&mut self, base: &HashFlatSetIndex<K, V, S>, key: K, value: V) -> bool
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.inner.remove(&base.inner, key, value.into())
     }
 
     #[inline]
-    pub fn remove_none(&mut self, base: &HashFlatSetIndex<K, V>, value: V) -> bool
+    pub fn remove_none(&mut self, base: &HashFlatSetIndex<K, V, S>, value: V) -> bool
     where
         V: Into<u32>,
     {
         self.inner.remove_none(&base.inner, value.into())
     }
 
+    /// Returns the staged changes, leaving this log empty and ready for reuse.
+    /// This log keeps its capacity.
+    #[inline]
+    pub fn take(&mut self) -> Self
+    where
+        K: Eq + Hash,
+        S: BuildHasher + Clone,
+    {
+        Self {
+            inner: self.inner.take(),
+            _v: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn value_count(&self, base: &HashFlatSetIndex<K, V, S>) -> usize
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.inner.value_count(&base.inner)
+    }
+
     /* ---- bulk operations --------------------------------------------- */
 
     #[inline]
-    pub fn union(&mut self, base: &HashFlatSetIndex<K, V>, key: K, rhs: &U32Set)
+    pub fn union(&mut self, base: &HashFlatSetIndex<K, V, S>, key: K, rhs: &U32Set)
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.inner.union(&base.inner, key, rhs)
     }
 
     #[inline]
-    pub fn union_none(&mut self, base: &HashFlatSetIndex<K, V>, rhs: &U32Set) {
+    pub fn union_none(&mut self, base: &HashFlatSetIndex<K, V, S>, rhs: &U32Set) {
         self.inner.union_none(&base.inner, rhs)
     }
 
     #[inline]
-    pub fn difference(&mut self, base: &HashFlatSetIndex<K, V>, key: K, rhs: &U32Set)
+    pub fn difference(&mut self, base: &HashFlatSetIndex<K, V, S>, key: K, rhs: &U32Set)
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.inner.difference(&base.inner, key, rhs)
     }
 
     #[inline]
-    pub fn difference_none(&mut self, base: &HashFlatSetIndex<K, V>, rhs: &U32Set) {
+    pub fn difference_none(&mut self, base: &HashFlatSetIndex<K, V, S>, rhs: &U32Set) {
         self.inner.difference_none(&base.inner, rhs)
     }
 
     #[inline]
-    pub fn intersection(&mut self, base: &HashFlatSetIndex<K, V>, key: K, rhs: &U32Set)
+    pub fn intersection(&mut self, base: &HashFlatSetIndex<K, V, S>, key: K, rhs: &U32Set)
     where
         K: Eq + Hash,
+        S: BuildHasher,
     {
         self.inner.intersection(&base.inner, key, rhs)
     }
 
     #[inline]
-    pub fn intersection_none(&mut self, base: &HashFlatSetIndex<K, V>, rhs: &U32Set) {
+    pub fn intersection_none(&mut self, base: &HashFlatSetIndex<K, V, S>, rhs: &U32Set) {
         self.inner.intersection_none(&base.inner, rhs)
     }
 }
 
-impl<K, V> Default for HashFlatSetIndexLog<K, V> {
+impl<V, S: BuildHasher> HashFlatSetIndexLog<Arc<str>, V, S> {
+    /// Inserts using a key taken from the process-wide [`str_pool`](crate::str_pool::str_pool).
+    #[inline]
+    pub fn insert_str(
+        &mut self,
+        base: &HashFlatSetIndex<Arc<str>, V, S>,
+        key: &str,
+        value: V,
+    ) -> bool
+    where
+        V: Into<u32>,
+    {
+        self.insert(base, str_pool().intern(key), value)
+    }
+
+    /// Removes without adding `key` to the pool when it is not pooled yet.
+    #[inline]
+    pub fn remove_str(
+        &mut self,
+        base: &HashFlatSetIndex<Arc<str>, V, S>,
+        key: &str,
+        value: V,
+    ) -> bool
+    where
+        V: Into<u32>,
+    {
+        let key = str_pool().get(key).unwrap_or_else(|| Arc::from(key));
+        self.remove(base, key, value)
+    }
+}
+
+impl<K, V, S: Default> Default for HashFlatSetIndexLog<K, V, S> {
     #[inline]
     fn default() -> Self {
         Self {
@@ -355,14 +705,14 @@ impl<K, V> Default for HashFlatSetIndexLog<K, V> {
     }
 }
 
-pub struct HashFlatSetIndexTrx<'a, K, V> {
-    base: &'a HashFlatSetIndex<K, V>,
-    log: &'a HashFlatSetIndexLog<K, V>,
+pub struct HashFlatSetIndexTrx<'a, K, V, S = FxBuildHasher> {
+    base: &'a HashFlatSetIndex<K, V, S>,
+    log: &'a HashFlatSetIndexLog<K, V, S>,
 }
 
-impl<'a, K, V> HashFlatSetIndexTrx<'a, K, V> {
+impl<'a, K, V, S> HashFlatSetIndexTrx<'a, K, V, S> {
     #[inline]
-    pub fn new(base: &'a HashFlatSetIndex<K, V>, log: &'a HashFlatSetIndexLog<K, V>) -> Self {
+    pub fn new(base: &'a HashFlatSetIndex<K, V, S>, log: &'a HashFlatSetIndexLog<K, V, S>) -> Self {
         Self { base, log }
     }
 
@@ -371,6 +721,7 @@ impl<'a, K, V> HashFlatSetIndexTrx<'a, K, V> {
     where
         K: Borrow<Q> + Eq + Hash,
         Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
         V: Into<u32>,
     {
         self.log.contains(self.base, k, value)
@@ -389,12 +740,130 @@ impl<'a, K, V> HashFlatSetIndexTrx<'a, K, V> {
     where
         K: Borrow<Q> + Eq + Hash,
         Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
     {
         self.log.get(self.base, k)
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn key_cardinality<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.log.key_cardinality(self.base, k)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.log.len(self.base)
+    }
+
     #[inline]
     pub fn none(&self) -> &IntSet<V> {
         self.log.none(self.base)
     }
+
+    #[inline]
+    pub fn value_count(&self) -> usize
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        self.log.value_count(self.base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_str_does_not_pool_unknown_keys() {
+        let mut index = HashFlatSetIndex::<Arc<str>, u32>::new();
+        let mut log = HashFlatSetIndexLog::new();
+
+        assert!(log.insert_str(&index, "remove-str-known", 1));
+        index.apply(log);
+
+        let mut log = HashFlatSetIndexLog::new();
+        assert!(log.remove_str(&index, "remove-str-known", 1));
+        assert!(!log.remove_str(&index, "remove-str-unknown", 1));
+        index.apply(log);
+
+        assert!(!index.contains("remove-str-known", 1));
+        assert!(str_pool().get("remove-str-unknown").is_none());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter_matches_iter() {
+        fn sorted(set: &IntSet<u32>) -> Vec<u32> {
+            let mut vec = set.iter().collect::<Vec<_>>();
+            vec.sort_unstable();
+            vec
+        }
+
+        let mut builder = HashFlatSetIndexBuilder::<String, u32>::new();
+
+        for k in 0..300u32 {
+            builder.insert(format!("k{}", k % 40), k);
+        }
+
+        let index = builder.build();
+
+        let mut seq = index
+            .iter()
+            .map(|(k, v)| (k.clone(), sorted(v)))
+            .collect::<Vec<_>>();
+
+        let mut par = index
+            .par_iter()
+            .map(|(k, v)| (k.clone(), sorted(v)))
+            .collect::<Vec<_>>();
+
+        seq.sort_unstable();
+        par.sort_unstable();
+        assert_eq!(par, seq);
+
+        let values = index
+            .par_values()
+            .map(Clone::clone)
+            .reduce(IntSet::new, |a, b| a | b);
+
+        assert_eq!(sorted(&values), sorted(&index.values()));
+    }
+
+    #[test]
+    fn builder_insert_set_and_extend() {
+        let mut builder = HashFlatSetIndexBuilder::<&str, u32>::new();
+
+        builder.insert_set("a", &IntSet::from_iter([1, 2]));
+        builder.insert_set("a", &IntSet::from_iter([2, 3]));
+        builder.insert_set("empty", &IntSet::new());
+        builder.extend([("b", 10), ("a", 4), ("b", 10)]);
+        builder.insert_none(7);
+
+        let index = builder.build();
+
+        assert!(index.get("a") == &IntSet::from_iter([1, 2, 3, 4]));
+        assert!(index.get("b") == &IntSet::from_iter([10]));
+        assert!(index.get("empty").is_empty());
+        assert!(index.contains_none(7));
+        assert_eq!(index.value_count(), 5);
+    }
 }
@@ -1,12 +1,321 @@
-use crate::U32Set;
+use crate::{
+    HashBackend, SetStorage, U32Set,
+    strict::{InvalidKey, KeyOutOfRange, check_key, try_key},
+    u32_set_ext::{range_sorted, smallest},
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use rustc_hash::FxHasher;
 use std::{
-    collections::hash_set,
+    collections::{BTreeSet, HashSet, hash_set},
+    fmt::{self, Debug},
+    hash::{Hash, Hasher},
+    iter::{self, FusedIterator},
     marker::PhantomData,
-    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Sub, SubAssign},
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Bound, RangeBounds, Sub, SubAssign},
 };
 
+/// Most values [`IntSet::insert_range`] reserves room for at once.
+const RANGE_RESERVE_CHUNK: usize = 1 << 20;
+
 #[repr(transparent)]
-pub struct IntSet<K>(U32Set, PhantomData<K>);
+pub struct IntSet<K, B: SetStorage = HashBackend>(B::Set, PhantomData<K>);
+
+impl<K, B: SetStorage> IntSet<K, B> {
+    #[inline]
+    pub fn clear(&mut self) {
+        B::clear(&mut self.0);
+    }
+
+    #[inline]
+    pub fn contains(&self, key: K) -> bool
+    where
+        K: Into<u32>,
+    {
+        B::contains(&self.0, key.into())
+    }
+
+    /// `true` if every key of `keys` is in the set; stops at the first miss.
+    #[inline]
+    pub fn contains_all<I>(&self, keys: I) -> bool
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<u32>,
+    {
+        keys.into_iter().all(|k| self.contains(k))
+    }
+
+    /// `true` if any key of `keys` is in the set; stops at the first hit.
+    #[inline]
+    pub fn contains_any<I>(&self, keys: I) -> bool
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<u32>,
+    {
+        keys.into_iter().any(|k| self.contains(k))
+    }
+
+    /// Membership of each of `keys`, in the same order, answered in one pass.
+    #[inline]
+    pub fn contains_many(&self, keys: &[K]) -> Vec<bool>
+    where
+        K: Copy + Into<u32>,
+    {
+        keys.iter()
+            .map(|k| B::contains(&self.0, (*k).into()))
+            .collect()
+    }
+
+    /// Rekeys the set as `K2`, dropping values that do not convert to `K2`.
+    pub fn cast<K2>(self) -> IntSet<K2, B>
+    where
+        K2: TryFrom<u32>,
+    {
+        let mut set = self.0;
+        B::retain(&mut set, |v| K2::try_from(v).is_ok());
+        IntSet(set, PhantomData)
+    }
+
+    /// Lazily yields the keys of `self` not in `other`.
+    #[inline]
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, K, B>
+    where
+        K: TryFrom<u32>,
+    {
+        Difference(Probe::new(&self.0, &other.0, false), PhantomData)
+    }
+
+    /// Removes every key, yielding them. The hash backend keeps its allocated
+    /// capacity.
+    #[inline]
+    pub fn drain(&mut self) -> Drain<'_, K, B>
+    where
+        K: TryFrom<u32>,
+    {
+        Drain(B::drain(&mut self.0), PhantomData)
+    }
+
+    /// Lazily yields the keys present in both sets.
+    #[inline]
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, K, B>
+    where
+        K: TryFrom<u32>,
+    {
+        let (small, large) = self.small_large(other);
+        Intersection(Probe::new(small, large, true), PhantomData)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        B::is_empty(&self.0)
+    }
+
+    /// Keys in storage order: arbitrary for [`HashBackend`], ascending for
+    /// [`RoaringBackend`](crate::RoaringBackend).
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, K, B>
+    where
+        K: TryFrom<u32>,
+    {
+        Iter(B::iter(&self.0), PhantomData)
+    }
+
+    /// Keys in ascending order of their `u32` representation.
+    #[inline]
+    pub fn iter_sorted(&self) -> impl Iterator<Item = K> + use<K, B>
+    where
+        K: TryFrom<u32>,
+    {
+        let mut values = B::iter(&self.0).collect::<Vec<_>>();
+        values.sort_unstable();
+        values.into_iter().filter_map(try_key)
+    }
+
+    /// Number of values of `self` not in `other`, without building the difference.
+    #[inline]
+    pub fn difference_len(&self, other: &Self) -> usize {
+        self.len() - self.intersection_len(other)
+    }
+
+    /// Number of values in both sets, without building the intersection.
+    pub fn intersection_len(&self, other: &Self) -> usize {
+        let (small, large) = self.small_large(other);
+        B::iter(small).filter(|v| B::contains(large, *v)).count()
+    }
+
+    #[inline]
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        let (small, large) = self.small_large(other);
+        !B::iter(small).any(|v| B::contains(large, v))
+    }
+
+    #[inline]
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && B::iter(&self.0).all(|v| B::contains(&other.0, v))
+    }
+
+    #[inline]
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.is_subset(self)
+    }
+
+    #[inline]
+    pub fn max(&self) -> Option<K>
+    where
+        K: TryFrom<u32>,
+    {
+        B::iter(&self.0).max().and_then(try_key)
+    }
+
+    #[inline]
+    pub fn min(&self) -> Option<K>
+    where
+        K: TryFrom<u32>,
+    {
+        B::iter(&self.0).min().and_then(try_key)
+    }
+
+    /// Keys falling in `range`, in ascending order.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = K> + use<K, B, R>
+    where
+        K: Clone + Into<u32> + TryFrom<u32>,
+        R: RangeBounds<K>,
+    {
+        fn map<K: Clone + Into<u32>>(b: Bound<&K>) -> Bound<u32> {
+            b.map(|k| k.clone().into())
+        }
+
+        let range = (map(range.start_bound()), map(range.end_bound()));
+
+        range_sorted(B::iter(&self.0), range)
+            .into_iter()
+            .filter_map(try_key)
+    }
+
+    /// Keeps the keys for which `f` returns `true`. Values that do not convert
+    /// to `K` are never passed to `f` and are kept.
+    #[inline]
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(K) -> bool,
+        K: TryFrom<u32>,
+    {
+        B::retain(&mut self.0, |v| try_key(v).is_none_or(&mut f))
+    }
+
+    /// The `n` smallest keys, in ascending order.
+    #[inline]
+    pub fn smallest(&self, n: usize) -> Vec<K>
+    where
+        K: TryFrom<u32>,
+    {
+        smallest(B::iter(&self.0), n)
+            .into_iter()
+            .filter_map(try_key)
+            .collect()
+    }
+
+    /// Lazily yields the keys present in either set, each once.
+    #[inline]
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, K, B>
+    where
+        K: TryFrom<u32>,
+    {
+        let rest = Probe::new(&other.0, &self.0, false);
+        Union(B::iter(&self.0).chain(rest), PhantomData)
+    }
+
+    /// Number of values in either set, without building the union.
+    #[inline]
+    pub fn union_len(&self, other: &Self) -> usize {
+        self.len() + other.len() - self.intersection_len(other)
+    }
+
+    #[inline]
+    fn small_large<'a>(&'a self, other: &'a Self) -> (&'a B::Set, &'a B::Set) {
+        if self.len() <= other.len() {
+            (&self.0, &other.0)
+        } else {
+            (&other.0, &self.0)
+        }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, key: K) -> bool
+    where
+        K: Into<u32>,
+    {
+        B::insert(&mut self.0, key.into())
+    }
+
+    /// Like [`insert`](Self::insert), but rejects keys above [`max_id`](crate::max_id).
+    #[inline]
+    pub fn insert_checked(&mut self, key: K) -> Result<bool, KeyOutOfRange>
+    where
+        K: Into<u32>,
+    {
+        let v = check_key(key.into(), crate::max_id())?;
+        Ok(B::insert(&mut self.0, v))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        B::len(&self.0)
+    }
+
+    #[inline]
+    pub fn remove(&mut self, key: K) -> bool
+    where
+        K: Into<u32>,
+    {
+        B::remove(&mut self.0, key.into())
+    }
+
+    /// Shape of the set, to help pick a backend and tune capacity.
+    pub fn stats(&self) -> IntSetStats {
+        let count = B::len(&self.0);
+        let min = B::iter(&self.0).min();
+        let max = B::iter(&self.0).max();
+        let mut histogram = [0; IntSetStats::BUCKETS];
+
+        if let (Some(min), Some(max)) = (min, max) {
+            let span = u64::from(max - min) + 1;
+
+            for v in B::iter(&self.0) {
+                let i = u64::from(v - min) * IntSetStats::BUCKETS as u64 / span;
+                histogram[i as usize] += 1;
+            }
+        }
+
+        IntSetStats {
+            count,
+            min,
+            max,
+            histogram,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntSetStats {
+    pub count: usize,
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+    /// Value counts over [`BUCKETS`](Self::BUCKETS) equal-width buckets spanning `min..=max`.
+    pub histogram: [usize; Self::BUCKETS],
+}
+
+impl IntSetStats {
+    pub const BUCKETS: usize = 16;
+
+    /// Fraction of `min..=max` that is present, `0.0` when empty.
+    pub fn density(&self) -> f64 {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => self.count as f64 / (f64::from(max - min) + 1.0),
+            _ => 0.0,
+        }
+    }
+}
 
 impl<K> IntSet<K> {
     #[inline]
@@ -42,108 +351,328 @@ impl<K> IntSet<K> {
         &self.0
     }
 
+    /// Mutable access to the raw values. Safe because `K` only constrains the
+    /// typed API; values that do not convert to `K` are skipped when iterating.
     #[inline]
-    pub fn clear(&mut self) {
-        self.0.clear();
+    pub fn as_set_mut(&mut self) -> &mut U32Set {
+        &mut self.0
+    }
+
+    /// Reinterprets the set as keyed by `K2` without copying.
+    ///
+    /// # Safety
+    /// The caller must ensure that `K` and `K2` transpose to the same `u32`
+    /// representation.
+    #[inline]
+    pub unsafe fn as_cast_ref<K2>(&self) -> &IntSet<K2> {
+        // SAFETY: `IntSet` is `#[repr(transparent)]` over `U32Set` for every `K`.
+        unsafe { IntSet::from_u32set_ref(&self.0) }
     }
 
     #[inline]
-    pub fn contains(&self, key: K) -> bool
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Consuming iterator that never skips values, hence exact-size.
+    #[inline]
+    pub fn into_iter_exact(self) -> IntoIterExact<K>
     where
-        K: Into<u32>,
+        K: From<u32>,
     {
-        self.0.contains(&key.into())
+        IntoIterExact(self.0.into_iter(), PhantomData)
     }
 
-    #[inline]
-    pub fn extend<I>(&mut self, iter: I)
+    /// Builds the set from ascending keys, pre-sizing the table once and
+    /// skipping adjacent duplicates without hashing them.
+    pub fn from_sorted_slice(keys: &[K]) -> Self
     where
-        I: IntoIterator<Item = K>,
-        K: Into<u32>,
+        K: Copy + Into<u32>,
     {
-        self.0.extend(iter.into_iter().map(Into::into))
+        let mut set = U32Set::with_capacity_and_hasher(keys.len(), Default::default());
+        let mut prev = None;
+
+        for &k in keys {
+            let v = k.into();
+            debug_assert!(prev.is_none_or(|p| p <= v), "slice is not sorted");
+
+            if prev != Some(v) {
+                set.insert(v);
+                prev = Some(v);
+            }
+        }
+
+        Self(set, PhantomData)
     }
 
-    #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+    /// Inserts every value of `range`, reserving room for up to 2^20 of them
+    /// up front; larger ranges grow the table as they go. Returns the number
+    /// of newly inserted keys.
+    pub fn insert_range<R>(&mut self, range: R) -> usize
+    where
+        K: Clone + Into<u32>,
+        R: RangeBounds<K>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(k) => k.clone().into(),
+            Bound::Excluded(k) => match k.clone().into().checked_add(1) {
+                Some(v) => v,
+                None => return 0,
+            },
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(k) => k.clone().into(),
+            Bound::Excluded(k) => match k.clone().into().checked_sub(1) {
+                Some(v) => v,
+                None => return 0,
+            },
+            Bound::Unbounded => u32::MAX,
+        };
+
+        if start > end {
+            return 0;
+        }
+
+        let len = usize::try_from(end - start)
+            .ok()
+            .and_then(|n| n.checked_add(1))
+            .unwrap_or(usize::MAX);
+
+        let before = self.0.len();
+        self.0.reserve(len.min(RANGE_RESERVE_CHUNK));
+        self.0.extend(start..=end);
+        self.0.len() - before
     }
 
+    /// Like [`iter`](Self::iter), but reports values that fail `K::try_from`
+    /// instead of skipping them.
     #[inline]
-    pub fn insert(&mut self, key: K) -> bool
+    pub fn try_iter(&self) -> impl Iterator<Item = Result<K, InvalidKey>> + '_
     where
-        K: Into<u32>,
+        K: TryFrom<u32>,
     {
-        self.0.insert(key.into())
+        self.0
+            .iter()
+            .map(|&v| K::try_from(v).map_err(|_| InvalidKey(v)))
     }
 
+    #[cfg(feature = "rayon")]
     #[inline]
-    pub fn iter(&self) -> Iter<'_, K>
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = K> + '_
     where
-        K: TryFrom<u32>,
+        K: Send + TryFrom<u32>,
     {
-        Iter(self.0.iter(), PhantomData)
+        self.0.par_iter().filter_map(|v| try_key(*v))
     }
 
+    /// Reserves room for at least `additional` more keys.
     #[inline]
-    pub fn len(&self) -> usize {
-        self.0.len()
+    pub fn reserve(&mut self, additional: usize) {
+        self.0.reserve(additional);
     }
 
+    /// Releases the capacity left over after large removals.
     #[inline]
-    pub fn remove(&mut self, key: K) -> bool
-    where
-        K: Into<u32>,
-    {
-        self.0.remove(&key.into())
+    pub fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit();
+    }
+}
+
+/// Intersection of all `sets`, smallest first, stopping as soon as the result
+/// is empty. No input yields an empty set.
+pub fn intersect_all<'a, K: 'a>(sets: impl IntoIterator<Item = &'a IntSet<K>>) -> IntSet<K> {
+    let mut sets = sets.into_iter().collect::<Vec<_>>();
+    sets.sort_unstable_by_key(|s| s.len());
+
+    let mut iter = sets.into_iter();
+
+    let Some(first) = iter.next() else {
+        return IntSet::default();
+    };
+
+    let mut set = first.0.clone();
+
+    for other in iter {
+        if set.is_empty() {
+            break;
+        }
+
+        set.retain(|v| other.0.contains(v));
+    }
+
+    IntSet(set, PhantomData)
+}
+
+/// Union of all `sets`, pre-sized from the sum of their cardinalities.
+pub fn union_all<'a, K: 'a>(sets: impl IntoIterator<Item = &'a IntSet<K>>) -> IntSet<K> {
+    let mut out = IntSet::default();
+    union_all_into(&mut out, sets);
+    out
+}
+
+/// Like [`union_all`], but adds into `out` so its allocation can be reused
+/// across calls.
+pub fn union_all_into<'a, K: 'a>(
+    out: &mut IntSet<K>,
+    sets: impl IntoIterator<Item = &'a IntSet<K>>,
+) {
+    let sets = sets.into_iter().collect::<Vec<_>>();
+    out.0.reserve(sets.iter().map(|s| s.len()).sum());
+
+    for s in sets {
+        out.0.extend(s.0.iter().copied());
     }
 }
 
-impl<K> Clone for IntSet<K> {
+/// Union of all `sets`, folded in parallel.
+#[cfg(feature = "rayon")]
+pub fn union_many<K: Sync>(sets: &[&IntSet<K>]) -> IntSet<K> {
+    let set = sets
+        .par_iter()
+        .fold(U32Set::default, |mut acc, s| {
+            acc.extend(s.0.iter().copied());
+            acc
+        })
+        .reduce(U32Set::default, |a, b| {
+            let (mut large, small) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+            large.extend(small);
+            large
+        });
+
+    IntSet(set, PhantomData)
+}
+
+/// Intersection of all `sets`, probing the smallest one in parallel.
+/// An empty slice yields an empty set.
+#[cfg(feature = "rayon")]
+pub fn intersect_many<K: Sync>(sets: &[&IntSet<K>]) -> IntSet<K> {
+    let Some(smallest) = sets.iter().min_by_key(|s| s.len()) else {
+        return IntSet::default();
+    };
+
+    let set = smallest
+        .0
+        .par_iter()
+        .filter(|v| sets.iter().all(|s| s.0.contains(v)))
+        .copied()
+        .collect();
+
+    IntSet(set, PhantomData)
+}
+
+impl<K, B: SetStorage> Clone for IntSet<K, B> {
     #[inline]
     fn clone(&self) -> Self {
         Self(self.0.clone(), PhantomData)
     }
 }
 
-impl<K> Default for IntSet<K> {
+impl<K, B: SetStorage> Debug for IntSet<K, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut v = B::iter(&self.0).collect::<Vec<_>>();
+        v.sort_unstable();
+        f.debug_set().entries(v).finish()
+    }
+}
+
+impl<K, B: SetStorage> Default for IntSet<K, B> {
     #[inline]
     fn default() -> Self {
-        Self::new()
+        Self(Default::default(), PhantomData)
+    }
+}
+
+impl<K, B: SetStorage> Eq for IntSet<K, B> {}
+
+impl<K, B: SetStorage> Extend<K> for IntSet<K, B>
+where
+    K: Into<u32>,
+{
+    #[inline]
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        for k in iter {
+            B::insert(&mut self.0, k.into());
+        }
     }
 }
 
-impl<K> FromIterator<K> for IntSet<K>
+impl<K> From<BTreeSet<K>> for IntSet<K>
+where
+    K: Into<u32>,
+{
+    #[inline]
+    fn from(set: BTreeSet<K>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+impl<K, S> From<HashSet<K, S>> for IntSet<K>
+where
+    K: Into<u32>,
+{
+    #[inline]
+    fn from(set: HashSet<K, S>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+/// Keys in ascending order.
+impl<K> From<IntSet<K>> for Vec<K>
+where
+    K: TryFrom<u32>,
+{
+    #[inline]
+    fn from(set: IntSet<K>) -> Self {
+        set.iter_sorted().collect()
+    }
+}
+
+impl<K, B: SetStorage> FromIterator<K> for IntSet<K, B>
 where
     K: Into<u32>,
 {
     fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
-        IntSet(
-            U32Set::from_iter(iter.into_iter().map(Into::into)),
-            PhantomData,
-        )
+        let mut set = Self::default();
+        set.extend(iter);
+        set
     }
 }
 
-impl<K> IntoIterator for IntSet<K>
+/// Order-independent: equal sets hash the same regardless of iteration order.
+impl<K, B: SetStorage> Hash for IntSet<K, B> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let sum = B::iter(&self.0).fold(0u64, |acc, v| {
+            let mut h = FxHasher::default();
+            v.hash(&mut h);
+            acc.wrapping_add(h.finish())
+        });
+
+        state.write_usize(B::len(&self.0));
+        state.write_u64(sum);
+    }
+}
+
+impl<K, B: SetStorage> IntoIterator for IntSet<K, B>
 where
     K: TryFrom<u32>,
 {
     type Item = K;
-    type IntoIter = IntoIter<K>;
+    type IntoIter = IntoIter<K, B>;
 
     #[inline]
     fn into_iter(self) -> Self::IntoIter {
-        IntoIter(self.0.into_iter(), PhantomData)
+        IntoIter(B::into_iter(self.0), PhantomData)
     }
 }
 
-impl<'a, K> IntoIterator for &'a IntSet<K>
+impl<'a, K, B: SetStorage> IntoIterator for &'a IntSet<K, B>
 where
     K: TryFrom<u32>,
 {
     type Item = K;
-    type IntoIter = Iter<'a, K>;
+    type IntoIter = Iter<'a, K, B>;
 
     #[inline]
     fn into_iter(self) -> Self::IntoIter {
@@ -151,16 +680,136 @@ where
     }
 }
 
-impl<K> PartialEq for IntSet<K> {
+/// Set equality: order and duplicates in the slice are ignored.
+impl<K> PartialEq<&[K]> for IntSet<K>
+where
+    K: Copy + Into<u32>,
+{
+    fn eq(&self, other: &&[K]) -> bool {
+        other.iter().all(|k| self.0.contains(&(*k).into()))
+            && other.iter().map(|k| (*k).into()).collect::<U32Set>().len() == self.len()
+    }
+}
+
+impl<K, B: SetStorage> PartialEq for IntSet<K, B> {
     #[inline]
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
     }
 }
 
-pub struct IntoIter<K>(hash_set::IntoIter<u32>, PhantomData<K>);
+#[cfg(feature = "roaring")]
+impl<K> IntSet<K, crate::RoaringBackend> {
+    #[inline]
+    pub fn as_bitmap(&self) -> &roaring::RoaringBitmap {
+        &self.0
+    }
+}
+
+#[cfg(feature = "roaring")]
+impl<K> From<IntSet<K>> for IntSet<K, crate::RoaringBackend> {
+    #[inline]
+    fn from(set: IntSet<K>) -> Self {
+        Self(set.0.into_iter().collect(), PhantomData)
+    }
+}
+
+#[cfg(feature = "roaring")]
+impl<K> From<IntSet<K, crate::RoaringBackend>> for IntSet<K> {
+    #[inline]
+    fn from(set: IntSet<K, crate::RoaringBackend>) -> Self {
+        Self(set.0.into_iter().collect(), PhantomData)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K> serde::Serialize for IntSet<K> {
+    #[inline]
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::codec::u32_set::serialize(&self.0, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K> serde::Deserialize<'de> for IntSet<K> {
+    #[inline]
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::codec::u32_set::deserialize(deserializer).map(|s| Self(s, PhantomData))
+    }
+}
+
+/// Values of `iter` whose membership in `other` is `present`.
+struct Probe<'a, B: SetStorage> {
+    iter: B::Iter<'a>,
+    other: &'a B::Set,
+    present: bool,
+}
+
+impl<'a, B: SetStorage> Probe<'a, B> {
+    #[inline]
+    fn new(set: &'a B::Set, other: &'a B::Set, present: bool) -> Self {
+        Self {
+            iter: B::iter(set),
+            other,
+            present,
+        }
+    }
+}
 
-impl<K> Iterator for IntoIter<K>
+impl<B: SetStorage> Iterator for Probe<'_, B> {
+    type Item = u32;
+
+    #[inline]
+    fn next(&mut self) -> Option<u32> {
+        self.iter
+            .find(|v| B::contains(self.other, *v) == self.present)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'a, B: SetStorage> FusedIterator for Probe<'a, B> where B::Iter<'a>: FusedIterator {}
+
+macro_rules! lazy_op {
+    ($name:ident, $inner:ty) => {
+        pub struct $name<'a, K, B: SetStorage = HashBackend>($inner, PhantomData<K>);
+
+        impl<K, B: SetStorage> Iterator for $name<'_, K, B>
+        where
+            K: TryFrom<u32>,
+        {
+            type Item = K;
+
+            #[inline]
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0.find_map(try_key)
+            }
+
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (0, self.0.size_hint().1)
+            }
+        }
+
+        impl<'a, K, B: SetStorage> FusedIterator for $name<'a, K, B>
+        where
+            B::Iter<'a>: FusedIterator,
+            K: TryFrom<u32>,
+        {
+        }
+    };
+}
+
+lazy_op!(Difference, Probe<'a, B>);
+lazy_op!(Intersection, Probe<'a, B>);
+lazy_op!(Union, iter::Chain<B::Iter<'a>, Probe<'a, B>>);
+
+pub struct Drain<'a, K, B: SetStorage = HashBackend>(B::Drain<'a>, PhantomData<K>);
+
+impl<K, B: SetStorage> Iterator for Drain<'_, K, B>
 where
     K: TryFrom<u32>,
 {
@@ -168,18 +817,23 @@ where
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().and_then(|k| K::try_from(k).ok())
+        self.0.find_map(try_key)
     }
 
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        (0, self.0.size_hint().1)
     }
 }
 
-pub struct Iter<'a, K>(hash_set::Iter<'a, u32>, PhantomData<K>);
+/// Consuming iterator over the keys.
+///
+/// Values that fail `K::try_from` are skipped (or panic under
+/// [`set_strict`](crate::set_strict)), so the size hint is only an upper
+/// bound; use [`IntSet::into_iter_exact`] when `K: From<u32>`.
+pub struct IntoIter<K, B: SetStorage = HashBackend>(B::IntoIter, PhantomData<K>);
 
-impl<K> Iterator for Iter<'_, K>
+impl<K, B: SetStorage> Iterator for IntoIter<K, B>
 where
     K: TryFrom<u32>,
 {
@@ -187,18 +841,44 @@ where
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().and_then(|v| K::try_from(*v).ok())
+        self.0.find_map(try_key)
     }
 
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        (0, self.0.size_hint().1)
     }
 }
 
-impl<K> ExactSizeIterator for Iter<'_, K>
+impl<K, B: SetStorage> FusedIterator for IntoIter<K, B>
 where
+    B::IntoIter: FusedIterator,
     K: TryFrom<u32>,
+{
+}
+
+pub struct IntoIterExact<K>(hash_set::IntoIter<u32>, PhantomData<K>);
+
+impl<K> Iterator for IntoIterExact<K>
+where
+    K: From<u32>,
+{
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(K::from)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K> ExactSizeIterator for IntoIterExact<K>
+where
+    K: From<u32>,
 {
     #[inline]
     fn len(&self) -> usize {
@@ -206,75 +886,102 @@ where
     }
 }
 
+impl<K> FusedIterator for IntoIterExact<K> where K: From<u32> {}
+
+pub struct Iter<'a, K, B: SetStorage = HashBackend>(B::Iter<'a>, PhantomData<K>);
+
+impl<K, B: SetStorage> Iterator for Iter<'_, K, B>
+where
+    K: TryFrom<u32>,
+{
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.find_map(try_key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.0.size_hint().1)
+    }
+}
+
 // 2. IntSet <op>= &IntSet
-impl<K> BitAndAssign<&IntSet<K>> for IntSet<K> {
+impl<K, B: SetStorage> BitAndAssign<&IntSet<K, B>> for IntSet<K, B> {
     #[inline]
-    fn bitand_assign(&mut self, rhs: &IntSet<K>) {
-        self.0.retain(|k| rhs.0.contains(k));
+    fn bitand_assign(&mut self, rhs: &IntSet<K, B>) {
+        B::retain(&mut self.0, |k| B::contains(&rhs.0, k));
     }
 }
 
-impl<K> BitOrAssign<&IntSet<K>> for IntSet<K> {
+impl<K, B: SetStorage> BitOrAssign<&IntSet<K, B>> for IntSet<K, B> {
     #[inline]
-    fn bitor_assign(&mut self, rhs: &IntSet<K>) {
-        self.0.extend(rhs.0.iter().copied());
+    fn bitor_assign(&mut self, rhs: &IntSet<K, B>) {
+        for k in B::iter(&rhs.0) {
+            B::insert(&mut self.0, k);
+        }
     }
 }
 
-impl<K> SubAssign<&IntSet<K>> for IntSet<K> {
+impl<K, B: SetStorage> SubAssign<&IntSet<K, B>> for IntSet<K, B> {
     #[inline]
-    fn sub_assign(&mut self, rhs: &IntSet<K>) {
-        self.0.retain(|k| !rhs.0.contains(k));
+    fn sub_assign(&mut self, rhs: &IntSet<K, B>) {
+        B::retain(&mut self.0, |k| !B::contains(&rhs.0, k));
     }
 }
 
 macro_rules! op {
     ($trait:ident, $method:ident, $trait_assign:ident, $method_assign:ident) => {
         // 1. &IntSet & &IntSet  -> IntSet
-        impl<K> $trait<&IntSet<K>> for &IntSet<K> {
-            type Output = IntSet<K>;
+        impl<K, B: SetStorage> $trait<&IntSet<K, B>> for &IntSet<K, B> {
+            type Output = IntSet<K, B>;
 
             #[inline]
-            fn $method(self, rhs: &IntSet<K>) -> IntSet<K> {
-                IntSet((&self.0).$method(&rhs.0), PhantomData)
+            fn $method(self, rhs: &IntSet<K, B>) -> IntSet<K, B> {
+                let mut out = self.clone();
+                out.$method_assign(rhs);
+                out
             }
         }
 
         // 3. IntSet <op>= IntSet   (delegates to &)
-        impl<K> $trait_assign<IntSet<K>> for IntSet<K> {
+        impl<K, B: SetStorage> $trait_assign<IntSet<K, B>> for IntSet<K, B> {
             #[inline]
-            fn $method_assign(&mut self, rhs: IntSet<K>) {
+            fn $method_assign(&mut self, rhs: IntSet<K, B>) {
                 self.$method_assign(&rhs);
             }
         }
 
         // 4. IntSet <op> IntSet   (delegates to &)
-        impl<K> $trait<IntSet<K>> for IntSet<K> {
-            type Output = IntSet<K>;
+        impl<K, B: SetStorage> $trait<IntSet<K, B>> for IntSet<K, B> {
+            type Output = IntSet<K, B>;
 
             #[inline]
-            fn $method(self, rhs: IntSet<K>) -> IntSet<K> {
-                (&self).$method(&rhs)
+            fn $method(mut self, rhs: IntSet<K, B>) -> IntSet<K, B> {
+                self.$method_assign(&rhs);
+                self
             }
         }
 
         // 5. &IntSet <op> IntSet   (delegates to &)
-        impl<K> $trait<IntSet<K>> for &IntSet<K> {
-            type Output = IntSet<K>;
+        impl<K, B: SetStorage> $trait<IntSet<K, B>> for &IntSet<K, B> {
+            type Output = IntSet<K, B>;
 
             #[inline]
-            fn $method(self, rhs: IntSet<K>) -> IntSet<K> {
+            fn $method(self, rhs: IntSet<K, B>) -> IntSet<K, B> {
                 self.$method(&rhs)
             }
         }
 
         // 6. IntSet <op> &IntSet   (delegates to &)
-        impl<K> $trait<&IntSet<K>> for IntSet<K> {
-            type Output = IntSet<K>;
+        impl<K, B: SetStorage> $trait<&IntSet<K, B>> for IntSet<K, B> {
+            type Output = IntSet<K, B>;
 
             #[inline]
-            fn $method(self, rhs: &IntSet<K>) -> IntSet<K> {
-                (&self).$method(rhs)
+            fn $method(mut self, rhs: &IntSet<K, B>) -> IntSet<K, B> {
+                self.$method_assign(rhs);
+                self
             }
         }
     };
@@ -283,3 +990,355 @@ macro_rules! op {
 op!(BitAnd, bitand, BitAndAssign, bitand_assign);
 op!(BitOr, bitor, BitOrAssign, bitor_assign);
 op!(Sub, sub, SubAssign, sub_assign);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_slice_and_range_construction() {
+        let mut set = IntSet::<u32>::from_sorted_slice(&[1, 2, 2, 5, 9]);
+        assert_eq!(set.len(), 4);
+
+        assert_eq!(set.insert_range(4..=6), 2);
+        assert_eq!(set.insert_range(10..10), 0);
+        assert_eq!(set.insert_range(u32::MAX..), 1);
+        assert_eq!(
+            set.iter_sorted().collect::<Vec<_>>(),
+            vec![1, 2, 4, 5, 6, 9, u32::MAX]
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_union_and_intersection() {
+        let a = IntSet::<u32>::from_iter([1, 2, 3, 4]);
+        let b = IntSet::<u32>::from_iter([2, 3, 4, 5]);
+        let c = IntSet::<u32>::from_iter([3, 4, 6]);
+
+        assert_eq!(
+            union_many(&[&a, &b, &c]).iter_sorted().collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(
+            intersect_many(&[&a, &b, &c])
+                .iter_sorted()
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        assert!(intersect_many::<u32>(&[]).is_empty());
+        assert_eq!(a.par_iter().sum::<u32>(), 10);
+    }
+
+    #[test]
+    fn lazy_ops_match_operators() {
+        let a = IntSet::<u32>::from_iter([1, 2, 3]);
+        let b = IntSet::<u32>::from_iter([2, 3, 4]);
+
+        assert_eq!(a.intersection(&b).collect::<IntSet<u32>>(), &a & &b);
+        assert_eq!(a.union(&b).collect::<IntSet<u32>>(), &a | &b);
+        assert_eq!(a.difference(&b).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn reserve_and_shrink() {
+        let mut set = IntSet::<u32>::new();
+        set.reserve(1000);
+        assert!(set.capacity() >= 1000);
+
+        set.insert(1);
+        set.shrink_to_fit();
+        assert!(set.capacity() < 1000);
+    }
+
+    #[test]
+    fn contains_all_and_any() {
+        let set = IntSet::<u32>::from_iter([1, 2, 3]);
+
+        assert!(set.contains_all([1, 3]));
+        assert!(!set.contains_all([1, 4]));
+        assert!(set.contains_all([]));
+        assert!(set.contains_any([4, 2]));
+        assert!(!set.contains_any([]));
+    }
+
+    #[test]
+    fn cast_drops_unconvertible_values() {
+        let set = IntSet::<u32>::from_iter([1, 300]);
+        let cast = set.clone().cast::<u8>();
+
+        assert_eq!(cast.iter().collect::<Vec<_>>(), vec![1u8]);
+        assert_eq!(unsafe { set.as_cast_ref::<u32>() }, &set);
+    }
+
+    #[test]
+    fn try_iter_reports_invalid_keys() {
+        let set = IntSet::<u32>::from_iter([300]);
+        let set = unsafe { set.as_cast_ref::<u8>() };
+
+        assert_eq!(
+            set.try_iter().collect::<Vec<_>>(),
+            vec![Err(InvalidKey(300))]
+        );
+        assert_eq!(set.iter().count(), 0);
+    }
+
+    #[test]
+    fn into_iter_skips_invalid_keys() {
+        let mut set = IntSet::<u32>::from_iter(0..100);
+        set.insert_range(300..400);
+
+        let iter = unsafe { set.as_cast_ref::<u8>() }.clone().into_iter();
+        assert_eq!(iter.size_hint(), (0, Some(200)));
+
+        let mut keys = iter.collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn intersect_all_smallest_first() {
+        let a = IntSet::<u32>::from_iter([1, 2, 3, 4]);
+        let b = IntSet::<u32>::from_iter([2, 4]);
+        let c = IntSet::<u32>::from_iter([4, 2, 9]);
+
+        assert_eq!(intersect_all([&a, &b, &c]), b);
+        assert!(intersect_all([&a, &IntSet::new()]).is_empty());
+        assert!(intersect_all::<u32>([]).is_empty());
+    }
+
+    #[test]
+    fn union_all_reuses_output() {
+        let a = IntSet::<u32>::from_iter([1, 2]);
+        let b = IntSet::<u32>::from_iter([2, 3]);
+
+        assert_eq!(union_all([&a, &b]), &a | &b);
+
+        let mut out = IntSet::from_iter([9]);
+        union_all_into(&mut out, [&a]);
+        assert_eq!(out, IntSet::from_iter([1, 2, 9]));
+    }
+
+    #[test]
+    fn stats_report_shape() {
+        let stats = IntSet::<u32>::from_iter(0..8).stats();
+
+        assert_eq!((stats.count, stats.min, stats.max), (8, Some(0), Some(7)));
+        assert_eq!(stats.density(), 1.0);
+        assert_eq!(stats.histogram.iter().sum::<usize>(), 8);
+        assert_eq!(stats.histogram[0], 1);
+
+        let stats = IntSet::<u32>::from_iter([0, u32::MAX]).stats();
+        assert_eq!(stats.histogram[IntSetStats::BUCKETS - 1], 1);
+        assert_eq!(IntSet::<u32>::new().stats().density(), 0.0);
+    }
+
+    #[test]
+    fn std_collection_conversions() {
+        let set = IntSet::<u32>::from(BTreeSet::from([3, 1, 2]));
+
+        assert_eq!(set, IntSet::from(HashSet::from([1, 2, 3])));
+        assert_eq!(Vec::from(set.clone()), vec![1, 2, 3]);
+        assert!(set == &[3, 2, 1, 1][..]);
+        assert!(set != &[1, 1, 2][..]);
+    }
+
+    #[test]
+    fn insert_checked_respects_max_id() {
+        use crate::one_index::OneIndexBuilder;
+
+        let mut set = IntSet::<u32>::new();
+        let out = KeyOutOfRange {
+            key: u32::MAX,
+            max: u32::MAX - 1,
+        };
+
+        assert_eq!(set.insert_checked(u32::MAX), Err(out));
+        assert_eq!(set.insert_checked(u32::MAX - 1), Ok(true));
+        assert_eq!(check_key(11, 10), Err(KeyOutOfRange { key: 11, max: 10 }));
+        assert_eq!(check_key(10, 10), Ok(10));
+
+        let mut builder = OneIndexBuilder::<u32, u32>::with_max_id(100_000);
+        let out = KeyOutOfRange {
+            key: 100_001,
+            max: 100_000,
+        };
+
+        assert_eq!(builder.try_insert(100_001, 1), Err(out));
+        assert_eq!(builder.try_insert(100_000, 1), Ok(()));
+        builder.insert(100_001, 2);
+        assert_eq!(builder.build().get(100_001), Some(&2));
+    }
+
+    #[test]
+    fn subset_superset_and_disjoint() {
+        let all = IntSet::<u32>::from_iter(0..10);
+        let low = IntSet::<u32>::from_iter(0..5);
+        let high = IntSet::<u32>::from_iter(5..10);
+        let empty = IntSet::<u32>::new();
+
+        assert!(low.is_subset(&all));
+        assert!(!all.is_subset(&low));
+        assert!(all.is_superset(&high));
+        assert!(!high.is_superset(&all));
+        assert!(low.is_disjoint(&high));
+        assert!(!low.is_disjoint(&all));
+
+        assert!(empty.is_subset(&low));
+        assert!(empty.is_disjoint(&empty));
+        assert!(all.is_subset(&all) && all.is_superset(&all));
+    }
+
+    #[test]
+    fn set_op_lengths_match_counts() {
+        let a = IntSet::<u32>::from_iter(0..50);
+        let b = IntSet::<u32>::from_iter((25..100).step_by(5));
+        let empty = IntSet::<u32>::new();
+        let disjoint = IntSet::<u32>::from_iter(500..510);
+
+        for (x, y) in [
+            (&a, &b),
+            (&b, &a),
+            (&a, &empty),
+            (&empty, &a),
+            (&a, &disjoint),
+        ] {
+            let shared = x.iter().filter(|k| y.contains(*k)).count();
+
+            assert_eq!(x.intersection_len(y), shared);
+            assert_eq!(x.union_len(y), x.len() + y.len() - shared);
+            assert_eq!(x.difference_len(y), x.len() - shared);
+        }
+
+        assert_eq!(a.intersection_len(&disjoint), 0);
+        assert_eq!(a.union_len(&disjoint), 60);
+        assert_eq!(empty.difference_len(&a), 0);
+    }
+
+    #[test]
+    fn retain_keeps_matching_keys() {
+        let mut set = IntSet::<u32>::from_iter(0..20);
+        set.retain(|k| k % 3 == 0);
+        assert_eq!(
+            set.iter_sorted().collect::<Vec<_>>(),
+            vec![0, 3, 6, 9, 12, 15, 18]
+        );
+
+        set.retain(|_| false);
+        assert!(set.is_empty());
+
+        let mut narrow = IntSet::<u8>::from_iter([1, 2]);
+        narrow.as_set_mut().insert(300);
+        narrow.retain(|k| k != 1);
+
+        assert_eq!(narrow.len(), 2);
+        assert!(narrow.as_set().contains(&300));
+        assert_eq!(narrow.iter_sorted().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn drain_empties_the_set() {
+        let mut set = IntSet::<u32>::from_iter(0..100);
+
+        let mut keys = set.drain().collect::<Vec<_>>();
+        keys.sort_unstable();
+
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+        assert!(set.is_empty());
+
+        let mut set = IntSet::<u32>::from_iter(0..10);
+        assert_eq!(set.drain().take(3).count(), 3);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn hash_eq_extend_and_debug() {
+        use std::hash::{BuildHasher, RandomState};
+
+        let a = IntSet::<u32>::from_iter(0..200);
+        let mut b = IntSet::<u32>::from_iter((100..200).rev());
+        b.extend((0..100).rev());
+
+        let state = RandomState::new();
+        assert_eq!(a, b);
+        assert_eq!(state.hash_one(&a), state.hash_one(&b));
+
+        b.remove(7);
+        assert_ne!(a, b);
+        assert_ne!(state.hash_one(&a), state.hash_one(&b));
+
+        let set = IntSet::<u32>::from_iter([30, 1, 200, 4]);
+        assert_eq!(format!("{set:?}"), "{1, 4, 30, 200}");
+        assert_eq!(format!("{:?}", IntSet::<u32>::new()), "{}");
+    }
+
+    #[test]
+    fn contains_many_keeps_order() {
+        let set = IntSet::<u32>::from_iter([2, 4, 8]);
+
+        assert_eq!(
+            set.contains_many(&[8, 1, 2, 2, 3]),
+            vec![true, false, true, true, false]
+        );
+        assert!(set.contains_many(&[]).is_empty());
+    }
+
+    #[test]
+    fn as_set_mut_edits_raw_values() {
+        let mut set = IntSet::<u8>::from_iter([1, 2]);
+        set.as_set_mut().insert(3);
+        set.as_set_mut().insert(300);
+        set.as_set_mut().remove(&1);
+
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(3));
+        assert_eq!(set.iter_sorted().collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn iter_skips_unconvertible_values() {
+        let mut set = IntSet::<u8>::from_iter([1]);
+        set.as_set_mut().insert(300);
+        set.as_set_mut().insert(2);
+
+        let iter = set.iter();
+        assert_eq!(iter.size_hint(), (0, Some(3)));
+
+        let mut keys = iter.collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1, 2]);
+    }
+
+    #[test]
+    fn small_backend_shares_generic_methods() {
+        type Small = IntSet<u32, crate::SmallBackend<2>>;
+
+        let a = Small::from_iter([5, 1, 3]);
+        let b = Small::from_iter([3, 4]);
+
+        assert_eq!(a.range(2..=5).collect::<Vec<_>>(), vec![3, 5]);
+        assert_eq!(a.smallest(2), vec![1, 3]);
+        assert_eq!(a.intersection(&b).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(a.union(&b).collect::<Small>(), &a | &b);
+        assert_eq!(a.difference(&b).collect::<Small>(), &a - &b);
+        assert_eq!(a.clone().cast::<u8>().len(), 3);
+
+        let mut keys = a.clone().into_iter().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1, 3, 5]);
+
+        let mut drained = b.clone();
+        assert_eq!(drained.drain().count(), 2);
+        assert!(drained.is_empty());
+    }
+
+    #[test]
+    fn insert_range_beyond_the_reserve_chunk() {
+        let mut set = IntSet::<u32>::new();
+        let end = RANGE_RESERVE_CHUNK as u32 + 10;
+
+        assert_eq!(set.insert_range(..end), end as usize);
+        assert!(set.contains(end - 1));
+        assert!(!set.contains(end));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::strict::try_key;
+use rustc_hash::FxHashSet;
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
+
+macro_rules! int_set_width {
+    ($(#[$meta:meta])* $name:ident, $int:ty) => {
+        $(#[$meta])*
+        #[repr(transparent)]
+        pub struct $name<K>(FxHashSet<$int>, PhantomData<K>);
+
+        impl<K> $name<K> {
+            #[inline]
+            pub fn new() -> Self {
+                Self(FxHashSet::default(), PhantomData)
+            }
+
+            #[inline]
+            pub fn as_set(&self) -> &FxHashSet<$int> {
+                &self.0
+            }
+
+            #[inline]
+            pub fn clear(&mut self) {
+                self.0.clear();
+            }
+
+            #[inline]
+            pub fn contains(&self, key: K) -> bool
+            where
+                K: Into<$int>,
+            {
+                self.0.contains(&key.into())
+            }
+
+            #[inline]
+            pub fn insert(&mut self, key: K) -> bool
+            where
+                K: Into<$int>,
+            {
+                self.0.insert(key.into())
+            }
+
+            #[inline]
+            pub fn is_empty(&self) -> bool {
+                self.0.is_empty()
+            }
+
+            #[inline]
+            pub fn iter(&self) -> impl Iterator<Item = K> + '_
+            where
+                K: TryFrom<$int>,
+            {
+                self.0.iter().filter_map(|v| try_key(*v))
+            }
+
+            #[inline]
+            pub fn len(&self) -> usize {
+                self.0.len()
+            }
+
+            #[inline]
+            pub fn remove(&mut self, key: K) -> bool
+            where
+                K: Into<$int>,
+            {
+                self.0.remove(&key.into())
+            }
+        }
+
+        impl<K> Clone for $name<K> {
+            #[inline]
+            fn clone(&self) -> Self {
+                Self(self.0.clone(), PhantomData)
+            }
+        }
+
+        impl<K> Debug for $name<K> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut v = self.0.iter().copied().collect::<Vec<_>>();
+                v.sort_unstable();
+                f.debug_set().entries(v).finish()
+            }
+        }
+
+        impl<K> Default for $name<K> {
+            #[inline]
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<K> Eq for $name<K> {}
+
+        impl<K> Extend<K> for $name<K>
+        where
+            K: Into<$int>,
+        {
+            #[inline]
+            fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+                self.0.extend(iter.into_iter().map(Into::into))
+            }
+        }
+
+        impl<K> FromIterator<K> for $name<K>
+        where
+            K: Into<$int>,
+        {
+            #[inline]
+            fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+                Self(iter.into_iter().map(Into::into).collect(), PhantomData)
+            }
+        }
+
+        impl<K> PartialEq for $name<K> {
+            #[inline]
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+    };
+}
+
+int_set_width!(
+    /// [`IntSet`](crate::IntSet) for keys that need the full `u64` range.
+    IntSet64,
+    u64
+);
+
+int_set_width!(
+    /// [`IntSet`](crate::IntSet) for keys that fit in a `u16`, at half the
+    /// per-entry footprint.
+    IntSet16,
+    u16
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths_round_trip_keys() {
+        let mut set = IntSet64::<u64>::from_iter([u64::MAX, 1]);
+        assert!(set.insert(u32::MAX as u64 + 1));
+        assert!(set.contains(u64::MAX));
+        assert_eq!(format!("{set:?}"), "{1, 4294967296, 18446744073709551615}");
+
+        let mut set = IntSet16::<u16>::new();
+        set.extend([3, 3, 9]);
+        assert_eq!(set.len(), 2);
+        assert!(set.remove(3));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![9]);
+
+        let mut set = IntSet64::<u32>::from_iter([7u32]);
+        set.0.insert(u64::MAX);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![7]);
+    }
+}
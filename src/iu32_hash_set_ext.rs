@@ -0,0 +1,312 @@
+use crate::U32Set;
+use intern::IU32HashSet;
+use once_cell::sync::Lazy;
+use rustc_hash::FxHashMap;
+use std::{
+    cmp::Ordering,
+    sync::{Mutex, PoisonError},
+};
+
+/// Entries kept before the operation cache starts over.
+const OP_CACHE_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Op {
+    Difference,
+    Intersection,
+    Subset,
+    Union,
+}
+
+#[derive(Clone)]
+enum OpResult {
+    Set(IU32HashSet),
+    Subset(bool),
+}
+
+/// The operands are kept alive by the entry, so their addresses cannot be
+/// reused by other sets while cached.
+struct OpEntry {
+    _operands: (IU32HashSet, IU32HashSet),
+    result: OpResult,
+}
+
+type OpCache = FxHashMap<(Op, usize, usize), OpEntry>;
+
+static OP_CACHE: Lazy<Mutex<OpCache>> = Lazy::new(Default::default);
+
+/// Helpers on interned [`IU32HashSet`] handles.
+pub trait IU32HashSetExt {
+    fn contains(&self, value: u32) -> bool;
+
+    /// Lexicographic comparison of the sorted values, for deterministic
+    /// ordering in snapshot and export code.
+    fn cmp_sorted(&self, other: &IU32HashSet) -> Ordering;
+
+    /// `self - other`, memoized on the identity of both handles.
+    fn difference_interned(&self, other: &IU32HashSet) -> IU32HashSet;
+
+    /// Interns the values of `bitmap`.
+    #[cfg(feature = "roaring")]
+    fn from_roaring(bitmap: &roaring::RoaringBitmap) -> Self
+    where
+        Self: Sized;
+
+    /// `self & other`, memoized on the identity of both handles.
+    fn intersection_interned(&self, other: &IU32HashSet) -> IU32HashSet;
+
+    /// Copies the values into a bitmap, in a single sorted pass.
+    #[cfg(feature = "roaring")]
+    fn to_roaring(&self) -> roaring::RoaringBitmap;
+
+    /// Whether every value of `self` is in `other`, memoized on the identity
+    /// of both handles.
+    fn is_subset_of(&self, other: &IU32HashSet) -> bool;
+
+    fn is_empty(&self) -> bool;
+
+    /// Values in arbitrary order.
+    fn iter(&self) -> impl Iterator<Item = u32> + '_;
+
+    fn len(&self) -> usize;
+
+    /// Whether both handles point to the same interned set.
+    fn ptr_eq(&self, other: &IU32HashSet) -> bool;
+
+    /// Address-based identity, stable while any handle to the set is alive.
+    fn ptr_id(&self) -> usize;
+
+    /// `self | other`, memoized on the identity of both handles.
+    fn union_interned(&self, other: &IU32HashSet) -> IU32HashSet;
+}
+
+impl IU32HashSetExt for IU32HashSet {
+    #[inline]
+    fn contains(&self, value: u32) -> bool {
+        self.as_set().contains(&value)
+    }
+
+    fn cmp_sorted(&self, other: &IU32HashSet) -> Ordering {
+        use crate::U32SetExt;
+
+        if self.ptr_eq(other) {
+            return Ordering::Equal;
+        }
+
+        self.as_set().sorted().cmp(&other.as_set().sorted())
+    }
+
+    fn difference_interned(&self, other: &IU32HashSet) -> IU32HashSet {
+        cached_set(Op::Difference, self, other, |a, b| {
+            a.difference(b).copied().collect()
+        })
+    }
+
+    #[cfg(feature = "roaring")]
+    fn from_roaring(bitmap: &roaring::RoaringBitmap) -> Self {
+        bitmap.iter().collect::<U32Set>().into()
+    }
+
+    fn intersection_interned(&self, other: &IU32HashSet) -> IU32HashSet {
+        // commutative, so both orders share one entry
+        let (a, b) = ordered(self, other);
+
+        cached_set(Op::Intersection, a, b, |a, b| {
+            a.intersection(b).copied().collect()
+        })
+    }
+
+    fn is_subset_of(&self, other: &IU32HashSet) -> bool {
+        if self.ptr_eq(other) {
+            return true;
+        }
+
+        let result = cached_op(Op::Subset, self, other, |a, b| {
+            OpResult::Subset(a.is_subset(b))
+        });
+
+        matches!(result, OpResult::Subset(true))
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.as_set().is_empty()
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.as_set().iter().copied()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.as_set().len()
+    }
+
+    #[inline]
+    fn ptr_eq(&self, other: &IU32HashSet) -> bool {
+        std::ptr::eq(self.as_set(), other.as_set())
+    }
+
+    #[inline]
+    fn ptr_id(&self) -> usize {
+        self.as_set() as *const U32Set as usize
+    }
+
+    #[cfg(feature = "roaring")]
+    fn to_roaring(&self) -> roaring::RoaringBitmap {
+        use crate::U32SetExt;
+
+        roaring::RoaringBitmap::from_sorted_iter(self.as_set().sorted()).expect("sorted values")
+    }
+
+    fn union_interned(&self, other: &IU32HashSet) -> IU32HashSet {
+        let (a, b) = ordered(self, other);
+        cached_set(Op::Union, a, b, |a, b| a.union(b).copied().collect())
+    }
+}
+
+fn cached_op(
+    op: Op,
+    a: &IU32HashSet,
+    b: &IU32HashSet,
+    f: impl FnOnce(&U32Set, &U32Set) -> OpResult,
+) -> OpResult {
+    let key = (op, a.ptr_id(), b.ptr_id());
+
+    if let Some(e) = OP_CACHE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&key)
+    {
+        return e.result.clone();
+    }
+
+    let result = f(a.as_set(), b.as_set());
+    let mut cache = OP_CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if cache.len() >= OP_CACHE_CAPACITY {
+        cache.clear();
+    }
+
+    cache.insert(
+        key,
+        OpEntry {
+            _operands: (a.clone(), b.clone()),
+            result: result.clone(),
+        },
+    );
+
+    result
+}
+
+fn cached_set(
+    op: Op,
+    a: &IU32HashSet,
+    b: &IU32HashSet,
+    f: impl FnOnce(&U32Set, &U32Set) -> U32Set,
+) -> IU32HashSet {
+    match cached_op(op, a, b, |a, b| OpResult::Set(f(a, b).into())) {
+        OpResult::Set(set) => set,
+        OpResult::Subset(_) => unreachable!("set operation cached as subset"),
+    }
+}
+
+/// Drops every memoized operation, releasing the sets it pins.
+pub fn clear_op_cache() {
+    OP_CACHE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+}
+
+fn ordered<'a>(a: &'a IU32HashSet, b: &'a IU32HashSet) -> (&'a IU32HashSet, &'a IU32HashSet) {
+    if a.ptr_id() <= b.ptr_id() {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::U32SetExt;
+
+    #[test]
+    fn memoized_ops_match_plain_ones() {
+        let a = IU32HashSet::from(U32Set::from_iter([1, 2, 3]));
+        let b = IU32HashSet::from(U32Set::from_iter([2, 3, 4]));
+
+        assert_eq!(
+            *a.union_interned(&b).as_set(),
+            U32Set::from_iter([1, 2, 3, 4])
+        );
+        assert_eq!(
+            *b.union_interned(&a).as_set(),
+            U32Set::from_iter([1, 2, 3, 4])
+        );
+        assert_eq!(
+            *a.intersection_interned(&b).as_set(),
+            U32Set::from_iter([2, 3])
+        );
+        assert_eq!(*a.difference_interned(&b).as_set(), U32Set::from_iter([1]));
+        assert_eq!(*b.difference_interned(&a).as_set(), U32Set::from_iter([4]));
+
+        let first = a.union_interned(&b);
+        let second = b.union_interned(&a);
+        assert!(first.ptr_eq(&second));
+        assert_eq!(first.ptr_id(), second.ptr_id());
+        assert!(!first.ptr_eq(&a));
+
+        clear_op_cache();
+        assert_eq!(
+            *a.union_interned(&b).as_set(),
+            U32Set::from_iter([1, 2, 3, 4])
+        );
+    }
+
+    #[cfg(feature = "roaring")]
+    #[test]
+    fn roaring_round_trip() {
+        let set = IU32HashSet::from(U32Set::from_iter([7, 1, 300_000]));
+        let bitmap = set.to_roaring();
+
+        assert_eq!(bitmap.iter().collect::<Vec<_>>(), vec![1, 7, 300_000]);
+        assert_eq!(*IU32HashSet::from_roaring(&bitmap).as_set(), *set.as_set());
+    }
+
+    #[test]
+    fn cmp_sorted_is_lexicographic() {
+        let mut sets = [vec![2, 1], vec![1], vec![1, 3], vec![]]
+            .map(|v| IU32HashSet::from(U32Set::from_iter(v)));
+
+        sets.sort_by(|a, b| a.cmp_sorted(b));
+
+        let sorted = sets.iter().map(|s| s.as_set().sorted()).collect::<Vec<_>>();
+        assert_eq!(sorted, vec![vec![], vec![1], vec![1, 2], vec![1, 3]]);
+    }
+
+    #[test]
+    fn is_subset_of_is_memoized_per_pair() {
+        let small = IU32HashSet::from(U32Set::from_iter([1, 2]));
+        let large = IU32HashSet::from(U32Set::from_iter([1, 2, 3]));
+
+        assert!(small.is_subset_of(&large));
+        assert!(small.is_subset_of(&large));
+        assert!(!large.is_subset_of(&small));
+        assert!(large.is_subset_of(&large));
+    }
+
+    #[test]
+    fn read_accessors() {
+        let set = IU32HashSet::from(U32Set::from_iter([4, 2]));
+
+        assert_eq!(set.len(), 2);
+        assert!(!set.is_empty());
+        assert!(set.contains(4));
+        assert!(!set.contains(3));
+        assert_eq!(set.iter().sum::<u32>(), 6);
+        assert!(IU32HashSet::default().is_empty());
+    }
+}
@@ -1,22 +1,67 @@
+#[cfg(feature = "rkyv")]
+pub mod archive;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod change_bus;
+pub mod codec;
+pub mod fast_set_trx;
 pub mod flat_set_index;
+pub mod frozen_int_set;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
 pub mod hash_flat_set_index;
 pub mod int_set;
+pub mod int_set_width;
+pub mod iu32_hash_set_ext;
+pub mod node_count_index;
+pub mod node_set_index;
 pub mod one_index;
+pub mod set_storage;
+pub mod snapshot;
+pub mod str_pool;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+pub mod strict;
 pub mod tree;
+pub mod u32_set_ext;
 pub mod u32based;
+pub mod versioned_index;
+pub mod wal;
 
+pub use change_bus::{Change, ChangeBus, ChangeEvent, Observe, SubscriptionId};
+pub use fast_set_trx::{Apply, FastSetTrx, Part, View};
 pub use flat_set_index::{FlatSetIndex, FlatSetIndexBuilder, FlatSetIndexLog};
+pub use frozen_int_set::FrozenIntSet;
 pub use hash_flat_set_index::{
     HashFlatSetIndex, HashFlatSetIndexBuilder, HashFlatSetIndexLog, HashFlatSetIndexTrx,
 };
-pub use int_set::IntSet;
+pub use int_set::{IntSet, IntSetStats, intersect_all, union_all, union_all_into};
+#[cfg(feature = "rayon")]
+pub use int_set::{intersect_many, union_many};
+pub use int_set_width::{IntSet16, IntSet64};
 use intern::U32HashSet;
+pub use iu32_hash_set_ext::{IU32HashSetExt, clear_op_cache};
+pub use node_count_index::{NodeCountIndex, NodeCountIndexLog};
+pub use node_set_index::{
+    HierarchyTrx, NodeSetIndex, NodeSetIndexBuilder, NodeSetIndexLog, NodeSetIndexReport,
+    NodeSetIndexTrx,
+};
 use once_cell::sync::OnceCell;
+#[cfg(feature = "roaring")]
+pub use set_storage::RoaringBackend;
+pub use set_storage::{HashBackend, SetStorage, SmallBackend};
+pub use snapshot::{Snapshot, SnapshotError, SnapshotWriter};
+pub use str_pool::{StrPool, str_pool};
+pub use strict::{InvalidKey, KeyOutOfRange, is_strict, max_id, set_max_id, set_strict};
 pub use tree::{Tree, TreeIndexLog};
+pub use u32_set_ext::U32SetExt;
+pub use versioned_index::{Version, VersionedIndex};
+pub use wal::{WalLog, replay};
 
 pub type U32Set = rustc_hash::FxHashSet<u32>;
 
-#[doc(hidden)]
+/// Interned, immutable [`U32Set`] shared between equal sets. Read it through
+/// [`as_set`](IU32HashSet::as_set) or [`IU32HashSetExt`], build it with `From<U32Set>`.
 pub use intern::IU32HashSet;
 
 fn empty_roaring() -> &'static U32HashSet {
@@ -0,0 +1,212 @@
+use crate::{IntSet, Tree, TreeIndexLog, strict::try_key, u32based};
+use std::marker::PhantomData;
+
+/// Items `I` attached to the nodes `N` of a [`Tree`], with per-subtree
+/// multiplicity. See [`u32based::NodeCountIndex`].
+#[repr(transparent)]
+pub struct NodeCountIndex<N, I> {
+    erased: u32based::NodeCountIndex,
+    _ni: PhantomData<(N, I)>,
+}
+
+impl<N, I> NodeCountIndex<N, I> {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn apply(&mut self, log: NodeCountIndexLog<N, I>) -> bool {
+        self.erased.apply(log.erased)
+    }
+
+    #[inline]
+    pub fn count(&self, node: N, item: I) -> u32
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.count(node.into(), item.into())
+    }
+
+    #[inline]
+    pub fn direct_items(&self, node: N) -> &IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe { IntSet::from_u32set_ref(self.erased.direct_items(node.into())) }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.erased.is_empty()
+    }
+
+    #[inline]
+    pub fn subtree_contains(&self, node: N, item: I) -> bool
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.subtree_contains(node.into(), item.into())
+    }
+
+    #[inline]
+    pub fn subtree_items(&self, node: N) -> impl Iterator<Item = I> + '_
+    where
+        N: Into<u32>,
+        I: TryFrom<u32>,
+    {
+        self.erased.subtree_items(node.into()).filter_map(try_key)
+    }
+}
+
+impl<N, I> Clone for NodeCountIndex<N, I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            erased: self.erased.clone(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+impl<N, I> Default for NodeCountIndex<N, I> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            erased: Default::default(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+#[repr(transparent)]
+pub struct NodeCountIndexLog<N, I> {
+    erased: u32based::NodeCountIndexLog,
+    _ni: PhantomData<(N, I)>,
+}
+
+impl<N, I> NodeCountIndexLog<N, I> {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn count(&self, base: &NodeCountIndex<N, I>, node: N, item: I) -> u32
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.count(&base.erased, node.into(), item.into())
+    }
+
+    #[inline]
+    pub fn direct_items<'a>(&'a self, base: &'a NodeCountIndex<N, I>, node: N) -> &'a IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe { IntSet::from_u32set_ref(self.erased.direct_items(&base.erased, node.into())) }
+    }
+
+    #[inline]
+    pub fn insert(
+        &mut self,
+        base: &NodeCountIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+        item: I,
+    ) where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.insert(
+            &base.erased,
+            &tree.erased,
+            &tree_log.erased,
+            node.into(),
+            item.into(),
+        );
+    }
+
+    #[inline]
+    pub fn insert_subtree(
+        &mut self,
+        base: &NodeCountIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+    ) where
+        N: Into<u32>,
+    {
+        self.erased
+            .insert_subtree(&base.erased, &tree.erased, &tree_log.erased, node.into());
+    }
+
+    #[inline]
+    pub fn remove(
+        &mut self,
+        base: &NodeCountIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+        item: I,
+    ) where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.remove(
+            &base.erased,
+            &tree.erased,
+            &tree_log.erased,
+            node.into(),
+            item.into(),
+        );
+    }
+
+    #[inline]
+    pub fn remove_subtree(
+        &mut self,
+        base: &NodeCountIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+    ) where
+        N: Into<u32>,
+    {
+        self.erased
+            .remove_subtree(&base.erased, &tree.erased, &tree_log.erased, node.into());
+    }
+
+    #[inline]
+    pub fn subtree_contains(&self, base: &NodeCountIndex<N, I>, node: N, item: I) -> bool
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased
+            .subtree_contains(&base.erased, node.into(), item.into())
+    }
+}
+
+impl<N, I> Clone for NodeCountIndexLog<N, I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            erased: self.erased.clone(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+impl<N, I> Default for NodeCountIndexLog<N, I> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            erased: Default::default(),
+            _ni: PhantomData,
+        }
+    }
+}
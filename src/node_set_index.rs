@@ -0,0 +1,778 @@
+use crate::{
+    IntSet, Tree, TreeIndexLog, codec::DecodeError, strict::try_key, tree::TreeTrx, u32based,
+};
+
+use std::marker::PhantomData;
+pub use u32based::node_set_index::InvariantViolation;
+
+/// Items `I` attached to the nodes `N` of a [`Tree`], aggregated per subtree.
+#[repr(transparent)]
+pub struct NodeSetIndex<N, I> {
+    pub(crate) erased: u32based::NodeSetIndex,
+    _ni: PhantomData<(N, I)>,
+}
+
+impl<N, I> NodeSetIndex<N, I> {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn all_items(&self) -> IntSet<I> {
+        unsafe { IntSet::from_set(self.erased.all_items()) }
+    }
+
+    #[inline]
+    pub fn apply(&mut self, log: NodeSetIndexLog<N, I>) -> bool {
+        self.erased.apply(log.erased)
+    }
+
+    #[inline]
+    pub fn apply_report(&mut self, log: NodeSetIndexLog<N, I>) -> NodeSetIndexReport<N> {
+        let report = self.erased.apply_report(log.erased);
+
+        NodeSetIndexReport {
+            direct: unsafe { IntSet::from_set(report.direct) },
+            subtree: unsafe { IntSet::from_set(report.subtree) },
+        }
+    }
+
+    #[inline]
+    pub fn direct_items(&self, node: N) -> &IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe { IntSet::from_u32set_ref(self.erased.direct_items(node.into())) }
+    }
+
+    /// See [`u32based::NodeSetIndex::enable_item_nodes`].
+    #[inline]
+    pub fn enable_item_nodes(&mut self) {
+        self.erased.enable_item_nodes();
+    }
+
+    #[inline]
+    pub fn has_item_nodes(&self) -> bool {
+        self.erased.has_item_nodes()
+    }
+
+    /// Items common to the subtrees of every one of `nodes`.
+    #[inline]
+    pub fn intersect_subtrees(&self, nodes: impl IntoIterator<Item = N>) -> IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe {
+            IntSet::from_set(
+                self.erased
+                    .intersect_subtrees(nodes.into_iter().map(Into::into)),
+            )
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.erased.is_empty()
+    }
+
+    #[inline]
+    pub fn iter_direct(&self) -> impl Iterator<Item = (N, &IntSet<I>)> + '_
+    where
+        N: TryFrom<u32>,
+    {
+        self.erased
+            .iter_direct()
+            .filter_map(|(n, s)| Some((try_key(n)?, unsafe { IntSet::from_u32set_ref(s) })))
+    }
+
+    #[inline]
+    pub fn iter_subtree(&self) -> impl Iterator<Item = (N, &IntSet<I>)> + '_
+    where
+        N: TryFrom<u32>,
+    {
+        self.erased
+            .iter_subtree()
+            .filter_map(|(n, s)| Some((try_key(n)?, unsafe { IntSet::from_u32set_ref(s) })))
+    }
+
+    #[inline]
+    pub fn nodes(&self) -> impl Iterator<Item = N> + '_
+    where
+        N: TryFrom<u32>,
+    {
+        self.erased.nodes().filter_map(try_key)
+    }
+
+    /// Nodes whose subtree items contain every one of `items`. Scans every
+    /// subtree aggregate.
+    #[inline]
+    pub fn nodes_containing_all(&self, items: &IntSet<I>) -> IntSet<N> {
+        unsafe { IntSet::from_set(self.erased.nodes_containing_all(items.as_set())) }
+    }
+
+    /// Nodes whose subtree items contain at least one of `items`. Scans every
+    /// subtree aggregate.
+    #[inline]
+    pub fn nodes_containing_any(&self, items: &IntSet<I>) -> IntSet<N> {
+        unsafe { IntSet::from_set(self.erased.nodes_containing_any(items.as_set())) }
+    }
+
+    #[inline]
+    pub fn nodes_with_item(&self, item: I) -> IntSet<N>
+    where
+        I: Into<u32>,
+    {
+        unsafe { IntSet::from_set(self.erased.nodes_with_item(item.into())) }
+    }
+
+    /// See [`u32based::NodeSetIndex::rebuild`].
+    #[inline]
+    pub fn rebuild(&mut self, tree: &Tree<N>) -> bool {
+        self.erased.rebuild(&tree.erased)
+    }
+
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_rebuild(&mut self, tree: &Tree<N>) -> bool {
+        self.erased.par_rebuild(&tree.erased)
+    }
+
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_rebuild_log(
+        &self,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+    ) -> NodeSetIndexLog<N, I> {
+        NodeSetIndexLog {
+            erased: self.erased.par_rebuild_log(&tree.erased, &tree_log.erased),
+            _ni: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn rebuild_log(&self, tree: &Tree<N>, tree_log: &TreeIndexLog<N>) -> NodeSetIndexLog<N, I> {
+        NodeSetIndexLog {
+            erased: self.erased.rebuild_log(&tree.erased, &tree_log.erased),
+            _ni: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn subtree_items(&self, node: N) -> &IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe { IntSet::from_u32set_ref(self.erased.subtree_items(node.into())) }
+    }
+
+    /// See [`u32based::NodeSetIndex::verify`].
+    #[inline]
+    pub fn verify(
+        &self,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        log: &NodeSetIndexLog<N, I>,
+    ) -> Result<(), Vec<InvariantViolation>> {
+        self.erased
+            .verify(&tree.erased, &tree_log.erased, &log.erased)
+    }
+}
+
+impl<N, I> Clone for NodeSetIndex<N, I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            erased: self.erased.clone(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+impl<N, I> Default for NodeSetIndex<N, I> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            erased: Default::default(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<N, I> serde::Serialize for NodeSetIndex<N, I> {
+    #[inline]
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.erased.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, N, I> serde::Deserialize<'de> for NodeSetIndex<N, I> {
+    #[inline]
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            erased: u32based::NodeSetIndex::deserialize(deserializer)?,
+            _ni: PhantomData,
+        })
+    }
+}
+
+#[repr(transparent)]
+pub struct NodeSetIndexLog<N, I> {
+    pub(crate) erased: u32based::NodeSetIndexLog,
+    _ni: PhantomData<(N, I)>,
+}
+
+impl<N, I> NodeSetIndexLog<N, I> {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    #[inline]
+    pub fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            erased: u32based::NodeSetIndexLog::from_wal_bytes(bytes)?,
+            _ni: PhantomData,
+        })
+    }
+
+    /// Encodes the log as a write-ahead log record, to be replayed in order
+    /// against the same base.
+    #[inline]
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        self.erased.to_wal_bytes()
+    }
+
+    #[inline]
+    pub fn all_items(&self, base: &NodeSetIndex<N, I>) -> IntSet<I> {
+        unsafe { IntSet::from_set(self.erased.all_items(&base.erased)) }
+    }
+
+    /// Removes every direct item of `node`, walking the ancestors once.
+    #[inline]
+    pub fn clear_node(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+    ) where
+        N: Into<u32>,
+    {
+        self.erased
+            .clear_node(&base.erased, &tree.erased, &tree_log.erased, node.into());
+    }
+
+    #[inline]
+    pub fn direct_items<'a>(&'a self, base: &'a NodeSetIndex<N, I>, node: N) -> &'a IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe { IntSet::from_u32set_ref(self.erased.direct_items(&base.erased, node.into())) }
+    }
+
+    #[inline]
+    pub fn insert(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+        item: I,
+    ) where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.insert(
+            &base.erased,
+            &tree.erased,
+            &tree_log.erased,
+            node.into(),
+            item.into(),
+        );
+    }
+
+    /// Inserts several items into `node`, walking the ancestors once.
+    #[inline]
+    pub fn insert_many(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+        items: &IntSet<I>,
+    ) where
+        N: Into<u32>,
+    {
+        self.erased.insert_many(
+            &base.erased,
+            &tree.erased,
+            &tree_log.erased,
+            node.into(),
+            items.as_set(),
+        );
+    }
+
+    /// Adds the items of `node`'s subtree to its new ancestors. Call after
+    /// `node` was moved in `tree_log`.
+    #[inline]
+    pub fn insert_subtree(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+    ) where
+        N: Into<u32>,
+    {
+        self.erased
+            .insert_subtree(&base.erased, &tree.erased, &tree_log.erased, node.into());
+    }
+
+    /// Moves `node` under `parent` in `tree_log`, carrying its subtree items
+    /// from the old ancestors to the new ones.
+    ///
+    /// Returns `false` and changes nothing if the move would close a cycle.
+    #[inline]
+    pub fn reparent(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &mut TreeIndexLog<N>,
+        parent: Option<N>,
+        node: N,
+    ) -> bool
+    where
+        N: Into<u32>,
+    {
+        self.erased.reparent(
+            &base.erased,
+            &tree.erased,
+            &mut tree_log.erased,
+            parent.map(Into::into),
+            node.into(),
+        )
+    }
+
+    #[inline]
+    pub fn remove(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+        item: I,
+    ) where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.remove(
+            &base.erased,
+            &tree.erased,
+            &tree_log.erased,
+            node.into(),
+            item.into(),
+        );
+    }
+
+    /// Removes the items of `node`'s subtree from its current ancestors. Call
+    /// before `node` is moved in `tree_log`.
+    #[inline]
+    pub fn remove_subtree(
+        &mut self,
+        base: &NodeSetIndex<N, I>,
+        tree: &Tree<N>,
+        tree_log: &TreeIndexLog<N>,
+        node: N,
+    ) where
+        N: Into<u32>,
+    {
+        self.erased
+            .remove_subtree(&base.erased, &tree.erased, &tree_log.erased, node.into());
+    }
+
+    #[inline]
+    pub fn subtree_items<'a>(&'a self, base: &'a NodeSetIndex<N, I>, node: N) -> &'a IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        unsafe { IntSet::from_u32set_ref(self.erased.subtree_items(&base.erased, node.into())) }
+    }
+}
+
+impl<N, I> Clone for NodeSetIndexLog<N, I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            erased: self.erased.clone(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+impl<N, I> Default for NodeSetIndexLog<N, I> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            erased: Default::default(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+/// Nodes touched by [`NodeSetIndex::apply_report`].
+pub struct NodeSetIndexReport<N> {
+    pub direct: IntSet<N>,
+    pub subtree: IntSet<N>,
+}
+
+impl<N> NodeSetIndexReport<N> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.direct.is_empty() && self.subtree.is_empty()
+    }
+}
+
+pub struct NodeSetIndexBuilder<N, I> {
+    erased: u32based::NodeSetIndexBuilder,
+    _ni: PhantomData<(N, I)>,
+}
+
+impl<N, I> NodeSetIndexBuilder<N, I> {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn build(self) -> NodeSetIndex<N, I> {
+        NodeSetIndex {
+            erased: self.erased.build(),
+            _ni: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, tree: &Tree<N>, node: N, item: I)
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.insert(&tree.erased, node.into(), item.into());
+    }
+
+    #[inline]
+    pub fn insert_many(&mut self, tree: &Tree<N>, node: N, items: &IntSet<I>)
+    where
+        N: Into<u32>,
+    {
+        self.erased
+            .insert_many(&tree.erased, node.into(), items.as_set());
+    }
+
+    /// Adds the items of `node`'s subtree to its ancestors. Call after `node`
+    /// was moved in the trx log.
+    #[inline]
+    pub fn insert_subtree(&mut self, tree: &TreeTrx<N>, node: N)
+    where
+        N: Into<u32>,
+    {
+        self.erased
+            .insert_subtree(&tree.base.erased, &tree.log.erased, node.into());
+    }
+
+    /// Like [`insert`](Self::insert), but follows the staged hierarchy of `tree`.
+    #[inline]
+    pub fn insert_trx(&mut self, tree: &TreeTrx<N>, node: N, item: I)
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.insert_with_log(
+            &tree.base.erased,
+            &tree.log.erased,
+            node.into(),
+            item.into(),
+        );
+    }
+
+    #[inline]
+    pub fn remove(&mut self, tree: &Tree<N>, node: N, item: I)
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.remove(&tree.erased, node.into(), item.into());
+    }
+
+    /// Removes the items of `node`'s subtree from its ancestors. Call before
+    /// `node` is moved in the trx log.
+    #[inline]
+    pub fn remove_subtree(&mut self, tree: &TreeTrx<N>, node: N)
+    where
+        N: Into<u32>,
+    {
+        self.erased
+            .remove_subtree(&tree.base.erased, &tree.log.erased, node.into());
+    }
+
+    /// Like [`remove`](Self::remove), but follows the staged hierarchy of `tree`.
+    #[inline]
+    pub fn remove_trx(&mut self, tree: &TreeTrx<N>, node: N, item: I)
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.erased.remove_with_log(
+            &tree.base.erased,
+            &tree.log.erased,
+            node.into(),
+            item.into(),
+        );
+    }
+}
+
+impl<N, I> Default for NodeSetIndexBuilder<N, I> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            erased: Default::default(),
+            _ni: PhantomData,
+        }
+    }
+}
+
+/// Stages hierarchy moves and item changes together, so that moving a node in
+/// the tree always updates the item aggregates along both branches.
+pub struct HierarchyTrx<'a, N, I> {
+    index: &'a NodeSetIndex<N, I>,
+    index_log: &'a mut NodeSetIndexLog<N, I>,
+    tree: &'a Tree<N>,
+    tree_log: &'a mut TreeIndexLog<N>,
+}
+
+impl<'a, N, I> HierarchyTrx<'a, N, I> {
+    #[inline]
+    pub fn new(
+        tree: &'a Tree<N>,
+        tree_log: &'a mut TreeIndexLog<N>,
+        index: &'a NodeSetIndex<N, I>,
+        index_log: &'a mut NodeSetIndexLog<N, I>,
+    ) -> Self {
+        Self {
+            index,
+            index_log,
+            tree,
+            tree_log,
+        }
+    }
+
+    #[inline]
+    pub fn clear_node(&mut self, node: N)
+    where
+        N: Into<u32>,
+    {
+        self.index_log
+            .clear_node(self.index, self.tree, self.tree_log, node);
+    }
+
+    #[inline]
+    pub fn insert_item(&mut self, node: N, item: I)
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.index_log
+            .insert(self.index, self.tree, self.tree_log, node, item);
+    }
+
+    /// Attaches `node` under `parent`, moving its subtree items along.
+    ///
+    /// Returns `false` and changes nothing if `parent` is `node` or one of its
+    /// descendants.
+    #[inline]
+    pub fn insert_node(&mut self, parent: Option<N>, node: N) -> bool
+    where
+        N: Into<u32>,
+    {
+        self.index_log
+            .reparent(self.index, self.tree, self.tree_log, parent, node)
+    }
+
+    #[inline]
+    pub fn remove_item(&mut self, node: N, item: I)
+    where
+        N: Into<u32>,
+        I: Into<u32>,
+    {
+        self.index_log
+            .remove(self.index, self.tree, self.tree_log, node, item);
+    }
+
+    #[inline]
+    pub fn subtree_items(&self, node: N) -> &IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        self.index_log.subtree_items(self.index, node)
+    }
+
+    #[inline]
+    pub fn tree_trx(&self) -> TreeTrx<'_, N> {
+        TreeTrx::new(self.tree, self.tree_log)
+    }
+}
+
+pub struct NodeSetIndexTrx<'a, N, I> {
+    base: &'a NodeSetIndex<N, I>,
+    log: &'a NodeSetIndexLog<N, I>,
+    tree: Option<(&'a Tree<N>, &'a TreeIndexLog<N>)>,
+}
+
+impl<'a, N, I> NodeSetIndexTrx<'a, N, I> {
+    #[inline]
+    pub fn new(base: &'a NodeSetIndex<N, I>, log: &'a NodeSetIndexLog<N, I>) -> Self {
+        Self {
+            base,
+            log,
+            tree: None,
+        }
+    }
+
+    /// Binds the hierarchy the index follows, enabling the tree-aware queries.
+    #[inline]
+    pub fn with_tree(
+        base: &'a NodeSetIndex<N, I>,
+        log: &'a NodeSetIndexLog<N, I>,
+        tree: &TreeTrx<'a, N>,
+    ) -> Self {
+        Self {
+            base,
+            log,
+            tree: Some((tree.base, tree.log)),
+        }
+    }
+
+    /// Every item held directly by any node.
+    #[inline]
+    pub fn all_items(&self) -> impl Iterator<Item = I> + use<'a, N, I>
+    where
+        I: TryFrom<u32>,
+    {
+        self.log
+            .erased
+            .all_items(&self.base.erased)
+            .into_iter()
+            .filter_map(try_key)
+    }
+
+    #[inline]
+    pub fn direct_items(&self, node: N) -> &'a IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        self.log.direct_items(self.base, node)
+    }
+
+    /// Subtree items of the parent of `node`, that is the items of `node` and
+    /// of its siblings' subtrees. `None` for a root or without a bound tree.
+    #[inline]
+    pub fn items_under_ancestor_of(&self, node: N) -> Option<&'a IntSet<I>>
+    where
+        N: TryFrom<u32> + Into<u32>,
+    {
+        let (tree, tree_log) = self.tree?;
+        let parent = tree_log.parent(tree, node)?;
+        Some(self.subtree_items(parent))
+    }
+
+    #[inline]
+    pub fn direct_items_iter(&self, node: N) -> impl Iterator<Item = I> + 'a
+    where
+        N: Into<u32>,
+        I: TryFrom<u32>,
+    {
+        self.direct_items(node).iter()
+    }
+
+    #[inline]
+    pub fn subtree_items(&self, node: N) -> &'a IntSet<I>
+    where
+        N: Into<u32>,
+    {
+        self.log.subtree_items(self.base, node)
+    }
+
+    #[inline]
+    pub fn subtree_items_iter(&self, node: N) -> impl Iterator<Item = I> + 'a
+    where
+        N: Into<u32>,
+        I: TryFrom<u32>,
+    {
+        self.subtree_items(node).iter()
+    }
+}
+
+impl<N, I> Clone for NodeSetIndexTrx<'_, N, I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<N, I> Copy for NodeSetIndexTrx<'_, N, I> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trx_with_tree_answers_ancestor_queries() {
+        let tree = Tree::<u32>::from_iter([(1, None), (2, Some(1)), (3, Some(1))]);
+        let tree_log = TreeIndexLog::new();
+        let mut builder = NodeSetIndexBuilder::<u32, u32>::new();
+        builder.insert(&tree, 2, 10);
+        builder.insert(&tree, 3, 20);
+        let index = builder.build();
+        let log = NodeSetIndexLog::new();
+
+        let tree_trx = TreeTrx::new(&tree, &tree_log);
+        let trx = NodeSetIndexTrx::with_tree(&index, &log, &tree_trx);
+
+        let mut items = trx
+            .items_under_ancestor_of(2)
+            .unwrap()
+            .iter()
+            .collect::<Vec<_>>();
+        items.sort_unstable();
+
+        assert_eq!(items, vec![10, 20]);
+        assert!(trx.items_under_ancestor_of(1).is_none());
+        assert!(
+            NodeSetIndexTrx::new(&index, &log)
+                .items_under_ancestor_of(2)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn hierarchy_trx_moves_items_with_nodes() {
+        let tree = Tree::<u32>::from_iter([(1, None), (2, Some(1)), (3, None)]);
+        let mut tree_log = TreeIndexLog::new();
+        let index = NodeSetIndex::<u32, u32>::new();
+        let mut log = NodeSetIndexLog::new();
+
+        let mut trx = HierarchyTrx::new(&tree, &mut tree_log, &index, &mut log);
+        trx.insert_item(2, 10);
+        assert!(trx.insert_node(Some(3), 2));
+        assert!(!trx.insert_node(Some(2), 3));
+
+        assert!(trx.subtree_items(1).is_empty());
+        assert!(trx.subtree_items(3).contains(10));
+        assert_eq!(trx.tree_trx().parent(2), Some(3));
+    }
+}
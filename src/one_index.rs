@@ -1,8 +1,22 @@
-use crate::u32based::one_index;
-use std::marker::PhantomData;
+use crate::{
+    IntSet, KeyOutOfRange, U32Set,
+    codec::{CodecValue, DecodeError},
+    strict::{check_key, try_key},
+    u32based::one_index,
+};
+pub use one_index::{OneIndexChange, OneIndexMemory, Resolution};
+use rustc_hash::{FxBuildHasher, FxHashMap};
+use std::{
+    collections::{VecDeque, hash_map::Entry},
+    error::Error,
+    fmt::{self, Debug},
+    hash::Hash,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
 
 pub struct OneIndex<K, V> {
-    index: one_index::OneIndex<V>,
+    pub(crate) index: one_index::OneIndex<V>,
     _k: PhantomData<K>,
 }
 
@@ -22,6 +36,59 @@ impl<K, V> OneIndex<K, V> {
         self.index.apply(log.log)
     }
 
+    /// Like [`apply`](Self::apply), but reports each effective change so
+    /// dependent indexes can be updated incrementally.
+    pub fn apply_report(&mut self, log: OneIndexLog<K, V>) -> Vec<(K, OneIndexChange)>
+    where
+        K: TryFrom<u32>,
+        V: PartialEq,
+    {
+        self.index
+            .apply_report(log.log)
+            .into_iter()
+            .filter_map(|(k, c)| Some((try_key(k)?, c)))
+            .collect()
+    }
+
+    /// Builds the index directly, without staging through a log. Entries are
+    /// best given in ascending key order; a repeated key keeps its last value.
+    #[inline]
+    pub fn bulk_load<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<u32>,
+    {
+        Self {
+            index: one_index::OneIndex::bulk_load(iter.into_iter().map(|(k, v)| (k.into(), v))),
+            _k: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.index.clear();
+    }
+
+    /// Maintains the set of occupied keys so that `iter` and `keys` skip empty
+    /// slots, at the cost of yielding in arbitrary order.
+    #[inline]
+    pub fn enable_occupied(&mut self) {
+        self.index.enable_occupied();
+    }
+
+    #[inline]
+    pub fn first_key(&self) -> Option<K>
+    where
+        K: TryFrom<u32>,
+    {
+        self.index.first_key().and_then(try_key)
+    }
+
+    #[inline]
+    pub fn has_occupied(&self) -> bool {
+        self.index.has_occupied()
+    }
+
     #[inline]
     pub fn get(&self, key: K) -> Option<&V>
     where
@@ -30,6 +97,22 @@ impl<K, V> OneIndex<K, V> {
         self.index.get(key.into())
     }
 
+    #[inline]
+    pub fn get_or<'a>(&'a self, key: K, default: &'a V) -> &'a V
+    where
+        K: Into<u32>,
+    {
+        self.get(key).unwrap_or(default)
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V>
+    where
+        K: Into<u32>,
+    {
+        self.index.get_mut(key.into())
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.index.is_empty()
@@ -42,7 +125,7 @@ impl<K, V> OneIndex<K, V> {
     {
         self.index
             .iter()
-            .filter_map(|(k, v)| Some((K::try_from(k).ok()?, v)))
+            .filter_map(|(k, v)| Some((try_key(k)?, v)))
     }
 
     #[inline]
@@ -50,15 +133,111 @@ impl<K, V> OneIndex<K, V> {
         self.index.len()
     }
 
+    #[inline]
+    pub fn last_key(&self) -> Option<K>
+    where
+        K: TryFrom<u32>,
+    {
+        self.index.last_key().and_then(try_key)
+    }
+
+    #[inline]
+    pub fn memory_usage(&self) -> OneIndexMemory {
+        self.index.memory_usage()
+    }
+
+    /// Occupied slots over allocated ones.
+    #[inline]
+    pub fn occupancy(&self) -> f64 {
+        self.index.occupancy()
+    }
+
+    /// Entries whose key falls in `range`, in ascending order.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (K, &V)> + '_
+    where
+        K: Clone + Into<u32> + TryFrom<u32>,
+        R: RangeBounds<K>,
+    {
+        self.index
+            .range(map_range(range))
+            .filter_map(|(k, v)| Some((try_key(k)?, v)))
+    }
+
     #[inline]
     pub fn keys(&self) -> impl Iterator<Item = K> + '_
     where
         K: TryFrom<u32>,
     {
-        self.index.keys().filter_map(|k| K::try_from(k).ok())
+        self.index.keys().filter_map(try_key)
+    }
+
+    /// Trims trailing empty slots and releases the spare capacity.
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.index.shrink_to_fit();
+    }
+
+    /// Removes every entry whose key is `key` or above.
+    #[inline]
+    pub fn truncate(&mut self, key: K)
+    where
+        K: Into<u32>,
+    {
+        self.index.truncate(key.into());
+    }
+
+    /// Mutates the value of `key` in place, bypassing the log. Returns `false`
+    /// if there is none.
+    #[inline]
+    pub fn update<F: FnOnce(&mut V)>(&mut self, key: K, f: F) -> bool
+    where
+        K: Into<u32>,
+    {
+        self.index.update(key.into(), f)
+    }
+
+    /// Keeps the entries for which `f` returns `true`. Entries whose key does
+    /// not convert to `K` are removed.
+    #[inline]
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(K, &mut V) -> bool,
+        K: TryFrom<u32>,
+    {
+        self.index
+            .retain(|k, v| K::try_from(k).is_ok_and(|k| f(k, v)))
     }
 }
 
+impl<K, V: Clone> Clone for OneIndex<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index.clone(),
+            _k: PhantomData,
+        }
+    }
+}
+
+impl<K, V: Debug> Debug for OneIndex<K, V> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.index.fmt(f)
+    }
+}
+
+fn map_range<K, R>(range: R) -> (Bound<u32>, Bound<u32>)
+where
+    K: Clone + Into<u32>,
+    R: RangeBounds<K>,
+{
+    fn map<K: Clone + Into<u32>>(b: Bound<&K>) -> Bound<u32> {
+        b.map(|k| k.clone().into())
+    }
+
+    (map(range.start_bound()), map(range.end_bound()))
+}
+
 impl<K, V> Default for OneIndex<K, V> {
     #[inline]
     fn default() -> Self {
@@ -86,8 +265,64 @@ where
     }
 }
 
+#[cfg(feature = "serde")]
+impl<K, V: serde::Serialize> serde::Serialize for OneIndex<K, V> {
+    #[inline]
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.index.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V> OneIndex<K, V> {
+    /// Like [`Deserialize`](serde::Deserialize), but takes the key bound
+    /// instead of deriving it from the entry count.
+    #[inline]
+    pub fn deserialize_with_max_id<'de, D>(deserializer: D, max_id: u32) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        V: serde::Deserialize<'de>,
+    {
+        Ok(Self {
+            index: one_index::OneIndex::deserialize_with_max_id(deserializer, max_id)?,
+            _k: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V: serde::Deserialize<'de>> serde::Deserialize<'de> for OneIndex<K, V> {
+    #[inline]
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            index: one_index::OneIndex::deserialize(deserializer)?,
+            _k: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V> rayon::iter::FromParallelIterator<(K, V)> for OneIndex<K, V>
+where
+    K: Into<u32> + Send,
+    V: Send,
+{
+    #[inline]
+    fn from_par_iter<I>(iter: I) -> Self
+    where
+        I: rayon::iter::IntoParallelIterator<Item = (K, V)>,
+    {
+        use rayon::prelude::*;
+
+        Self {
+            index: iter.into_par_iter().map(|(k, v)| (k.into(), v)).collect(),
+            _k: PhantomData,
+        }
+    }
+}
+
 pub struct OneIndexLog<K, V> {
-    log: one_index::OneIndexLog<V>,
+    pub(crate) log: one_index::OneIndexLog<V>,
     _k: PhantomData<K>,
 }
 
@@ -100,6 +335,28 @@ impl<K, V> OneIndexLog<K, V> {
         }
     }
 
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    #[inline]
+    pub fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError>
+    where
+        V: CodecValue,
+    {
+        Ok(Self {
+            log: one_index::OneIndexLog::from_wal_bytes(bytes)?,
+            _k: PhantomData,
+        })
+    }
+
+    /// Encodes the log as a write-ahead log record, to be replayed in order
+    /// against the same base.
+    #[inline]
+    pub fn to_wal_bytes(&self) -> Vec<u8>
+    where
+        V: CodecValue,
+    {
+        self.log.to_wal_bytes()
+    }
+
     #[inline]
     pub fn get<'a>(&'a self, base: &'a OneIndex<K, V>, key: K) -> Option<&'a V>
     where
@@ -117,6 +374,51 @@ impl<K, V> OneIndexLog<K, V> {
         self.log.insert(&base.index, key.into(), value)
     }
 
+    /// Folds `other` into this log. When both logs staged different edits for
+    /// the same key, `conflict` gets both (`None` for a removal) and picks one.
+    /// Keys that do not convert to `K` keep our edit.
+    #[inline]
+    pub fn merge<F>(&mut self, other: Self, mut conflict: F)
+    where
+        F: FnMut(K, Option<&V>, Option<&V>) -> Resolution,
+        K: TryFrom<u32>,
+        V: PartialEq,
+    {
+        self.log
+            .merge(other.log, |k, ours, theirs| match try_key(k) {
+                Some(k) => conflict(k, ours, theirs),
+                None => Resolution::Ours,
+            })
+    }
+
+    /// Stages `value` only if the current value of `key` is still `expected`.
+    #[inline]
+    pub fn insert_if(
+        &mut self,
+        base: &OneIndex<K, V>,
+        key: K,
+        expected: Option<&V>,
+        value: V,
+    ) -> bool
+    where
+        K: Into<u32>,
+        V: PartialEq,
+    {
+        self.log.insert_if(&base.index, key.into(), expected, value)
+    }
+
+    /// Stages a modified copy of the current value of `key`. Returns `false`
+    /// if there is none.
+    #[inline]
+    pub fn update<F>(&mut self, base: &OneIndex<K, V>, key: K, f: F) -> bool
+    where
+        F: FnOnce(&mut V),
+        K: Into<u32>,
+        V: Clone + PartialEq,
+    {
+        self.log.update(&base.index, key.into(), f)
+    }
+
     #[inline]
     pub fn remove(&mut self, base: &OneIndex<K, V>, key: K)
     where
@@ -127,6 +429,23 @@ impl<K, V> OneIndexLog<K, V> {
     }
 }
 
+impl<K, V: Clone> Clone for OneIndexLog<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            log: self.log.clone(),
+            _k: PhantomData,
+        }
+    }
+}
+
+impl<K, V: Debug> Debug for OneIndexLog<K, V> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.log.fmt(f)
+    }
+}
+
 impl<K, V> Default for OneIndexLog<K, V> {
     #[inline]
     fn default() -> Self {
@@ -137,6 +456,7 @@ impl<K, V> Default for OneIndexLog<K, V> {
 pub struct OneIndexBuilder<K, V> {
     base: OneIndex<K, V>,
     log: OneIndexLog<K, V>,
+    max_id: u32,
 }
 
 impl<K, V> OneIndexBuilder<K, V> {
@@ -145,6 +465,15 @@ impl<K, V> OneIndexBuilder<K, V> {
         Self::default()
     }
 
+    /// Rejects keys above `max_id` instead of the crate-wide [`max_id`](crate::max_id).
+    #[inline]
+    pub fn with_max_id(max_id: u32) -> Self {
+        Self {
+            max_id,
+            ..Self::default()
+        }
+    }
+
     #[inline]
     pub fn build(mut self) -> OneIndex<K, V>
     where
@@ -154,13 +483,45 @@ impl<K, V> OneIndexBuilder<K, V> {
         self.base
     }
 
+    /// Does not check the max id; see [`try_insert`](Self::try_insert).
     #[inline]
     pub fn insert(&mut self, key: K, value: V)
     where
         K: Into<u32>,
         V: PartialEq,
     {
-        self.log.insert(&self.base, key, value)
+        self.log.log.insert(&self.base.index, key.into(), value);
+    }
+
+    /// Like [`insert`](Self::insert), but rejects keys above the builder's max id.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<(), KeyOutOfRange>
+    where
+        K: Into<u32>,
+        V: PartialEq,
+    {
+        let key = check_key(key.into(), self.max_id)?;
+        self.log.log.insert(&self.base.index, key, value);
+        Ok(())
+    }
+}
+
+impl<K, V: Clone> Clone for OneIndexBuilder<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base.clone(),
+            log: self.log.clone(),
+            max_id: self.max_id,
+        }
+    }
+}
+
+impl<K, V: Debug> Debug for OneIndexBuilder<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OneIndexBuilder")
+            .field("log", &self.log)
+            .field("max_id", &self.max_id)
+            .finish()
     }
 }
 
@@ -170,10 +531,281 @@ impl<K, V> Default for OneIndexBuilder<K, V> {
         Self {
             base: OneIndex::new(),
             log: OneIndexLog::new(),
+            max_id: crate::max_id(),
         }
     }
 }
 
+/// [`OneIndex`] that also maintains `value -> keys`, kept in sync by [`apply`](Self::apply).
+pub struct OneIndexRev<K, V> {
+    index: OneIndex<K, V>,
+    rev: FxHashMap<V, U32Set>,
+}
+
+impl<K, V> OneIndexRev<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            index: OneIndex::new(),
+            rev: FxHashMap::default(),
+        }
+    }
+
+    pub fn apply(&mut self, log: OneIndexLog<K, V>) -> bool
+    where
+        V: Clone + Eq + Hash,
+    {
+        let olds = log
+            .log
+            .keys()
+            .map(|k| (k, self.index.index.get(k).cloned()))
+            .collect::<Vec<_>>();
+
+        if !self.index.apply(log) {
+            return false;
+        }
+
+        for (k, old) in olds {
+            let new = self.index.index.get(k);
+
+            if old.as_ref() == new {
+                continue;
+            }
+
+            if let Some(old) = old
+                && let Entry::Occupied(mut o) = self.rev.entry(old)
+            {
+                o.get_mut().remove(&k);
+
+                if o.get().is_empty() {
+                    o.remove();
+                }
+            }
+
+            if let Some(new) = new {
+                self.rev.entry(new.clone()).or_default().insert(k);
+            }
+        }
+
+        true
+    }
+
+    #[inline]
+    pub fn as_index(&self) -> &OneIndex<K, V> {
+        &self.index
+    }
+
+    #[inline]
+    pub fn get(&self, key: K) -> Option<&V>
+    where
+        K: Into<u32>,
+    {
+        self.index.get(key)
+    }
+
+    /// Keys currently holding `value`.
+    #[inline]
+    pub fn keys_of(&self, value: &V) -> &IntSet<K>
+    where
+        V: Eq + Hash,
+    {
+        static EMPTY: U32Set = U32Set::with_hasher(FxBuildHasher);
+        let set = self.rev.get(value).unwrap_or(&EMPTY);
+
+        // SAFETY: every value was inserted from a `K` key of `index`.
+        unsafe { IntSet::from_u32set_ref(set) }
+    }
+}
+
+impl<K, V> Default for OneIndexRev<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> From<OneIndex<K, V>> for OneIndexRev<K, V>
+where
+    V: Clone + Eq + Hash,
+{
+    fn from(index: OneIndex<K, V>) -> Self {
+        let mut rev = FxHashMap::<V, U32Set>::default();
+
+        for (k, v) in index.index.iter() {
+            rev.entry(v.clone()).or_default().insert(k);
+        }
+
+        Self { index, rev }
+    }
+}
+
+/// [`OneIndex`] whose [`get`](Self::get) yields `V::default()` for missing keys,
+/// like [`FlatSetIndex::get`](crate::FlatSetIndex::get) yields an empty set.
+pub struct DefaultedOneIndex<K, V> {
+    default: V,
+    index: OneIndex<K, V>,
+}
+
+impl<K, V: Default> DefaultedOneIndex<K, V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::from(OneIndex::new())
+    }
+}
+
+impl<K, V> DefaultedOneIndex<K, V> {
+    #[inline]
+    pub fn apply(&mut self, log: OneIndexLog<K, V>) -> bool
+    where
+        V: PartialEq,
+    {
+        self.index.apply(log)
+    }
+
+    #[inline]
+    pub fn as_index(&self) -> &OneIndex<K, V> {
+        &self.index
+    }
+
+    #[inline]
+    pub fn get(&self, key: K) -> &V
+    where
+        K: Into<u32>,
+    {
+        self.index.get_or(key, &self.default)
+    }
+}
+
+impl<K, V: Default> Default for DefaultedOneIndex<K, V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V: Default> From<OneIndex<K, V>> for DefaultedOneIndex<K, V> {
+    #[inline]
+    fn from(index: OneIndex<K, V>) -> Self {
+        Self {
+            default: V::default(),
+            index,
+        }
+    }
+}
+
+/// [`OneIndex`] that keeps the previous values overwritten by its last `keep`
+/// applies, so readers pinned to an older version still see consistent data.
+pub struct VersionedOneIndex<K, V> {
+    index: OneIndex<K, V>,
+    keep: usize,
+    /// Oldest first; each entry holds the values as they were before `version`.
+    undo: VecDeque<(u64, FxHashMap<u32, Option<V>>)>,
+    version: u64,
+}
+
+impl<K, V> VersionedOneIndex<K, V> {
+    #[inline]
+    pub fn new(keep: usize) -> Self {
+        Self {
+            index: OneIndex::new(),
+            keep,
+            undo: VecDeque::new(),
+            version: 0,
+        }
+    }
+
+    /// Applies `log` as a new version, returning it. Versions only advance when
+    /// something changed.
+    pub fn apply(&mut self, log: OneIndexLog<K, V>) -> u64
+    where
+        V: Clone + PartialEq,
+    {
+        let mut olds = log
+            .log
+            .keys()
+            .map(|k| (k, self.index.index.get(k).cloned()))
+            .collect::<FxHashMap<_, _>>();
+
+        let changes = self.index.index.apply_report(log.log);
+
+        if changes.is_empty() {
+            return self.version;
+        }
+
+        let olds = changes
+            .into_iter()
+            .filter_map(|(k, _)| Some((k, olds.remove(&k)?)))
+            .collect();
+
+        self.version += 1;
+        self.undo.push_back((self.version, olds));
+
+        while self.undo.len() > self.keep {
+            self.undo.pop_front();
+        }
+
+        self.version
+    }
+
+    #[inline]
+    pub fn as_index(&self) -> &OneIndex<K, V> {
+        &self.index
+    }
+
+    #[inline]
+    pub fn get(&self, key: K) -> Option<&V>
+    where
+        K: Into<u32>,
+    {
+        self.index.get(key)
+    }
+
+    /// Value of `key` as of `version`.
+    pub fn get_at(&self, version: u64, key: K) -> Result<Option<&V>, VersionUnavailable>
+    where
+        K: Into<u32>,
+    {
+        if version > self.version || version < self.oldest_version() {
+            return Err(VersionUnavailable(version));
+        }
+
+        let key = key.into();
+
+        for (v, olds) in &self.undo {
+            if *v > version
+                && let Some(old) = olds.get(&key)
+            {
+                return Ok(old.as_ref());
+            }
+        }
+
+        Ok(self.index.index.get(key))
+    }
+
+    /// Oldest version still readable through [`get_at`](Self::get_at).
+    #[inline]
+    pub fn oldest_version(&self) -> u64 {
+        self.undo.front().map_or(self.version, |(v, _)| v - 1)
+    }
+
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// The requested version is newer than the index or no longer retained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VersionUnavailable(pub u64);
+
+impl fmt::Display for VersionUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "version {} is not available", self.0)
+    }
+}
+
+impl Error for VersionUnavailable {}
+
 pub struct OneIndexTrx<'a, K, V> {
     base: &'a OneIndex<K, V>,
     log: &'a OneIndexLog<K, V>,
@@ -185,6 +817,14 @@ impl<'a, K, V> OneIndexTrx<'a, K, V> {
         Self { base, log }
     }
 
+    #[inline]
+    pub fn first_key(&self) -> Option<K>
+    where
+        K: TryFrom<u32>,
+    {
+        self.log.log.first_key(&self.base.index).and_then(try_key)
+    }
+
     #[inline]
     pub fn get(&self, key: K) -> Option<&V>
     where
@@ -192,4 +832,106 @@ impl<'a, K, V> OneIndexTrx<'a, K, V> {
     {
         self.log.get(self.base, key)
     }
+
+    #[inline]
+    pub fn last_key(&self) -> Option<K>
+    where
+        K: TryFrom<u32>,
+    {
+        self.log.log.last_key(&self.base.index).and_then(try_key)
+    }
+
+    /// Entries whose key falls in `range` with the log applied, in ascending order.
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = (K, &V)> + '_
+    where
+        K: Clone + Into<u32> + TryFrom<u32>,
+        R: RangeBounds<K>,
+    {
+        self.log
+            .log
+            .range(&self.base.index, map_range(range))
+            .into_iter()
+            .filter_map(|(k, v)| Some((try_key(k)?, v)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_follows_apply() {
+        let mut index = OneIndexRev::from(OneIndex::<u32, &str>::from_iter([(1, "a"), (2, "a")]));
+        assert_eq!(index.keys_of(&"a"), &IntSet::from_iter([1, 2]));
+
+        let mut log = OneIndexLog::new();
+        log.insert(index.as_index(), 2, "b");
+        log.remove(index.as_index(), 1);
+        log.insert(index.as_index(), 3, "b");
+        assert!(index.apply(log));
+
+        assert!(index.keys_of(&"a").is_empty());
+        assert_eq!(index.keys_of(&"b"), &IntSet::from_iter([2, 3]));
+        assert!(!index.rev.contains_key("a"));
+    }
+
+    #[test]
+    fn versioned_reads_older_generations() {
+        let mut index = VersionedOneIndex::<u32, u32>::new(2);
+
+        for value in 1..=3 {
+            let mut log = OneIndexLog::new();
+            log.insert(index.as_index(), 7, value);
+            log.insert(index.as_index(), value, value);
+            assert_eq!(index.apply(log), u64::from(value));
+        }
+
+        assert_eq!(index.apply(OneIndexLog::new()), 3);
+        assert_eq!(index.oldest_version(), 1);
+        assert_eq!(index.get_at(3, 7), Ok(Some(&3)));
+        assert_eq!(index.get_at(2, 7), Ok(Some(&2)));
+        assert_eq!(index.get_at(1, 7), Ok(Some(&1)));
+        assert_eq!(index.get_at(1, 3), Ok(None));
+        assert_eq!(index.get_at(0, 7), Err(VersionUnavailable(0)));
+        assert_eq!(index.get_at(4, 7), Err(VersionUnavailable(4)));
+    }
+
+    #[test]
+    fn clone_and_debug() {
+        let index = OneIndex::<u32, &str>::from_iter([(3, "c"), (1, "a")]);
+
+        assert_eq!(format!("{:?}", index.clone()), r#"{1: "a", 3: "c"}"#);
+    }
+
+    #[test]
+    fn defaulted_get_never_misses() {
+        let index = DefaultedOneIndex::from(OneIndex::<u32, u32>::from_iter([(1, 10)]));
+
+        assert_eq!(*index.get(1), 10);
+        assert_eq!(*index.get(2), 0);
+        assert_eq!(index.as_index().get_or(2, &5), &5);
+    }
+
+    #[test]
+    fn merge_passes_typed_keys() {
+        let mut index = OneIndex::<u16, u32>::from_iter([(1, 10)]);
+        let mut ours = OneIndexLog::new();
+        let mut theirs = OneIndexLog::new();
+
+        ours.insert(&index, 1, 11);
+        ours.insert(&index, 2, 20);
+        theirs.insert(&index, 1, 12);
+
+        let mut conflicts = Vec::new();
+
+        ours.merge(theirs, |k: u16, _, _| {
+            conflicts.push(k);
+            Resolution::Theirs
+        });
+
+        index.apply(ours);
+        assert_eq!(conflicts, vec![1]);
+        assert_eq!(index.get(1), Some(&12));
+        assert_eq!(index.get(2), Some(&20));
+    }
 }
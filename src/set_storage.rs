@@ -0,0 +1,424 @@
+use crate::U32Set;
+use std::{array, collections::hash_set, iter, mem, option, slice};
+
+/// Backing storage of an [`IntSet`](crate::IntSet).
+///
+/// [`HashBackend`] suits sparse ids, [`SmallBackend`] sets that mostly hold a
+/// handful of ids and [`RoaringBackend`] (feature `roaring`) dense id ranges.
+/// Membership, iteration, draining, set queries, lazy set operations and
+/// operators on [`IntSet`](crate::IntSet) work with every backend.
+/// `IntSet::new` stays on [`HashBackend`] so that it needs no type annotation,
+/// as with `HashMap::new`; other backends start from `Default`. Capacity
+/// management and raw [`U32Set`] access are specific to [`HashBackend`].
+pub trait SetStorage {
+    type Set: Clone + Default + PartialEq;
+
+    type Drain<'a>: Iterator<Item = u32>;
+
+    type IntoIter: Iterator<Item = u32>;
+
+    type Iter<'a>: Iterator<Item = u32>;
+
+    fn clear(set: &mut Self::Set);
+
+    fn contains(set: &Self::Set, value: u32) -> bool;
+
+    /// Empties `set`, yielding its values. The set is empty even if the
+    /// iterator is dropped early.
+    fn drain(set: &mut Self::Set) -> Self::Drain<'_>;
+
+    fn insert(set: &mut Self::Set, value: u32) -> bool;
+
+    fn into_iter(set: Self::Set) -> Self::IntoIter;
+
+    fn is_empty(set: &Self::Set) -> bool;
+
+    fn iter(set: &Self::Set) -> Self::Iter<'_>;
+
+    fn len(set: &Self::Set) -> usize;
+
+    fn remove(set: &mut Self::Set, value: u32) -> bool;
+
+    fn retain(set: &mut Self::Set, f: impl FnMut(u32) -> bool);
+}
+
+pub struct HashBackend;
+
+impl SetStorage for HashBackend {
+    type Set = U32Set;
+    type Drain<'a> = hash_set::Drain<'a, u32>;
+    type IntoIter = hash_set::IntoIter<u32>;
+    type Iter<'a> = iter::Copied<hash_set::Iter<'a, u32>>;
+
+    #[inline]
+    fn clear(set: &mut U32Set) {
+        set.clear();
+    }
+
+    #[inline]
+    fn contains(set: &U32Set, value: u32) -> bool {
+        set.contains(&value)
+    }
+
+    /// Keeps the allocated capacity.
+    #[inline]
+    fn drain(set: &mut U32Set) -> Self::Drain<'_> {
+        set.drain()
+    }
+
+    #[inline]
+    fn insert(set: &mut U32Set, value: u32) -> bool {
+        set.insert(value)
+    }
+
+    #[inline]
+    fn into_iter(set: U32Set) -> Self::IntoIter {
+        set.into_iter()
+    }
+
+    #[inline]
+    fn is_empty(set: &U32Set) -> bool {
+        set.is_empty()
+    }
+
+    #[inline]
+    fn iter(set: &U32Set) -> Self::Iter<'_> {
+        set.iter().copied()
+    }
+
+    #[inline]
+    fn len(set: &U32Set) -> usize {
+        set.len()
+    }
+
+    #[inline]
+    fn remove(set: &mut U32Set, value: u32) -> bool {
+        set.remove(&value)
+    }
+
+    #[inline]
+    fn retain(set: &mut U32Set, mut f: impl FnMut(u32) -> bool) {
+        set.retain(|v| f(*v));
+    }
+}
+
+#[cfg(feature = "roaring")]
+pub struct RoaringBackend;
+
+#[cfg(feature = "roaring")]
+impl SetStorage for RoaringBackend {
+    type Set = roaring::RoaringBitmap;
+    type Drain<'a> = roaring::bitmap::IntoIter;
+    type IntoIter = roaring::bitmap::IntoIter;
+    type Iter<'a> = roaring::bitmap::Iter<'a>;
+
+    #[inline]
+    fn clear(set: &mut Self::Set) {
+        set.clear();
+    }
+
+    #[inline]
+    fn contains(set: &Self::Set, value: u32) -> bool {
+        set.contains(value)
+    }
+
+    #[inline]
+    fn drain(set: &mut Self::Set) -> Self::Drain<'_> {
+        mem::take(set).into_iter()
+    }
+
+    #[inline]
+    fn insert(set: &mut Self::Set, value: u32) -> bool {
+        set.insert(value)
+    }
+
+    #[inline]
+    fn into_iter(set: Self::Set) -> Self::IntoIter {
+        set.into_iter()
+    }
+
+    #[inline]
+    fn is_empty(set: &Self::Set) -> bool {
+        set.is_empty()
+    }
+
+    #[inline]
+    fn iter(set: &Self::Set) -> Self::Iter<'_> {
+        set.iter()
+    }
+
+    #[inline]
+    fn len(set: &Self::Set) -> usize {
+        set.len() as usize
+    }
+
+    #[inline]
+    fn remove(set: &mut Self::Set, value: u32) -> bool {
+        set.remove(value)
+    }
+
+    fn retain(set: &mut Self::Set, mut f: impl FnMut(u32) -> bool) {
+        let removed = set.iter().filter(|v| !f(*v)).collect::<Vec<_>>();
+
+        for v in removed {
+            set.remove(v);
+        }
+    }
+}
+
+/// Keeps up to `N` values inline and only allocates a [`U32Set`] beyond that.
+pub struct SmallBackend<const N: usize = 4>;
+
+/// Set of a [`SmallBackend`]. Once spilled, it moves back inline when
+/// removals leave `N / 2` values or fewer.
+#[derive(Clone)]
+pub struct SmallSet<const N: usize>(SmallRepr<N>);
+
+#[derive(Clone)]
+enum SmallRepr<const N: usize> {
+    Inline { len: usize, values: [u32; N] },
+    Spilled(U32Set),
+}
+
+impl<const N: usize> SmallSet<N> {
+    /// Moves a spilled set back inline once it holds `N / 2` values or fewer.
+    /// Waiting for `N / 2` rather than `N` keeps the next inserts from
+    /// spilling it again right away.
+    fn unspill(&mut self) {
+        let SmallRepr::Spilled(s) = &self.0 else {
+            return;
+        };
+
+        if s.len() > N / 2 {
+            return;
+        }
+
+        let mut values = [0; N];
+
+        for (slot, v) in values.iter_mut().zip(s) {
+            *slot = *v;
+        }
+
+        self.0 = SmallRepr::Inline {
+            len: s.len(),
+            values,
+        };
+    }
+}
+
+impl<const N: usize> Default for SmallSet<N> {
+    #[inline]
+    fn default() -> Self {
+        Self(SmallRepr::Inline {
+            len: 0,
+            values: [0; N],
+        })
+    }
+}
+
+impl<const N: usize> PartialEq for SmallSet<N> {
+    fn eq(&self, other: &Self) -> bool {
+        SmallBackend::<N>::len(self) == SmallBackend::<N>::len(other)
+            && SmallBackend::<N>::iter(self).all(|v| SmallBackend::<N>::contains(other, v))
+    }
+}
+
+/// Inline values, then the spilled set if any.
+pub type SmallIter<'a> =
+    iter::Copied<iter::Chain<slice::Iter<'a, u32>, iter::Flatten<option::IntoIter<&'a U32Set>>>>;
+
+/// Owned [`SmallIter`].
+pub type SmallIntoIter<const N: usize> =
+    iter::Chain<iter::Take<array::IntoIter<u32, N>>, iter::Flatten<option::IntoIter<U32Set>>>;
+
+impl<const N: usize> SetStorage for SmallBackend<N> {
+    type Set = SmallSet<N>;
+    type Drain<'a> = SmallIntoIter<N>;
+    type IntoIter = SmallIntoIter<N>;
+    type Iter<'a> = SmallIter<'a>;
+
+    #[inline]
+    fn clear(set: &mut Self::Set) {
+        *set = SmallSet::default();
+    }
+
+    #[inline]
+    fn contains(set: &Self::Set, value: u32) -> bool {
+        match &set.0 {
+            SmallRepr::Inline { len, values } => values[..*len].contains(&value),
+            SmallRepr::Spilled(s) => s.contains(&value),
+        }
+    }
+
+    #[inline]
+    fn drain(set: &mut Self::Set) -> Self::Drain<'_> {
+        Self::into_iter(mem::take(set))
+    }
+
+    fn insert(set: &mut Self::Set, value: u32) -> bool {
+        match &mut set.0 {
+            SmallRepr::Inline { len, values } => {
+                if values[..*len].contains(&value) {
+                    return false;
+                }
+
+                if *len < N {
+                    values[*len] = value;
+                    *len += 1;
+                } else {
+                    let mut s = U32Set::with_capacity_and_hasher(N * 2, Default::default());
+                    s.extend(values.iter().copied());
+                    s.insert(value);
+                    set.0 = SmallRepr::Spilled(s);
+                }
+
+                true
+            }
+            SmallRepr::Spilled(s) => s.insert(value),
+        }
+    }
+
+    #[inline]
+    fn into_iter(set: Self::Set) -> Self::IntoIter {
+        let (inline, spilled) = match set.0 {
+            SmallRepr::Inline { len, values } => (values.into_iter().take(len), None),
+            SmallRepr::Spilled(s) => ([0; N].into_iter().take(0), Some(s)),
+        };
+
+        inline.chain(spilled.into_iter().flatten())
+    }
+
+    #[inline]
+    fn is_empty(set: &Self::Set) -> bool {
+        Self::len(set) == 0
+    }
+
+    #[inline]
+    fn iter(set: &Self::Set) -> Self::Iter<'_> {
+        let (inline, spilled) = match &set.0 {
+            SmallRepr::Inline { len, values } => (&values[..*len], None),
+            SmallRepr::Spilled(s) => (&[][..], Some(s)),
+        };
+
+        inline.iter().chain(spilled.into_iter().flatten()).copied()
+    }
+
+    #[inline]
+    fn len(set: &Self::Set) -> usize {
+        match &set.0 {
+            SmallRepr::Inline { len, .. } => *len,
+            SmallRepr::Spilled(s) => s.len(),
+        }
+    }
+
+    fn remove(set: &mut Self::Set, value: u32) -> bool {
+        match &mut set.0 {
+            SmallRepr::Inline { len, values } => {
+                match values[..*len].iter().position(|v| *v == value) {
+                    Some(i) => {
+                        values.copy_within(i + 1..*len, i);
+                        *len -= 1;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            SmallRepr::Spilled(s) => {
+                let removed = s.remove(&value);
+                set.unspill();
+                removed
+            }
+        }
+    }
+
+    fn retain(set: &mut Self::Set, mut f: impl FnMut(u32) -> bool) {
+        match &mut set.0 {
+            SmallRepr::Inline { len, values } => {
+                let mut kept = 0;
+
+                for i in 0..*len {
+                    if f(values[i]) {
+                        values[kept] = values[i];
+                        kept += 1;
+                    }
+                }
+
+                *len = kept;
+            }
+            SmallRepr::Spilled(s) => {
+                s.retain(|v| f(*v));
+                set.unspill();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntSet;
+
+    #[test]
+    fn small_backend_spills_past_inline_capacity() {
+        let mut raw = SmallSet::<2>::default();
+        SmallBackend::insert(&mut raw, 7);
+        SmallBackend::insert(&mut raw, 3);
+        assert!(matches!(raw.0, SmallRepr::Inline { len: 2, .. }));
+
+        SmallBackend::insert(&mut raw, 9);
+        assert!(matches!(raw.0, SmallRepr::Spilled(_)));
+
+        SmallBackend::remove(&mut raw, 9);
+        assert!(matches!(raw.0, SmallRepr::Spilled(_)));
+
+        SmallBackend::retain(&mut raw, |v| v == 3);
+        assert!(matches!(raw.0, SmallRepr::Inline { len: 1, .. }));
+        assert_eq!(SmallBackend::iter(&raw).collect::<Vec<_>>(), vec![3]);
+
+        let mut set = IntSet::<u32, SmallBackend<2>>::from_iter([7, 3, 7, 9]);
+        assert!(set.remove(3));
+        assert_eq!(format!("{set:?}"), "{7, 9}");
+        assert_eq!(set, IntSet::from_iter([9, 7]));
+
+        let mut keys = (&set).into_iter().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![7, 9]);
+    }
+
+    #[test]
+    fn small_backend_set_ops() {
+        type Small = IntSet<u32, SmallBackend<2>>;
+
+        let a = Small::from_iter([1, 2, 3]);
+        let b = Small::from_iter([3, 4]);
+
+        assert_eq!(&a & &b, Small::from_iter([3]));
+        assert_eq!(&a | &b, Small::from_iter([1, 2, 3, 4]));
+        assert_eq!(&a - &b, Small::from_iter([1, 2]));
+        assert_eq!(a.intersection_len(&b), 1);
+        assert_eq!(a.union_len(&b), 4);
+        assert!(Small::from_iter([1, 3]).is_subset(&a));
+        assert!(a.is_disjoint(&Small::from_iter([9])));
+        assert_eq!((a.min(), a.max()), (Some(1), Some(3)));
+        assert_eq!(a.iter_sorted().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let mut c = a.clone();
+        c.retain(|k| k != 2);
+        assert_eq!(c, Small::from_iter([1, 3]));
+    }
+
+    #[cfg(feature = "roaring")]
+    #[test]
+    fn roaring_matches_hash_backend() {
+        use crate::RoaringBackend;
+
+        let hash = IntSet::<u32>::from_iter([5, 1, 70_000]);
+        let mut roaring = IntSet::<u32, RoaringBackend>::from(hash.clone());
+
+        assert!(roaring.contains(70_000));
+        assert_eq!(roaring.iter().collect::<Vec<_>>(), vec![1, 5, 70_000]);
+        assert!(roaring.remove(5));
+        assert_eq!(format!("{roaring:?}"), "{1, 70000}");
+        assert_eq!(IntSet::<u32>::from(roaring).len(), 2);
+    }
+}
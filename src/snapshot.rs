@@ -0,0 +1,662 @@
+//! Single-file snapshots of a set of indexes.
+//!
+//! A snapshot is a versioned header followed by named sections, each with its
+//! own CRC-32. Interned sets are written once to a leading set table and
+//! referenced by position from every index section, so a set shared by several
+//! indexes is stored and re-interned only once.
+//!
+//! ```text
+//! magic "FSSNAP" | version | section count | sections...
+//! section: name | kind | payload length | crc32 (le) | payload
+//! ```
+//!
+//! Integers are varints from [`codec`](crate::codec) and sets use
+//! [`encode_sorted`].
+
+use crate::{
+    FlatSetIndex, IU32HashSetExt, KeyOutOfRange, NodeSetIndex, Tree,
+    codec::{
+        CodecValue, DecodeError, decode_option_u32, decode_sorted, encode_option_u32,
+        encode_sorted, expect_end, read_varint, write_varint,
+    },
+    one_index::OneIndex,
+    u32based::{self, one_index::load_max_id},
+};
+use intern::IU32HashSet;
+use rustc_hash::FxHashMap;
+use std::{
+    collections::hash_map::Entry,
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+};
+
+const MAGIC: &[u8; 6] = b"FSSNAP";
+const VERSION: u32 = 1;
+
+const KIND_SETS: u8 = 0;
+const KIND_FLAT_SET_INDEX: u8 = 1;
+const KIND_TREE: u8 = 2;
+const KIND_NODE_SET_INDEX: u8 = 3;
+const KIND_ONE_INDEX: u8 = 4;
+
+/// Collects indexes into a snapshot.
+#[derive(Default)]
+pub struct SnapshotWriter {
+    /// Keeps every referenced set alive so its address stays a valid key.
+    sets: Vec<IU32HashSet>,
+    set_ids: FxHashMap<usize, u32>,
+    sections: Vec<(String, u8, Vec<u8>)>,
+}
+
+impl SnapshotWriter {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flat_set_index<K, V>(&mut self, name: &str, index: &FlatSetIndex<K, V>) -> &mut Self {
+        let mut entries = index.inner.iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(k, _)| **k);
+
+        let mut out = Vec::new();
+        let none = self.set_id(index.inner.none());
+
+        write_varint(&mut out, none);
+        self.write_sets(&mut out, entries.into_iter().map(|(k, s)| (*k, s)));
+        self.push(name, KIND_FLAT_SET_INDEX, out)
+    }
+
+    pub fn node_set_index<N, I>(&mut self, name: &str, index: &NodeSetIndex<N, I>) -> &mut Self {
+        let mut out = Vec::new();
+
+        for map in [&index.erased.direct_items, &index.erased.subtree_items] {
+            let mut entries = map.iter().map(|(k, s)| (*k, s)).collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|(k, _)| *k);
+            self.write_sets(&mut out, entries);
+        }
+
+        self.push(name, KIND_NODE_SET_INDEX, out)
+    }
+
+    pub fn one_index<K, V: CodecValue>(&mut self, name: &str, index: &OneIndex<K, V>) -> &mut Self {
+        let mut out = Vec::new();
+
+        write_varint(&mut out, index.len() as u32);
+
+        for (k, v) in index.index.iter() {
+            write_varint(&mut out, k);
+            v.encode(&mut out);
+        }
+
+        self.push(name, KIND_ONE_INDEX, out)
+    }
+
+    /// Writes the parent of every node; children and descendants are rebuilt
+    /// on load.
+    pub fn tree<K>(&mut self, name: &str, tree: &Tree<K>) -> &mut Self {
+        let mut nodes = tree.erased.all_nodes().iter().copied().collect::<Vec<_>>();
+        nodes.sort_unstable();
+
+        let mut out = Vec::new();
+        write_varint(&mut out, nodes.len() as u32);
+
+        for n in nodes {
+            write_varint(&mut out, n);
+            encode_option_u32(tree.erased.parent(n), &mut out);
+        }
+
+        self.push(name, KIND_TREE, out)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sets = Vec::new();
+        write_varint(&mut sets, self.sets.len() as u32);
+
+        for s in &self.sets {
+            encode_sorted(s.as_set(), &mut sets);
+        }
+
+        let mut out = MAGIC.to_vec();
+        write_varint(&mut out, VERSION);
+        write_varint(&mut out, self.sections.len() as u32 + 1);
+        write_section(&mut out, "", KIND_SETS, &sets);
+
+        for (name, kind, payload) in &self.sections {
+            write_section(&mut out, name, *kind, payload);
+        }
+
+        out
+    }
+
+    #[inline]
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    fn push(&mut self, name: &str, kind: u8, payload: Vec<u8>) -> &mut Self {
+        self.sections.retain(|(n, _, _)| n != name);
+        self.sections.push((name.to_owned(), kind, payload));
+        self
+    }
+
+    fn set_id(&mut self, set: &IU32HashSet) -> u32 {
+        *self.set_ids.entry(set.ptr_id()).or_insert_with(|| {
+            self.sets.push(set.clone());
+            self.sets.len() as u32 - 1
+        })
+    }
+
+    fn write_sets<'a>(
+        &mut self,
+        out: &mut Vec<u8>,
+        entries: impl IntoIterator<Item = (u32, &'a IU32HashSet)>,
+    ) {
+        let entries = entries.into_iter().collect::<Vec<_>>();
+        write_varint(out, entries.len() as u32);
+
+        for (k, s) in entries {
+            write_varint(out, k);
+            write_varint(out, self.set_id(s));
+        }
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, name: &str, kind: u8, payload: &[u8]) {
+    write_varint(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+    out.push(kind);
+    write_varint(out, payload.len() as u32);
+    out.extend_from_slice(&crc32(payload).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// A validated snapshot, with its sets interned and its sections ready to be
+/// loaded by name.
+pub struct Snapshot {
+    sets: Vec<IU32HashSet>,
+    sections: FxHashMap<String, (u8, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /// Checks the header and every section checksum, and interns the set table.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let b = &mut bytes;
+
+        if take(b, MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = read_varint(b)?;
+
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let count = read_varint(b)?;
+        let mut sets = None;
+        let mut sections = FxHashMap::default();
+
+        for _ in 0..count {
+            let len = read_varint(b)? as usize;
+            let name = String::from_utf8(take(b, len)?.to_vec())
+                .map_err(|_| SnapshotError::InvalidSectionName)?;
+            let kind = take(b, 1)?[0];
+            let len = read_varint(b)? as usize;
+            let crc = u32::from_le_bytes(take(b, 4)?.try_into().expect("4 bytes"));
+            let payload = take(b, len)?;
+
+            if crc32(payload) != crc {
+                return Err(SnapshotError::Checksum(name));
+            }
+
+            if kind == KIND_SETS {
+                if sets.is_some() {
+                    return Err(SnapshotError::DuplicateSection(name));
+                }
+
+                sets = Some(read_set_table(payload)?);
+            } else {
+                match sections.entry(name) {
+                    Entry::Occupied(e) => {
+                        return Err(SnapshotError::DuplicateSection(e.key().clone()));
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert((kind, payload.to_vec()));
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            sets: sets.unwrap_or_default(),
+            sections,
+        })
+    }
+
+    #[inline]
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn flat_set_index<K, V>(&self, name: &str) -> Result<FlatSetIndex<K, V>, SnapshotError> {
+        let mut b = self.section(name, KIND_FLAT_SET_INDEX)?;
+        let none = self.read_set(&mut b)?;
+        let entries = self.read_sets(&mut b)?;
+        let mut index = FlatSetIndex::new();
+
+        expect_end(b)?;
+        index.inner = u32based::U32FlatSetIndex::from_interned(entries, none);
+        Ok(index)
+    }
+
+    /// Names of the index sections, in arbitrary order.
+    #[inline]
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.sections.keys().map(String::as_str)
+    }
+
+    pub fn node_set_index<N, I>(&self, name: &str) -> Result<NodeSetIndex<N, I>, SnapshotError> {
+        let mut b = self.section(name, KIND_NODE_SET_INDEX)?;
+        let mut index = NodeSetIndex::new();
+
+        index.erased.direct_items = self.read_sets(&mut b)?.into_iter().collect();
+        index.erased.subtree_items = self.read_sets(&mut b)?.into_iter().collect();
+        expect_end(b)?;
+        Ok(index)
+    }
+
+    /// Fails on keys past 64 slots per entry (and at least 65 536), which
+    /// would otherwise size the slot vector. Sparser indexes load through
+    /// [`one_index_with_max_id`](Self::one_index_with_max_id).
+    #[inline]
+    pub fn one_index<K, V: CodecValue>(&self, name: &str) -> Result<OneIndex<K, V>, SnapshotError> {
+        self.read_one_index(name, None)
+    }
+
+    /// Like [`one_index`](Self::one_index), but only fails on keys above `max_id`.
+    #[inline]
+    pub fn one_index_with_max_id<K, V: CodecValue>(
+        &self,
+        name: &str,
+        max_id: u32,
+    ) -> Result<OneIndex<K, V>, SnapshotError> {
+        self.read_one_index(name, Some(max_id))
+    }
+
+    pub fn tree<K>(&self, name: &str) -> Result<Tree<K>, SnapshotError> {
+        let mut b = self.section(name, KIND_TREE)?;
+        let len = read_varint(&mut b)?;
+        let mut nodes = Vec::with_capacity((len as usize).min(b.len()));
+
+        for _ in 0..len {
+            let node = read_varint(&mut b)?;
+            nodes.push((node, decode_option_u32(&mut b)?));
+        }
+
+        expect_end(b)?;
+
+        let mut tree = Tree::new();
+        tree.erased = nodes.into_iter().collect();
+        Ok(tree)
+    }
+
+    fn read_one_index<K, V: CodecValue>(
+        &self,
+        name: &str,
+        max_id: Option<u32>,
+    ) -> Result<OneIndex<K, V>, SnapshotError> {
+        let mut b = self.section(name, KIND_ONE_INDEX)?;
+        let len = read_varint(&mut b)?;
+        let mut entries = Vec::with_capacity((len as usize).min(b.len()));
+
+        for _ in 0..len {
+            let key = read_varint(&mut b)?;
+            entries.push((key, V::decode(&mut b)?));
+        }
+
+        expect_end(b)?;
+
+        let max_id = max_id.unwrap_or_else(|| load_max_id(entries.len()));
+        let mut index = OneIndex::default();
+        index.index = u32based::OneIndex::bulk_load_checked(entries, max_id)?;
+        Ok(index)
+    }
+
+    fn read_set(&self, b: &mut &[u8]) -> Result<IU32HashSet, SnapshotError> {
+        let id = read_varint(b)?;
+
+        self.sets
+            .get(id as usize)
+            .cloned()
+            .ok_or(SnapshotError::InvalidSetRef(id))
+    }
+
+    fn read_sets(&self, b: &mut &[u8]) -> Result<Vec<(u32, IU32HashSet)>, SnapshotError> {
+        let len = read_varint(b)?;
+        let mut entries = Vec::with_capacity((len as usize).min(b.len()));
+
+        for _ in 0..len {
+            entries.push((read_varint(b)?, self.read_set(b)?));
+        }
+
+        Ok(entries)
+    }
+
+    fn section(&self, name: &str, kind: u8) -> Result<&[u8], SnapshotError> {
+        match self.sections.get(name) {
+            Some((k, payload)) if *k == kind => Ok(payload),
+            Some(_) => Err(SnapshotError::WrongKind(name.to_owned())),
+            None => Err(SnapshotError::MissingSection(name.to_owned())),
+        }
+    }
+}
+
+fn read_set_table(mut b: &[u8]) -> Result<Vec<IU32HashSet>, SnapshotError> {
+    let len = read_varint(&mut b)?;
+    let mut sets = Vec::with_capacity((len as usize).min(b.len()));
+
+    for _ in 0..len {
+        let (set, rest) = decode_sorted(b)?;
+        sets.push(set.into());
+        b = rest;
+    }
+
+    Ok(sets)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if bytes.len() < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+/// CRC-32 (IEEE), bitwise.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &b in bytes {
+        crc ^= u32::from(b);
+
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    BadMagic,
+    Checksum(String),
+    Decode(DecodeError),
+    DuplicateSection(String),
+    InvalidSectionName,
+    InvalidSetRef(u32),
+    Io(io::Error),
+    KeyOutOfRange(KeyOutOfRange),
+    MissingSection(String),
+    UnsupportedVersion(u32),
+    WrongKind(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not a snapshot file"),
+            Self::Checksum(name) => write!(f, "checksum mismatch in section `{name}`"),
+            Self::Decode(e) => write!(f, "malformed snapshot: {e}"),
+            Self::DuplicateSection(name) => write!(f, "section `{name}` appears twice"),
+            Self::InvalidSectionName => f.write_str("section name is not valid UTF-8"),
+            Self::InvalidSetRef(id) => write!(f, "reference to unknown set {id}"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::KeyOutOfRange(e) => write!(f, "{e}"),
+            Self::MissingSection(name) => write!(f, "no section named `{name}`"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {v}"),
+            Self::WrongKind(name) => write!(f, "section `{name}` holds another index type"),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::KeyOutOfRange(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for SnapshotError {
+    #[inline]
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<KeyOutOfRange> for SnapshotError {
+    #[inline]
+    fn from(e: KeyOutOfRange) -> Self {
+        Self::KeyOutOfRange(e)
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    #[inline]
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlatSetIndexLog, NodeSetIndexLog, TreeIndexLog, U32Set};
+
+    type Sample = (
+        FlatSetIndex<u32, u32>,
+        FlatSetIndex<u32, u32>,
+        Tree<u32>,
+        NodeSetIndex<u32, u32>,
+        OneIndex<u32, u64>,
+    );
+
+    fn sample() -> Sample {
+        let mut a = FlatSetIndex::new();
+        let mut log = FlatSetIndexLog::new();
+        log.insert(&a, 1, 10);
+        log.insert(&a, 1, 11);
+        log.insert(&a, 2, 20);
+        log.insert_none(&a, 5);
+        a.apply(log);
+
+        let mut b = a.clone();
+        let mut log = FlatSetIndexLog::new();
+        log.remove(&b, 2, 20);
+        b.apply(log);
+
+        let tree = Tree::from_iter([(1, None), (2, Some(1)), (3, Some(2))]);
+        let mut nodes = NodeSetIndex::new();
+        let mut log = NodeSetIndexLog::new();
+        let tree_log = TreeIndexLog::new();
+        log.insert(&nodes, &tree, &tree_log, 3, 30);
+        nodes.apply(log);
+
+        let one = OneIndex::from_iter([(4, 40), (9, u64::MAX)]);
+
+        (a, b, tree, nodes, one)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (a, b, tree, nodes, one) = sample();
+        let mut writer = SnapshotWriter::new();
+
+        writer.flat_set_index("a", &a).flat_set_index("b", &b);
+
+        // b still shares {10, 11} and the none set with a
+        assert_eq!(writer.sets.len(), 3);
+
+        writer
+            .tree("tree", &tree)
+            .node_set_index("nodes", &nodes)
+            .one_index("one", &one);
+
+        let snapshot = Snapshot::from_bytes(&writer.to_bytes()).unwrap();
+        let a2 = snapshot.flat_set_index::<u32, u32>("a").unwrap();
+        let b2 = snapshot.flat_set_index::<u32, u32>("b").unwrap();
+
+        assert!(a2.contains(1, 11));
+        assert!(a2.contains(2, 20));
+        assert!(a2.none().contains(5));
+        assert!(a2.inner.get(&1).ptr_eq(b2.inner.get(&1)));
+        assert!(b2.get(2).is_empty());
+
+        let tree2 = snapshot.tree::<u32>("tree").unwrap();
+        assert_eq!(tree2.parent(3), Some(2));
+        assert_eq!(tree2.ancestors(3).collect::<Vec<_>>(), vec![2, 1]);
+
+        let nodes2 = snapshot.node_set_index::<u32, u32>("nodes").unwrap();
+        assert_eq!(*nodes2.erased.subtree_items(1), U32Set::from_iter([30]));
+
+        let one2 = snapshot.one_index::<u32, u64>("one").unwrap();
+        assert_eq!(one2.get(9), Some(&u64::MAX));
+        assert_eq!(one2.len(), 2);
+    }
+
+    #[test]
+    fn detects_corruption_and_misuse() {
+        let (a, ..) = sample();
+        let mut writer = SnapshotWriter::new();
+        writer.flat_set_index("a", &a);
+
+        let mut bytes = writer.to_bytes();
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+
+        assert!(matches!(
+            snapshot.tree::<u32>("a"),
+            Err(SnapshotError::WrongKind(_))
+        ));
+        assert!(matches!(
+            snapshot.tree::<u32>("missing"),
+            Err(SnapshotError::MissingSection(_))
+        ));
+
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes),
+            Err(SnapshotError::Checksum(name)) if name == "a"
+        ));
+
+        assert!(matches!(
+            Snapshot::from_bytes(b"nope"),
+            Err(SnapshotError::Decode(DecodeError::UnexpectedEnd))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_sections() {
+        let tree = Tree::from_iter([(u32::MAX, None), (1, Some(u32::MAX))]);
+        let mut writer = SnapshotWriter::new();
+        writer.tree("tree", &tree);
+
+        let mut key = Vec::new();
+        write_varint(&mut key, 1);
+        write_varint(&mut key, u32::MAX);
+        40u64.encode(&mut key);
+        writer.push("one", KIND_ONE_INDEX, key);
+
+        let mut trailing = Vec::new();
+        write_varint(&mut trailing, writer.set_id(&IU32HashSet::default()));
+        writer.write_sets(&mut trailing, []);
+        trailing.push(0);
+        writer.push("flat", KIND_FLAT_SET_INDEX, trailing);
+        writer.push("nodes", KIND_NODE_SET_INDEX, vec![0, 0, 0]);
+
+        let snapshot = Snapshot::from_bytes(&writer.to_bytes()).unwrap();
+        let tree2 = snapshot.tree::<u32>("tree").unwrap();
+        assert_eq!(tree2.parent(1), Some(u32::MAX));
+        assert_eq!(tree2.parent(u32::MAX), None);
+
+        assert!(matches!(
+            snapshot.one_index::<u32, u64>("one"),
+            Err(SnapshotError::KeyOutOfRange(e)) if e.key == u32::MAX
+        ));
+        assert!(matches!(
+            snapshot.flat_set_index::<u32, u32>("flat"),
+            Err(SnapshotError::Decode(DecodeError::TrailingBytes))
+        ));
+        assert!(matches!(
+            snapshot.node_set_index::<u32, u32>("nodes"),
+            Err(SnapshotError::Decode(DecodeError::TrailingBytes))
+        ));
+    }
+
+    #[test]
+    fn one_index_keys_are_bounded() {
+        let index = OneIndex::<u32, u64>::from_iter([(1, 10), (1_000_000, 20)]);
+        let mut writer = SnapshotWriter::new();
+        writer.one_index("one", &index);
+
+        let snapshot = Snapshot::from_bytes(&writer.to_bytes()).unwrap();
+
+        assert!(matches!(
+            snapshot.one_index::<u32, u64>("one"),
+            Err(SnapshotError::KeyOutOfRange(e)) if e.key == 1_000_000
+        ));
+        assert!(matches!(
+            snapshot.one_index_with_max_id::<u32, u64>("one", 999_999),
+            Err(SnapshotError::KeyOutOfRange(_))
+        ));
+
+        let back = snapshot
+            .one_index_with_max_id::<u32, u64>("one", 1_000_000)
+            .unwrap();
+        assert_eq!(back.get(1_000_000), Some(&20));
+    }
+
+    #[test]
+    fn rejects_duplicate_and_misnamed_sections() {
+        let file = |sections: &[(&[u8], u8)]| {
+            let mut out = MAGIC.to_vec();
+            write_varint(&mut out, VERSION);
+            write_varint(&mut out, sections.len() as u32);
+
+            for (name, kind) in sections {
+                let payload = [0];
+
+                write_varint(&mut out, name.len() as u32);
+                out.extend_from_slice(name);
+                out.push(*kind);
+                write_varint(&mut out, payload.len() as u32);
+                out.extend_from_slice(&crc32(&payload).to_le_bytes());
+                out.extend_from_slice(&payload);
+            }
+
+            Snapshot::from_bytes(&out)
+        };
+
+        assert!(file(&[(b"", KIND_SETS), (b"t", KIND_TREE)]).is_ok());
+        assert!(matches!(
+            file(&[(b"", KIND_SETS), (b"t", KIND_TREE), (b"t", KIND_ONE_INDEX)]),
+            Err(SnapshotError::DuplicateSection(name)) if name == "t"
+        ));
+        assert!(matches!(
+            file(&[(b"", KIND_SETS), (b"t", KIND_TREE), (b"", KIND_SETS)]),
+            Err(SnapshotError::DuplicateSection(_))
+        ));
+        assert!(matches!(
+            file(&[(b"", KIND_SETS), (b"\xff", KIND_TREE)]),
+            Err(SnapshotError::InvalidSectionName)
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use once_cell::sync::OnceCell;
+use rustc_hash::FxHashSet;
+use std::sync::{Arc, Mutex};
+
+/// Deduplicates string keys so that indexes keyed by `Arc<str>` share a
+/// single allocation per distinct string.
+#[derive(Default)]
+pub struct StrPool(Mutex<FxHashSet<Arc<str>>>);
+
+impl StrPool {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the pooled `Arc<str>` for `s`, if any, without inserting it.
+    pub fn get(&self, s: &str) -> Option<Arc<str>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(s)
+            .cloned()
+    }
+
+    /// Returns the pooled `Arc<str>` for `s`, inserting it on first use.
+    pub fn intern(&self, s: &str) -> Arc<str> {
+        let mut set = self.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        match set.get(s) {
+            Some(v) => v.clone(),
+            None => {
+                let v: Arc<str> = Arc::from(s);
+                set.insert(v.clone());
+                v
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Drops every string that is only referenced by the pool itself.
+    pub fn purge(&self) {
+        let mut set = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        set.retain(|s| Arc::strong_count(s) > 1);
+        set.shrink_to_fit();
+    }
+}
+
+/// Process-wide pool used by the `*_str` helpers on the hash indexes.
+pub fn str_pool() -> &'static StrPool {
+    static POOL: OnceCell<StrPool> = OnceCell::new();
+    POOL.get_or_init(StrPool::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern_shares_allocation() {
+        let pool = StrPool::new();
+        let a = pool.intern("tenant");
+        let b = pool.intern("tenant");
+
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(Arc::ptr_eq(&a, &pool.get("tenant").unwrap()));
+        assert!(pool.get("other").is_none());
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn purge_drops_unreferenced() {
+        let pool = StrPool::new();
+        let a = pool.intern("a");
+        drop(pool.intern("b"));
+
+        pool.purge();
+
+        assert_eq!(pool.len(), 1);
+        assert!(Arc::ptr_eq(&a, &pool.intern("a")));
+    }
+}
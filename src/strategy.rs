@@ -0,0 +1,393 @@
+//! [`proptest`] strategies for the containers, with reference models to check
+//! them against.
+//!
+//! Logs only make sense against the base they were staged on, so they are
+//! generated as cases: a base and a list of ops, staged on demand with
+//! [`TreeCase::log`] or [`FlatSetIndexCase::log`]. Shrinking works on the
+//! entries and ops.
+
+use crate::{FlatSetIndex, FlatSetIndexBuilder, FlatSetIndexLog, IntSet, Tree, TreeIndexLog};
+use proptest::{
+    collection::{btree_set, vec},
+    option,
+    prelude::*,
+    sample::Index,
+    test_runner::TestCaseError,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An [`IntSet`] of up to `max_len` ids below `max_id`.
+pub fn int_set(max_id: u32, max_len: usize) -> impl Strategy<Value = IntSet<u32>> {
+    btree_set(0..max_id, 0..=max_len).prop_map(IntSet::from)
+}
+
+/// `(node, parent)` entries of an acyclic forest of up to `max_nodes` nodes
+/// numbered from `0`. Each parent precedes its child.
+pub fn forest(max_nodes: usize) -> impl Strategy<Value = Vec<(u32, Option<u32>)>> {
+    vec(option::weighted(0.8, any::<Index>()), 0..=max_nodes).prop_map(|parents| {
+        parents
+            .into_iter()
+            .enumerate()
+            .map(|(n, p)| {
+                let parent = p.filter(|_| n > 0).map(|p| p.index(n) as u32);
+                (n as u32, parent)
+            })
+            .collect()
+    })
+}
+
+/// A [`Tree`] built from a [`forest`].
+pub fn tree(max_nodes: usize) -> impl Strategy<Value = Tree<u32>> {
+    forest(max_nodes).prop_map(Tree::from_iter)
+}
+
+/// A [`FlatSetIndex`] of up to `max_pairs` pairs, keys below `max_key` and
+/// values below `max_value`.
+pub fn flat_set_index(
+    max_key: u32,
+    max_value: u32,
+    max_pairs: usize,
+) -> impl Strategy<Value = FlatSetIndex<u32, u32>> {
+    vec((0..max_key, 0..max_value), 0..=max_pairs).prop_map(|pairs| {
+        let mut builder = FlatSetIndexBuilder::new();
+
+        for (k, v) in pairs {
+            builder.insert(k, v);
+        }
+
+        builder.build()
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeOp {
+    Insert { parent: Option<u32>, child: u32 },
+    Remove(u32),
+}
+
+/// A [`TreeOp`] on nodes below `max_id`.
+pub fn tree_op(max_id: u32) -> impl Strategy<Value = TreeOp> {
+    prop_oneof![
+        3 => (option::of(0..max_id), 0..max_id)
+            .prop_map(|(parent, child)| TreeOp::Insert { parent, child }),
+        1 => (0..max_id).prop_map(TreeOp::Remove),
+    ]
+}
+
+/// A base [`Tree`] and the ops of a [`TreeIndexLog`] staged on it.
+#[derive(Clone, Debug)]
+pub struct TreeCase {
+    pub entries: Vec<(u32, Option<u32>)>,
+    pub ops: Vec<TreeOp>,
+}
+
+impl TreeCase {
+    #[inline]
+    pub fn base(&self) -> Tree<u32> {
+        self.entries.iter().copied().collect()
+    }
+
+    /// Stages the ops on `base`. Inserts that would close a cycle are skipped,
+    /// as in [`TreeModel::insert`].
+    pub fn log(&self, base: &Tree<u32>) -> TreeIndexLog<u32> {
+        let mut log = TreeIndexLog::new();
+        let mut model = TreeModel::from_entries(&self.entries);
+
+        for op in &self.ops {
+            match *op {
+                TreeOp::Insert { parent, child } => {
+                    if model.insert(parent, child) {
+                        log.insert(base, parent, child);
+                    }
+                }
+                TreeOp::Remove(node) => {
+                    model.remove(node);
+                    log.remove(base, node);
+                }
+            }
+        }
+
+        log
+    }
+
+    /// The expected state once the log is applied.
+    pub fn model(&self) -> TreeModel {
+        let mut model = TreeModel::from_entries(&self.entries);
+
+        for op in &self.ops {
+            match *op {
+                TreeOp::Insert { parent, child } => {
+                    model.insert(parent, child);
+                }
+                TreeOp::Remove(node) => model.remove(node),
+            }
+        }
+
+        model
+    }
+}
+
+/// A [`TreeCase`] on a [`forest`] of up to `max_nodes` nodes with up to
+/// `max_ops` ops, touching a few nodes outside of the base.
+pub fn tree_case(max_nodes: usize, max_ops: usize) -> impl Strategy<Value = TreeCase> {
+    let max_id = max_nodes as u32 + 4;
+
+    (forest(max_nodes), vec(tree_op(max_id), 0..=max_ops))
+        .prop_map(|(entries, ops)| TreeCase { entries, ops })
+}
+
+/// Reference model of a [`Tree`] without cycles: a plain parent map.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeModel {
+    pub parents: BTreeMap<u32, Option<u32>>,
+}
+
+impl TreeModel {
+    pub fn from_entries(entries: &[(u32, Option<u32>)]) -> Self {
+        let mut model = Self::default();
+
+        for (child, parent) in entries {
+            model.insert(*parent, *child);
+        }
+
+        model
+    }
+
+    pub fn children(&self, node: u32) -> BTreeSet<u32> {
+        self.parents
+            .iter()
+            .filter(|(_, p)| **p == Some(node))
+            .map(|(c, _)| *c)
+            .collect()
+    }
+
+    pub fn descendants(&self, node: u32) -> BTreeSet<u32> {
+        let mut out = BTreeSet::new();
+        let mut stack = vec![node];
+
+        while let Some(n) = stack.pop() {
+            for c in self.children(n) {
+                if out.insert(c) {
+                    stack.push(c);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Attaches `child` under `parent`, adding both nodes. Returns `false`
+    /// and does nothing if that would close a cycle.
+    pub fn insert(&mut self, parent: Option<u32>, child: u32) -> bool {
+        if let Some(p) = parent {
+            if p == child || self.descendants(child).contains(&p) {
+                return false;
+            }
+
+            self.parents.entry(p).or_insert(None);
+        }
+
+        self.parents.insert(child, parent);
+        true
+    }
+
+    /// Removes `node` with its whole subtree.
+    pub fn remove(&mut self, node: u32) {
+        for n in self.descendants(node) {
+            self.parents.remove(&n);
+        }
+
+        self.parents.remove(&node);
+    }
+
+    /// Compares every node, parent, children and descendants of `tree` with
+    /// the model.
+    pub fn check(&self, tree: &Tree<u32>) -> Result<(), TestCaseError> {
+        let nodes = tree.all_nodes().collect::<BTreeSet<_>>();
+
+        prop_assert_eq!(&nodes, &self.parents.keys().copied().collect());
+        prop_assert_eq!(tree.cycles().count(), 0);
+
+        for (&n, &p) in &self.parents {
+            prop_assert_eq!(tree.parent(n), p, "parent of {}", n);
+            prop_assert_eq!(
+                tree.children(n).iter().collect::<BTreeSet<_>>(),
+                self.children(n),
+                "children of {}",
+                n
+            );
+            prop_assert_eq!(
+                tree.descendants(n).iter().collect::<BTreeSet<_>>(),
+                self.descendants(n),
+                "descendants of {}",
+                n
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlatSetIndexOp {
+    Insert(u32, u32),
+    InsertNone(u32),
+    Remove(u32, u32),
+    RemoveNone(u32),
+}
+
+/// A [`FlatSetIndexOp`] with keys below `max_key` and values below `max_value`.
+pub fn flat_set_index_op(max_key: u32, max_value: u32) -> impl Strategy<Value = FlatSetIndexOp> {
+    prop_oneof![
+        3 => (0..max_key, 0..max_value).prop_map(|(k, v)| FlatSetIndexOp::Insert(k, v)),
+        1 => (0..max_value).prop_map(FlatSetIndexOp::InsertNone),
+        2 => (0..max_key, 0..max_value).prop_map(|(k, v)| FlatSetIndexOp::Remove(k, v)),
+        1 => (0..max_value).prop_map(FlatSetIndexOp::RemoveNone),
+    ]
+}
+
+/// A base [`FlatSetIndex`] and the ops of a [`FlatSetIndexLog`] staged on it.
+#[derive(Clone, Debug)]
+pub struct FlatSetIndexCase {
+    pub base: Vec<FlatSetIndexOp>,
+    pub ops: Vec<FlatSetIndexOp>,
+}
+
+impl FlatSetIndexCase {
+    pub fn base(&self) -> FlatSetIndex<u32, u32> {
+        let mut base = FlatSetIndex::new();
+        base.apply(self.log_of(&base, &self.base));
+        base
+    }
+
+    #[inline]
+    pub fn log(&self, base: &FlatSetIndex<u32, u32>) -> FlatSetIndexLog<u32, u32> {
+        self.log_of(base, &self.ops)
+    }
+
+    /// The expected state once the log is applied.
+    pub fn model(&self) -> FlatSetIndexModel {
+        let mut model = FlatSetIndexModel::default();
+
+        for op in self.base.iter().chain(&self.ops) {
+            model.apply(*op);
+        }
+
+        model
+    }
+
+    fn log_of(
+        &self,
+        base: &FlatSetIndex<u32, u32>,
+        ops: &[FlatSetIndexOp],
+    ) -> FlatSetIndexLog<u32, u32> {
+        let mut log = FlatSetIndexLog::new();
+
+        for op in ops {
+            match *op {
+                FlatSetIndexOp::Insert(k, v) => {
+                    log.insert(base, k, v);
+                }
+                FlatSetIndexOp::InsertNone(v) => {
+                    log.insert_none(base, v);
+                }
+                FlatSetIndexOp::Remove(k, v) => {
+                    log.remove(base, k, v);
+                }
+                FlatSetIndexOp::RemoveNone(v) => {
+                    log.remove_none(base, v);
+                }
+            }
+        }
+
+        log
+    }
+}
+
+/// A [`FlatSetIndexCase`] of up to `max_ops` base ops and as many log ops.
+pub fn flat_set_index_case(
+    max_key: u32,
+    max_value: u32,
+    max_ops: usize,
+) -> impl Strategy<Value = FlatSetIndexCase> {
+    let ops = || vec(flat_set_index_op(max_key, max_value), 0..=max_ops);
+
+    (ops(), ops()).prop_map(|(base, ops)| FlatSetIndexCase { base, ops })
+}
+
+/// Reference model of a [`FlatSetIndex`]; keys with an empty set are absent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlatSetIndexModel {
+    pub keys: BTreeMap<u32, BTreeSet<u32>>,
+    pub none: BTreeSet<u32>,
+}
+
+impl FlatSetIndexModel {
+    pub fn apply(&mut self, op: FlatSetIndexOp) {
+        match op {
+            FlatSetIndexOp::Insert(k, v) => {
+                self.keys.entry(k).or_default().insert(v);
+            }
+            FlatSetIndexOp::InsertNone(v) => {
+                self.none.insert(v);
+            }
+            FlatSetIndexOp::Remove(k, v) => {
+                if let Some(set) = self.keys.get_mut(&k) {
+                    set.remove(&v);
+
+                    if set.is_empty() {
+                        self.keys.remove(&k);
+                    }
+                }
+            }
+            FlatSetIndexOp::RemoveNone(v) => {
+                self.none.remove(&v);
+            }
+        }
+    }
+
+    /// Compares the non-empty sets and the `none` set of `index` with the
+    /// model.
+    pub fn check(&self, index: &FlatSetIndex<u32, u32>) -> Result<(), TestCaseError> {
+        let keys = index
+            .iter()
+            .filter(|(_, s)| !s.is_empty())
+            .map(|(k, s)| (k, s.iter().collect::<BTreeSet<_>>()))
+            .collect::<BTreeMap<_, _>>();
+
+        prop_assert_eq!(&keys, &self.keys);
+        prop_assert_eq!(&index.none().iter().collect::<BTreeSet<_>>(), &self.none);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn tree_apply_matches_model(case in tree_case(24, 16)) {
+            let mut tree = case.base();
+            TreeModel::from_entries(&case.entries).check(&tree)?;
+
+            let log = case.log(&tree);
+            tree.apply(log);
+            case.model().check(&tree)?;
+        }
+
+        #[test]
+        fn flat_set_index_apply_matches_model(case in flat_set_index_case(8, 16, 32)) {
+            let mut index = case.base();
+            let log = case.log(&index);
+            index.apply(log);
+            case.model().check(&index)?;
+        }
+
+        #[test]
+        fn int_set_respects_bounds(set in int_set(50, 10)) {
+            prop_assert!(set.len() <= 10);
+            prop_assert!(set.iter().all(|v| v < 50));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+static MAX_ID: AtomicU32 = AtomicU32::new(u32::MAX - 1);
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// A stored `u32` that does not convert back to the key type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidKey(pub u32);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "value {} does not convert to the key type", self.0)
+    }
+}
+
+impl Error for InvalidKey {}
+
+/// A key above the configured [`max_id`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyOutOfRange {
+    pub key: u32,
+    pub max: u32,
+}
+
+impl fmt::Display for KeyOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key {} exceeds max id {}", self.key, self.max)
+    }
+}
+
+impl Error for KeyOutOfRange {}
+
+/// Largest id accepted by the checked inserts, `u32::MAX - 1` unless changed
+/// by [`set_max_id`], so that the `u32::MAX` sentinel is rejected by default.
+#[inline]
+pub fn max_id() -> u32 {
+    MAX_ID.load(Ordering::Relaxed)
+}
+
+/// Sets the default guard used by the checked inserts and builders, so that a
+/// sentinel or corrupted id is rejected before it sizes an allocation.
+#[inline]
+pub fn set_max_id(max: u32) {
+    MAX_ID.store(max, Ordering::Relaxed);
+}
+
+#[inline]
+pub(crate) fn check_key(key: u32, max: u32) -> Result<u32, KeyOutOfRange> {
+    if key <= max {
+        Ok(key)
+    } else {
+        Err(KeyOutOfRange { key, max })
+    }
+}
+
+/// Whether typed iterators panic on unconvertible values instead of skipping them.
+#[inline]
+pub fn is_strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+/// Makes typed iterators panic on values that fail `K::try_from`, so corrupted
+/// ids are detected instead of silently dropped.
+#[inline]
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Converts a stored value to `K`, following [`is_strict`] on failure. Generic
+/// over the value width for [`IntSet16`](crate::IntSet16) and
+/// [`IntSet64`](crate::IntSet64).
+#[inline]
+pub(crate) fn try_key<T, K>(v: T) -> Option<K>
+where
+    T: Copy + fmt::Display,
+    K: TryFrom<T>,
+{
+    match K::try_from(v) {
+        Ok(k) => Some(k),
+        Err(_) if is_strict() => panic!("value {v} does not convert to the key type"),
+        Err(_) => None,
+    }
+}
@@ -1,5 +1,8 @@
-use crate::{IntSet, u32based};
-use std::{fmt::Debug, marker::PhantomData};
+use crate::{IntSet, codec::DecodeError, strict::try_key, u32based};
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
 
 #[repr(transparent)]
 pub struct Tree<K> {
@@ -18,10 +21,7 @@ impl<K> Tree<K> {
     where
         K: TryFrom<u32>,
     {
-        self.erased
-            .all_nodes()
-            .iter()
-            .filter_map(|v| K::try_from(*v).ok())
+        self.erased.all_nodes().iter().filter_map(|v| try_key(*v))
     }
 
     #[inline]
@@ -29,6 +29,20 @@ impl<K> Tree<K> {
         self.erased.apply(log.erased)
     }
 
+    /// Like [`apply`](Self::apply), but returns the nodes whose parent changed
+    /// with their new parent.
+    #[inline]
+    pub fn apply_report(&mut self, log: TreeIndexLog<K>) -> Vec<(K, Option<K>)>
+    where
+        K: TryFrom<u32>,
+    {
+        self.erased
+            .apply_report(log.erased)
+            .into_iter()
+            .filter_map(|(n, p)| Some((try_key(n)?, p.and_then(try_key))))
+            .collect()
+    }
+
     #[inline]
     pub fn children(&self, parent: K) -> &IntSet<K>
     where
@@ -45,7 +59,7 @@ impl<K> Tree<K> {
         self.erased
             .children_with_self(node.into())
             .into_iter()
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 
     #[inline]
@@ -64,7 +78,7 @@ impl<K> Tree<K> {
         self.erased
             .descendants_with_self(node.into())
             .into_iter()
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 
     #[inline]
@@ -72,7 +86,7 @@ impl<K> Tree<K> {
     where
         K: TryFrom<u32>,
     {
-        self.erased.cycles().filter_map(|k| K::try_from(*k).ok())
+        self.erased.cycles().filter_map(|k| try_key(*k))
     }
 
     #[inline]
@@ -80,9 +94,7 @@ impl<K> Tree<K> {
     where
         K: TryFrom<u32> + Into<u32>,
     {
-        self.erased
-            .parent(child.into())
-            .and_then(|k| K::try_from(k).ok())
+        self.erased.parent(child.into()).and_then(try_key)
     }
 
     #[inline]
@@ -117,9 +129,7 @@ impl<K> Tree<K> {
     where
         K: TryFrom<u32> + Into<u32>,
     {
-        self.erased
-            .ancestors(child.into())
-            .filter_map(|k| K::try_from(k).ok())
+        self.erased.ancestors(child.into()).filter_map(try_key)
     }
 
     #[inline]
@@ -129,7 +139,7 @@ impl<K> Tree<K> {
     {
         self.erased
             .ancestors_with_self(child.into())
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 }
 
@@ -143,6 +153,18 @@ impl<K> Clone for Tree<K> {
     }
 }
 
+/// Lists each node with its parent, in erased ids sorted by node.
+impl<K> Debug for Tree<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut nodes = self.erased.all_nodes().iter().copied().collect::<Vec<_>>();
+        nodes.sort_unstable();
+
+        f.debug_map()
+            .entries(nodes.into_iter().map(|n| (n, self.erased.parent(n))))
+            .finish()
+    }
+}
+
 impl<K> Default for Tree<K> {
     #[inline]
     fn default() -> Self {
@@ -168,6 +190,27 @@ where
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<K> rayon::iter::FromParallelIterator<(K, Option<K>)> for Tree<K>
+where
+    K: Into<u32> + Send,
+{
+    fn from_par_iter<I>(iter: I) -> Self
+    where
+        I: rayon::iter::IntoParallelIterator<Item = (K, Option<K>)>,
+    {
+        use rayon::prelude::*;
+
+        Self {
+            erased: iter
+                .into_par_iter()
+                .map(|(n, p)| (n.into(), p.map(Into::into)))
+                .collect(),
+            _k: PhantomData,
+        }
+    }
+}
+
 #[repr(transparent)]
 pub struct TreeIndexLog<K> {
     pub(crate) erased: u32based::TreeLog,
@@ -180,6 +223,22 @@ impl<K> TreeIndexLog<K> {
         Default::default()
     }
 
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    #[inline]
+    pub fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            erased: u32based::TreeLog::from_wal_bytes(bytes)?,
+            _k: PhantomData,
+        })
+    }
+
+    /// Encodes the log as a write-ahead log record, to be replayed in order
+    /// against the same base.
+    #[inline]
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        self.erased.to_wal_bytes()
+    }
+
     #[inline]
     pub fn children<'a>(&'a self, base: &'a Tree<K>, parent: K) -> &'a IntSet<K>
     where
@@ -200,7 +259,7 @@ impl<K> TreeIndexLog<K> {
         self.erased
             .children_with_self(&base.erased, node.into())
             .into_iter()
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 
     #[inline]
@@ -223,7 +282,7 @@ impl<K> TreeIndexLog<K> {
         self.erased
             .descendants_with_self(&base.erased, node.into())
             .into_iter()
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 
     #[inline]
@@ -234,7 +293,7 @@ impl<K> TreeIndexLog<K> {
         self.erased
             .cycles(&base.erased)
             .iter()
-            .filter_map(|k| K::try_from(*k).ok())
+            .filter_map(|k| try_key(*k))
     }
 
     pub fn depth(&self, base: &Tree<K>, node: K) -> Result<usize, CycleError<K>>
@@ -262,7 +321,7 @@ impl<K> TreeIndexLog<K> {
     {
         self.erased
             .parent(&base.erased, child.into())
-            .and_then(|k| K::try_from(k).ok())
+            .and_then(try_key)
     }
 
     #[inline]
@@ -302,7 +361,7 @@ impl<K> TreeIndexLog<K> {
     {
         self.erased
             .ancestors(&base.erased, child.into())
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 
     #[inline]
@@ -316,7 +375,7 @@ impl<K> TreeIndexLog<K> {
     {
         self.erased
             .ancestors_with_self(&base.erased, child.into())
-            .filter_map(|k| K::try_from(k).ok())
+            .filter_map(try_key)
     }
 }
 
@@ -341,8 +400,8 @@ impl<K> Default for TreeIndexLog<K> {
 }
 
 pub struct TreeTrx<'a, K> {
-    base: &'a Tree<K>,
-    log: &'a TreeIndexLog<K>,
+    pub(crate) base: &'a Tree<K>,
+    pub(crate) log: &'a TreeIndexLog<K>,
 }
 
 impl<'a, K> TreeTrx<'a, K> {
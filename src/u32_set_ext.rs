@@ -0,0 +1,113 @@
+use crate::U32Set;
+use std::{collections::BinaryHeap, ops::RangeBounds};
+
+/// Helpers on the raw [`U32Set`] that the std `HashSet` does not provide.
+pub trait U32SetExt {
+    /// Membership of each of `values`, in the same order.
+    fn contains_many(&self, values: &[u32]) -> Vec<bool>;
+
+    fn max_value(&self) -> Option<u32>;
+
+    fn min_value(&self) -> Option<u32>;
+
+    /// Values falling in `range`, in ascending order.
+    fn range_sorted<R: RangeBounds<u32>>(&self, range: R) -> Vec<u32>;
+
+    /// The `n` smallest values, in ascending order.
+    fn smallest(&self, n: usize) -> Vec<u32>;
+
+    /// Every value, in ascending order.
+    fn sorted(&self) -> Vec<u32>;
+}
+
+impl U32SetExt for U32Set {
+    #[inline]
+    fn contains_many(&self, values: &[u32]) -> Vec<bool> {
+        values.iter().map(|v| self.contains(v)).collect()
+    }
+
+    #[inline]
+    fn max_value(&self) -> Option<u32> {
+        self.iter().copied().max()
+    }
+
+    #[inline]
+    fn min_value(&self) -> Option<u32> {
+        self.iter().copied().min()
+    }
+
+    #[inline]
+    fn range_sorted<R: RangeBounds<u32>>(&self, range: R) -> Vec<u32> {
+        range_sorted(self.iter().copied(), range)
+    }
+
+    #[inline]
+    fn smallest(&self, n: usize) -> Vec<u32> {
+        smallest(self.iter().copied(), n)
+    }
+
+    fn sorted(&self) -> Vec<u32> {
+        let mut v = self.iter().copied().collect::<Vec<_>>();
+        v.sort_unstable();
+        v
+    }
+}
+
+/// Backend-agnostic [`U32SetExt::range_sorted`].
+pub(crate) fn range_sorted<R: RangeBounds<u32>>(
+    values: impl Iterator<Item = u32>,
+    range: R,
+) -> Vec<u32> {
+    let mut v = values.filter(|v| range.contains(v)).collect::<Vec<_>>();
+    v.sort_unstable();
+    v
+}
+
+/// Backend-agnostic [`U32SetExt::smallest`].
+pub(crate) fn smallest(values: impl Iterator<Item = u32>, n: usize) -> Vec<u32> {
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // max-heap holding the `n` smallest values seen so far
+    let mut heap = BinaryHeap::with_capacity(n.min(values.size_hint().0) + 1);
+
+    for v in values {
+        if heap.len() < n {
+            heap.push(v);
+        } else if heap.peek().is_some_and(|&top| v < top) {
+            heap.pop();
+            heap.push(v);
+        }
+    }
+
+    heap.into_sorted_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smallest_is_sorted_and_bounded() {
+        let set = U32Set::from_iter([9, 3, 7, 1, 5]);
+
+        assert_eq!(set.smallest(3), vec![1, 3, 5]);
+        assert_eq!(set.smallest(10), vec![1, 3, 5, 7, 9]);
+        assert!(set.smallest(0).is_empty());
+        assert_eq!(set.min_value(), Some(1));
+        assert_eq!(set.max_value(), Some(9));
+        assert_eq!(U32Set::default().min_value(), None);
+    }
+
+    #[test]
+    fn range_sorted_respects_bounds() {
+        let set = U32Set::from_iter([5, 1, 4, 2, 3]);
+
+        assert_eq!(set.sorted(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(set.range_sorted(2..4), vec![2, 3]);
+        assert_eq!(set.range_sorted(4..), vec![4, 5]);
+        assert_eq!(set.range_sorted(..=1), vec![1]);
+        assert_eq!(set.contains_many(&[3, 9, 1]), vec![true, false, true]);
+    }
+}
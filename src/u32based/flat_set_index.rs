@@ -1,8 +1,21 @@
-use crate::{U32Set, default_iu32_hashset};
+use crate::{
+    U32Set,
+    codec::{
+        DecodeError, decode_option_set, decode_set_map, encode_option_set, encode_set_map,
+        expect_end,
+    },
+    default_iu32_hashset,
+};
 use intern::IU32HashSet;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::{
     borrow::Borrow,
-    collections::hash_map::{self, Entry, HashMap, Keys},
+    collections::{
+        hash_map::{self, Entry, HashMap, Keys},
+        hash_set,
+    },
     hash::{BuildHasher, Hash, RandomState},
 };
 
@@ -11,10 +24,38 @@ pub type U32FlatSetIndexBuilder = FlatSetIndexBuilder<u32, rustc_hash::FxBuildHa
 pub type U32FlatSetIndexLog = FlatSetIndexLog<u32, rustc_hash::FxBuildHasher>;
 
 pub struct FlatSetIndex<K, S = RandomState> {
+    inverse: Option<Box<Inverse<K>>>,
     map: HashMap<K, IU32HashSet, S>,
     none: IU32HashSet,
 }
 
+/// Optional value -> keys map, kept in sync by `apply`.
+struct Inverse<K> {
+    clone_key: fn(&K) -> K,
+    map: FxHashMap<u32, FxHashSet<K>>,
+}
+
+impl<K: Eq + Hash> Inverse<K> {
+    fn update(&mut self, key: &K, old: &U32Set, new: &U32Set) {
+        for v in old.difference(new) {
+            if let Entry::Occupied(mut o) = self.map.entry(*v) {
+                o.get_mut().remove(key);
+
+                if o.get().is_empty() {
+                    o.remove();
+                }
+            }
+        }
+
+        for v in new.difference(old) {
+            self.map
+                .entry(*v)
+                .or_default()
+                .insert((self.clone_key)(key));
+        }
+    }
+}
+
 impl<K> FlatSetIndex<K, RandomState> {
     #[inline]
     pub fn new() -> Self {
@@ -31,6 +72,7 @@ impl<K, S> FlatSetIndex<K, S> {
     #[inline]
     pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         Self {
+            inverse: None,
             map: HashMap::with_capacity_and_hasher(capacity, hasher),
             none: Default::default(),
         }
@@ -39,6 +81,7 @@ impl<K, S> FlatSetIndex<K, S> {
     #[inline]
     pub fn with_hasher(hasher: S) -> Self {
         Self {
+            inverse: None,
             map: HashMap::with_hasher(hasher),
             none: IU32HashSet::default(),
         }
@@ -55,15 +98,27 @@ impl<K, S> FlatSetIndex<K, S> {
             match self.map.entry(key) {
                 Entry::Occupied(mut o) => {
                     if val.is_empty() {
+                        if let Some(inverse) = &mut self.inverse {
+                            inverse.update(o.key(), o.get().as_set(), &val);
+                        }
+
                         o.remove();
                         changed = true;
                     } else if *o.get() != val {
+                        if let Some(inverse) = &mut self.inverse {
+                            inverse.update(o.key(), o.get().as_set(), &val);
+                        }
+
                         o.insert(val.into());
                         changed = true;
                     }
                 }
                 Entry::Vacant(v) => {
                     if !val.is_empty() {
+                        if let Some(inverse) = &mut self.inverse {
+                            inverse.update(v.key(), &U32Set::default(), &val);
+                        }
+
                         changed = true;
                         v.insert(val.into());
                     }
@@ -81,6 +136,80 @@ impl<K, S> FlatSetIndex<K, S> {
         changed
     }
 
+    /// Like [`apply`](Self::apply), but returns the keys whose set changed and
+    /// whether the `none` set changed.
+    pub fn apply_report(&mut self, log: FlatSetIndexLog<K, S>) -> (Vec<K>, bool)
+    where
+        K: Clone + Eq + Hash,
+        S: BuildHasher,
+    {
+        let keys = log
+            .map
+            .iter()
+            .filter(|(k, v)| match self.map.get(*k) {
+                Some(old) => old != *v,
+                None => !v.is_empty(),
+            })
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let none = log.none.as_ref().is_some_and(|n| self.none != *n);
+
+        self.apply(log);
+        (keys, none)
+    }
+
+    /// Like [`apply`](Self::apply), but compares and interns the staged sets
+    /// on the rayon pool before updating the map.
+    ///
+    /// The interner is global and synchronized, so sets interned from worker
+    /// threads are shared with equal sets interned anywhere else.
+    #[cfg(feature = "rayon")]
+    pub fn par_apply(&mut self, log: FlatSetIndexLog<K, S>) -> bool
+    where
+        K: Eq + Hash + Send + Sync,
+        S: BuildHasher + Sync,
+    {
+        let updates = log
+            .map
+            .into_par_iter()
+            .filter_map(|(k, v)| match self.map.get(&k) {
+                Some(old) if *old == v => None,
+                None if v.is_empty() => None,
+                _ => Some((k, (!v.is_empty()).then(|| IU32HashSet::from(v)))),
+            })
+            .collect::<Vec<_>>();
+
+        let mut changed = !updates.is_empty();
+
+        for (k, v) in updates {
+            if let Some(inverse) = &mut self.inverse {
+                let empty = default_iu32_hashset().as_set();
+                let old = self.map.get(&k).map_or(empty, IU32HashSet::as_set);
+                let new = v.as_ref().map_or(empty, IU32HashSet::as_set);
+                inverse.update(&k, old, new);
+            }
+
+            match v {
+                Some(v) => {
+                    self.map.insert(k, v);
+                }
+                None => {
+                    self.map.remove(&k);
+                }
+            }
+        }
+
+        if let Some(log) = log.none
+            && self.none != log
+        {
+            self.none = log.into();
+            changed = true;
+        }
+
+        changed
+    }
+
     #[inline]
     pub fn contains<Q>(&self, k: &Q, val: u32) -> bool
     where
@@ -96,6 +225,125 @@ impl<K, S> FlatSetIndex<K, S> {
         self.none.as_set().contains(&val)
     }
 
+    /// Produces a log that, once applied to `self`, yields `other`. Sets shared
+    /// by both sides are skipped without comparing their content.
+    pub fn diff(&self, other: &Self) -> FlatSetIndexLog<K, S>
+    where
+        K: Clone + Eq + Hash,
+        S: BuildHasher + Clone,
+    {
+        // Interned sets are usually shared: check identity before content.
+        fn same_set(a: &IU32HashSet, b: &IU32HashSet) -> bool {
+            std::ptr::eq(a.as_set(), b.as_set()) || a.as_set() == b.as_set()
+        }
+
+        let mut log = FlatSetIndexLog::with_hasher(self.map.hasher().clone());
+
+        for (k, v) in &other.map {
+            if !self.map.get(k).is_some_and(|s| same_set(s, v)) {
+                log.map.insert(k.clone(), v.as_set().clone());
+            }
+        }
+
+        for k in self.map.keys() {
+            if !other.map.contains_key(k) {
+                log.map.insert(k.clone(), U32Set::default());
+            }
+        }
+
+        if !same_set(&self.none, &other.none) {
+            log.none = Some(other.none.as_set().clone());
+        }
+
+        log
+    }
+
+    /// Removes from every key the values `other` holds for that same key.
+    /// Returns `true` if anything changed.
+    pub fn difference_with(&mut self, other: &Self) -> bool
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut changed = false;
+
+        for (k, rhs) in &other.map {
+            let Some(lhs) = self.map.get_mut(k) else {
+                continue;
+            };
+
+            if lhs.as_set().is_disjoint(rhs.as_set()) {
+                continue;
+            }
+
+            let set = lhs.as_set() - rhs.as_set();
+
+            if let Some(inverse) = &mut self.inverse {
+                inverse.update(k, lhs.as_set(), &set);
+            }
+
+            if set.is_empty() {
+                self.map.remove(k);
+            } else {
+                *lhs = set.into();
+            }
+
+            changed = true;
+        }
+
+        if !self.none.as_set().is_disjoint(other.none.as_set()) {
+            self.none = (self.none.as_set() - other.none.as_set()).into();
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Starts maintaining a value -> keys map so that [`keys_of`](Self::keys_of)
+    /// no longer scans every key. Does nothing if already enabled.
+    pub fn enable_inverse(&mut self)
+    where
+        K: Clone + Eq + Hash,
+    {
+        if self.inverse.is_some() {
+            return;
+        }
+
+        let mut inverse = Inverse {
+            clone_key: K::clone,
+            map: FxHashMap::default(),
+        };
+
+        let empty = U32Set::default();
+
+        for (k, v) in &self.map {
+            inverse.update(k, &empty, v.as_set());
+        }
+
+        self.inverse = Some(Box::new(inverse));
+    }
+
+    /// Removes every key for which `f` returns `true` and returns them with an
+    /// owned copy of their set, releasing the interned handles.
+    pub fn extract_if<F>(&mut self, mut f: F) -> Vec<(K, U32Set)>
+    where
+        F: FnMut(&K, &U32Set) -> bool,
+        K: Eq + Hash,
+    {
+        let empty = U32Set::default();
+
+        self.map
+            .extract_if(|k, v| f(k, v.as_set()))
+            .map(|(k, v)| {
+                if let Some(inverse) = &mut self.inverse {
+                    inverse.update(&k, v.as_set(), &empty);
+                }
+
+                (k, v.as_set().clone())
+            })
+            .collect()
+    }
+
     #[inline]
     pub fn get<Q>(&self, k: &Q) -> &IU32HashSet
     where
@@ -106,6 +354,17 @@ impl<K, S> FlatSetIndex<K, S> {
         self.map.get(k).unwrap_or_else(|| default_iu32_hashset())
     }
 
+    #[inline]
+    pub fn has_inverse(&self) -> bool {
+        self.inverse.is_some()
+    }
+
+    /// `true` when no key holds any value.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     #[inline]
     pub fn iter(&self) -> hash_map::Iter<'_, K, IU32HashSet> {
         self.map.iter()
@@ -116,11 +375,136 @@ impl<K, S> FlatSetIndex<K, S> {
         self.map.keys()
     }
 
+    /// Number of values held by the key `k`.
+    #[inline]
+    pub fn key_cardinality<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.map.get(k).map_or(0, |s| s.as_set().len())
+    }
+
+    /// Keys whose set contains `val`. Uses the inverse map when enabled,
+    /// otherwise scans every key.
+    pub fn keys_of(&self, val: u32) -> KeysOf<'_, K> {
+        match &self.inverse {
+            Some(inverse) => match inverse.map.get(&val) {
+                Some(keys) => KeysOf::Inverse(keys.iter()),
+                None => KeysOf::Empty,
+            },
+            None => KeysOf::Scan(self.map.iter(), val),
+        }
+    }
+
+    /// Number of keys holding at least one value.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
     #[inline]
     pub fn none(&self) -> &IU32HashSet {
         &self.none
     }
 
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (&K, &IU32HashSet)>
+    where
+        K: Eq + Hash + Sync,
+        S: BuildHasher + Sync,
+    {
+        self.map.par_iter()
+    }
+
+    #[cfg(feature = "rayon")]
+    #[inline]
+    pub fn par_values(&self) -> impl ParallelIterator<Item = &IU32HashSet>
+    where
+        K: Eq + Hash + Sync,
+        S: BuildHasher + Sync,
+    {
+        self.map.par_iter().map(|(_, v)| v)
+    }
+
+    /// Union of every set, `none` included, merged on the rayon pool.
+    #[cfg(feature = "rayon")]
+    pub fn par_union_values(&self) -> U32Set
+    where
+        K: Eq + Hash + Sync,
+        S: BuildHasher + Sync,
+    {
+        self.par_values()
+            .fold(U32Set::default, |mut acc, s| {
+                acc.extend(s.as_set());
+                acc
+            })
+            .reduce_with(|a, b| {
+                let (mut large, small) = if a.len() < b.len() { (b, a) } else { (a, b) };
+                large.extend(small);
+                large
+            })
+            .map_or_else(
+                || self.none.as_set().clone(),
+                |mut b| {
+                    b.extend(self.none.as_set());
+                    b
+                },
+            )
+    }
+
+    /// Adds to every key the values `other` holds for that same key, sharing
+    /// the interned sets of keys missing from `self`.
+    /// Returns `true` if anything changed.
+    pub fn union_with(&mut self, other: &Self) -> bool
+    where
+        K: Clone + Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut changed = false;
+
+        for (k, rhs) in &other.map {
+            match self.map.get_mut(k) {
+                Some(lhs) => {
+                    if rhs.as_set().is_subset(lhs.as_set()) {
+                        continue;
+                    }
+
+                    let set = lhs.as_set() | rhs.as_set();
+
+                    if let Some(inverse) = &mut self.inverse {
+                        inverse.update(k, lhs.as_set(), &set);
+                    }
+
+                    *lhs = set.into();
+                }
+                None => {
+                    if let Some(inverse) = &mut self.inverse {
+                        inverse.update(k, &U32Set::default(), rhs.as_set());
+                    }
+
+                    self.map.insert(k.clone(), rhs.clone());
+                }
+            }
+
+            changed = true;
+        }
+
+        if !other.none.as_set().is_subset(self.none.as_set()) {
+            self.none = (self.none.as_set() | other.none.as_set()).into();
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Number of `(key, value)` pairs, excluding the `none` set.
+    pub fn value_count(&self) -> usize {
+        self.map.values().map(|s| s.as_set().len()).sum()
+    }
+
     pub fn values(&self) -> U32Set {
         let mut b = self.none.as_set().clone();
 
@@ -132,16 +516,64 @@ impl<K, S> FlatSetIndex<K, S> {
     }
 }
 
+impl<K: Eq + Hash, S: BuildHasher + Default> FlatSetIndex<K, S> {
+    /// Builds the index from sets that are already interned, skipping empty ones.
+    pub(crate) fn from_interned<I>(iter: I, none: IU32HashSet) -> Self
+    where
+        I: IntoIterator<Item = (K, IU32HashSet)>,
+    {
+        Self {
+            inverse: None,
+            map: iter
+                .into_iter()
+                .filter(|(_, s)| !s.as_set().is_empty())
+                .collect(),
+            none,
+        }
+    }
+}
+
 impl<K: Clone, S: Clone> Clone for FlatSetIndex<K, S> {
     #[inline]
     fn clone(&self) -> Self {
         Self {
+            inverse: self.inverse.clone(),
             map: self.map.clone(),
             none: self.none.clone(),
         }
     }
 }
 
+impl<K: Clone> Clone for Inverse<K> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            clone_key: self.clone_key,
+            map: self.map.clone(),
+        }
+    }
+}
+
+pub enum KeysOf<'a, K> {
+    Empty,
+    Inverse(hash_set::Iter<'a, K>),
+    Scan(hash_map::Iter<'a, K, IU32HashSet>, u32),
+}
+
+impl<'a, K> Iterator for KeysOf<'a, K> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Empty => None,
+            Self::Inverse(it) => it.next(),
+            Self::Scan(it, val) => it
+                .find(|(_, set)| set.as_set().contains(val))
+                .map(|(k, _)| k),
+        }
+    }
+}
+
 impl<K, S: Default> Default for FlatSetIndex<K, S> {
     #[inline]
     fn default() -> Self {
@@ -312,6 +744,13 @@ impl<K, S> FlatSetIndexLog<K, S> {
         }
     }
 
+    /// Drops every staged change while keeping the allocated capacity.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.none = None;
+    }
+
     #[inline]
     pub fn contains<Q>(&self, base: &FlatSetIndex<K, S>, k: &Q, val: u32) -> bool
     where
@@ -347,6 +786,23 @@ impl<K, S> FlatSetIndexLog<K, S> {
         *v = v.difference(rhs).copied().collect();
     }
 
+    /// Drops the staged changes of a single key. Returns `true` if the key was staged.
+    #[inline]
+    pub fn discard_key<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.map.remove(k).is_some()
+    }
+
+    /// Drops the staged changes of the `none` set. Returns `true` if it was staged.
+    #[inline]
+    pub fn discard_none(&mut self) -> bool {
+        self.none.take().is_some()
+    }
+
     #[inline]
     pub fn get<'a, Q>(&'a self, base: &'a FlatSetIndex<K, S>, k: &Q) -> &'a U32Set
     where
@@ -388,6 +844,38 @@ impl<K, S> FlatSetIndexLog<K, S> {
         self.none_mut(base).insert(val)
     }
 
+    /// `true` when nothing is staged.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty() && self.none.is_none()
+    }
+
+    #[inline]
+    pub fn key_cardinality<Q>(&self, base: &FlatSetIndex<K, S>, k: &Q) -> usize
+    where
+        K: Borrow<Q> + Eq + Hash,
+        Q: ?Sized + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.get(base, k).len()
+    }
+
+    /// Number of keys holding at least one value once this log is applied to `base`.
+    pub fn len(&self, base: &FlatSetIndex<K, S>) -> usize
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut len = base.len();
+
+        for (k, v) in &self.map {
+            len -= usize::from(base.map.contains_key(k));
+            len += usize::from(!v.is_empty());
+        }
+
+        len
+    }
+
     pub fn intersection(&mut self, base: &FlatSetIndex<K, S>, key: K, rhs: &U32Set)
     where
         K: Eq + Hash,
@@ -428,6 +916,39 @@ impl<K, S> FlatSetIndexLog<K, S> {
         self.none_mut(base).remove(&val)
     }
 
+    /// Number of `(key, value)` pairs once this log is applied to `base`.
+    pub fn value_count(&self, base: &FlatSetIndex<K, S>) -> usize
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut count = base.value_count();
+
+        for (k, v) in &self.map {
+            count -= base.key_cardinality(k);
+            count += v.len();
+        }
+
+        count
+    }
+
+    /// Returns the staged changes, leaving this log empty and ready for reuse.
+    /// The entries move to a map sized to fit them, so this log keeps its
+    /// capacity.
+    pub fn take(&mut self) -> Self
+    where
+        K: Eq + Hash,
+        S: BuildHasher + Clone,
+    {
+        let mut map = HashMap::with_capacity_and_hasher(self.map.len(), self.map.hasher().clone());
+        map.extend(self.map.drain());
+
+        Self {
+            map,
+            none: self.none.take(),
+        }
+    }
+
     pub fn union(&mut self, base: &FlatSetIndex<K, S>, key: K, rhs: &U32Set)
     where
         K: Eq + Hash,
@@ -441,6 +962,25 @@ impl<K, S> FlatSetIndexLog<K, S> {
     }
 }
 
+impl<S: BuildHasher + Default> FlatSetIndexLog<u32, S> {
+    /// Encodes the staged sets as a write-ahead log record.
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_set_map(&self.map, &mut out);
+        encode_option_set(self.none.as_ref(), &mut out);
+        out
+    }
+
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    pub fn from_wal_bytes(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let map = decode_set_map(&mut bytes)?;
+        let none = decode_option_set(&mut bytes)?;
+
+        expect_end(bytes)?;
+        Ok(Self { map, none })
+    }
+}
+
 impl<K, S: Default> Default for FlatSetIndexLog<K, S> {
     #[inline]
     fn default() -> Self {
@@ -460,6 +1000,41 @@ mod tests {
 
     /* ---------- basic consistency ---------- */
 
+    #[test]
+    fn inverse_follows_apply() {
+        let mut idx = FlatSetIndex::new();
+        idx.enable_inverse();
+
+        let mut log = FlatSetIndexLog::new();
+        log.insert(&idx, 1, 10);
+        log.insert(&idx, 2, 10);
+        log.insert(&idx, 2, 20);
+        idx.apply(log);
+
+        let mut keys = idx.keys_of(10).copied().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1, 2]);
+
+        let mut log = FlatSetIndexLog::new();
+        log.remove(&idx, 1, 10);
+        idx.apply(log);
+
+        assert_eq!(idx.keys_of(10).copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(idx.keys_of(20).copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(idx.keys_of(30).count(), 0);
+    }
+
+    #[test]
+    fn keys_of_scans_without_inverse() {
+        let mut builder = FlatSetIndexBuilder::new();
+        builder.insert(1, 10);
+        builder.insert(2, 20);
+        let idx = builder.build();
+
+        assert!(!idx.has_inverse());
+        assert_eq!(idx.keys_of(20).copied().collect::<Vec<_>>(), vec![2]);
+    }
+
     #[test]
     fn empty_index_is_consistent() {
         let idx = FlatSetIndex::<u32, _>::new();
@@ -559,6 +1134,93 @@ mod tests {
         }
     }
 
+    #[test]
+    fn diff_then_apply_yields_other() {
+        let mut a = FlatSetIndexBuilder::new();
+        a.union(1, &bitmap(&[1, 2]));
+        a.union(2, &bitmap(&[3]));
+        a.insert_none(9);
+        let mut a = a.build();
+
+        let mut b = FlatSetIndexBuilder::new();
+        b.union(1, &bitmap(&[2]));
+        b.union(3, &bitmap(&[4]));
+        let b = b.build();
+
+        assert!(a.apply(a.diff(&b)));
+        assert!(a.diff(&b).is_empty());
+        assert_eq!(*a.get(&1).as_set(), bitmap(&[2]));
+        assert!(a.get(&2).as_set().is_empty());
+        assert_eq!(*a.get(&3).as_set(), bitmap(&[4]));
+        assert!(a.none().as_set().is_empty());
+    }
+
+    #[test]
+    fn extract_if_removes_matching_keys() {
+        let mut b = FlatSetIndexBuilder::new();
+        b.union(1, &bitmap(&[1]));
+        b.union(2, &bitmap(&[2]));
+        b.union(3, &bitmap(&[2, 3]));
+        let mut idx = b.build();
+        idx.enable_inverse();
+
+        let mut removed = idx.extract_if(|k, _| *k >= 2);
+        removed.sort_unstable_by_key(|(k, _)| *k);
+
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[1], (3, bitmap(&[2, 3])));
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx.keys_of(2).count(), 0);
+    }
+
+    #[test]
+    fn log_aware_counts() {
+        let mut b = FlatSetIndexBuilder::new();
+        b.union(1, &bitmap(&[1, 2]));
+        b.union(2, &bitmap(&[3]));
+        let base = b.build();
+
+        assert_eq!(base.len(), 2);
+        assert_eq!(base.value_count(), 3);
+        assert_eq!(base.key_cardinality(&1), 2);
+
+        let mut log = FlatSetIndexLog::new();
+        log.remove(&base, 2, 3);
+        log.insert(&base, 3, 4);
+        log.insert(&base, 1, 5);
+
+        assert_eq!(log.len(&base), 2);
+        assert_eq!(log.value_count(&base), 4);
+        assert_eq!(log.key_cardinality(&base, &1), 3);
+        assert_eq!(log.key_cardinality(&base, &2), 0);
+    }
+
+    #[test]
+    fn union_with_and_difference_with() {
+        let mut a = FlatSetIndexBuilder::new();
+        a.union(1, &bitmap(&[1, 2]));
+        a.insert_none(7);
+        let mut a = a.build();
+        a.enable_inverse();
+
+        let mut b = FlatSetIndexBuilder::new();
+        b.union(1, &bitmap(&[2, 3]));
+        b.union(2, &bitmap(&[4]));
+        let b = b.build();
+
+        assert!(a.union_with(&b));
+        assert!(!a.union_with(&b));
+        assert_eq!(*a.get(&1).as_set(), bitmap(&[1, 2, 3]));
+        assert_eq!(*a.get(&2).as_set(), bitmap(&[4]));
+        assert_eq!(a.keys_of(4).copied().collect::<Vec<_>>(), vec![2]);
+
+        assert!(a.difference_with(&b));
+        assert_eq!(*a.get(&1).as_set(), bitmap(&[1]));
+        assert!(a.get(&2).as_set().is_empty());
+        assert_eq!(a.keys_of(4).count(), 0);
+        assert!(a.contains_none(7));
+    }
+
     /* ---------- log-only consistency ---------- */
 
     #[test]
@@ -576,6 +1238,26 @@ mod tests {
         assert!(log.contains_none(&base, 20));
     }
 
+    #[test]
+    fn take_leaves_log_empty() {
+        let mut base = FlatSetIndex::new();
+        let mut log = FlatSetIndexLog::new();
+
+        log.insert(&base, 1, 10);
+        log.insert(&base, 2, 20);
+        log.insert_none(&base, 30);
+        assert!(log.discard_key(&2));
+        assert!(!log.discard_key(&2));
+
+        let capacity = log.map.capacity();
+        assert!(base.apply(log.take()));
+        assert!(log.is_empty());
+        assert_eq!(log.map.capacity(), capacity);
+        assert!(base.contains(&1, 10));
+        assert!(!base.contains(&2, 20));
+        assert!(base.contains_none(30));
+    }
+
     /* ---------- miri-friendly threaded stress ---------- */
 
     #[test]
@@ -599,4 +1281,92 @@ mod tests {
             assert!(!idx.get(&0).as_set().is_empty());
         }
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_apply_matches_apply() {
+        let mut seq = U32FlatSetIndex::default();
+        seq.enable_inverse();
+
+        let mut log = U32FlatSetIndexLog::default();
+
+        for k in 0..200 {
+            log.insert(&seq, k, k % 7);
+        }
+
+        log.insert_none(&seq, 99);
+        seq.apply(log);
+
+        let mut par = seq.clone();
+        let stage = |base: &U32FlatSetIndex| {
+            let mut log = U32FlatSetIndexLog::default();
+
+            for k in 0..100 {
+                log.remove(base, k, k % 7);
+                log.insert(base, k + 150, 1000);
+            }
+
+            log
+        };
+
+        let log = stage(&par);
+        assert!(par.par_apply(log));
+        assert!(seq.apply(stage(&seq)));
+        assert!(!par.par_apply(stage(&seq)));
+
+        assert_eq!(par.len(), seq.len());
+        assert_eq!(par.par_union_values(), seq.values());
+
+        for (k, v) in seq.iter() {
+            assert!(*par.get(k) == *v.as_set());
+        }
+
+        assert_eq!(par.keys_of(1000).count(), 100);
+        assert_eq!(par.keys_of(3).count(), seq.keys_of(3).count());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter_matches_iter() {
+        fn sorted(set: &IU32HashSet) -> Vec<u32> {
+            let mut vec = set.as_set().iter().copied().collect::<Vec<_>>();
+            vec.sort_unstable();
+            vec
+        }
+
+        let mut index = U32FlatSetIndex::default();
+        let mut log = U32FlatSetIndexLog::default();
+
+        for k in 0..300 {
+            log.insert(&index, k, k % 13);
+            log.insert(&index, k, k / 3);
+        }
+
+        index.apply(log);
+
+        let mut seq = index
+            .iter()
+            .map(|(k, v)| (*k, sorted(v)))
+            .collect::<Vec<_>>();
+
+        let mut par = index
+            .par_iter()
+            .map(|(k, v)| (*k, sorted(v)))
+            .collect::<Vec<_>>();
+
+        seq.sort_unstable();
+        par.sort_unstable();
+        assert_eq!(par, seq);
+
+        let values =
+            index
+                .par_values()
+                .map(|v| v.as_set().clone())
+                .reduce(U32Set::default, |mut a, b| {
+                    a.extend(b);
+                    a
+                });
+
+        assert_eq!(values, index.values());
+    }
 }
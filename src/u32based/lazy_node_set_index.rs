@@ -0,0 +1,218 @@
+use super::{Tree, TreeLog};
+use crate::{U32Set, default_iu32_hashset};
+use intern::IU32HashSet;
+use rustc_hash::FxHashMap;
+use std::{
+    collections::hash_map::Entry,
+    sync::{PoisonError, RwLock},
+};
+
+/// A [`NodeSetIndex`](super::NodeSetIndex) that only stores direct items.
+///
+/// Subtree aggregates are computed on first query and cached until a log
+/// touching the subtree is applied. The cache assumes the tree passed to
+/// queries does not change; call [`invalidate`](Self::invalidate) after moving
+/// nodes.
+#[derive(Default)]
+pub struct LazyNodeSetIndex {
+    cache: RwLock<FxHashMap<u32, IU32HashSet>>,
+    direct_items: FxHashMap<u32, IU32HashSet>,
+}
+
+impl LazyNodeSetIndex {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the log, dropping the cached aggregates of every ancestor of a
+    /// changed node. Returns `true` if anything changed.
+    pub fn apply(&mut self, tree: &Tree, log: LazyNodeSetIndexLog) -> bool {
+        let cache = self.cache.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let mut changed = false;
+
+        for (k, b) in log.direct_items {
+            match self.direct_items.entry(k) {
+                Entry::Occupied(o) if b.is_empty() => {
+                    o.remove();
+                }
+                Entry::Occupied(mut o) if b != *o.get().as_set() => {
+                    o.insert(b.into());
+                }
+                Entry::Vacant(v) if !b.is_empty() => {
+                    v.insert(b.into());
+                }
+                _ => continue,
+            }
+
+            for n in tree.ancestors_with_self(k) {
+                cache.remove(&n);
+            }
+
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Number of cached subtree aggregates.
+    #[inline]
+    pub fn cached_len(&self) -> usize {
+        self.cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    pub fn direct_items(&self, node: u32) -> &U32Set {
+        self.direct_items
+            .get(&node)
+            .unwrap_or_else(|| default_iu32_hashset())
+            .as_set()
+    }
+
+    /// Drops every cached aggregate.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.cache
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.direct_items.is_empty()
+    }
+
+    /// Items held by `node` or any of its descendants in `tree`.
+    pub fn subtree_items(&self, tree: &Tree, node: u32) -> IU32HashSet {
+        if let Some(set) = self
+            .cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&node)
+        {
+            return set.clone();
+        }
+
+        let set = IU32HashSet::from(
+            tree.descendants_with_self(node)
+                .iter()
+                .flat_map(|n| self.direct_items(n).iter().copied())
+                .collect::<U32Set>(),
+        );
+
+        self.cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(node, set.clone());
+
+        set
+    }
+}
+
+impl Clone for LazyNodeSetIndex {
+    fn clone(&self) -> Self {
+        Self {
+            cache: RwLock::new(
+                self.cache
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone(),
+            ),
+            direct_items: self.direct_items.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct LazyNodeSetIndexLog {
+    direct_items: FxHashMap<u32, U32Set>,
+}
+
+impl LazyNodeSetIndexLog {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn direct_items<'a>(&'a self, base: &'a LazyNodeSetIndex, node: u32) -> &'a U32Set {
+        self.direct_items
+            .get(&node)
+            .unwrap_or_else(|| base.direct_items(node))
+    }
+
+    fn direct_items_mut(&mut self, base: &LazyNodeSetIndex, node: u32) -> &mut U32Set {
+        self.direct_items
+            .entry(node)
+            .or_insert_with(|| base.direct_items(node).clone())
+    }
+
+    #[inline]
+    pub fn insert(&mut self, base: &LazyNodeSetIndex, node: u32, item: u32) -> bool {
+        self.direct_items_mut(base, node).insert(item)
+    }
+
+    #[inline]
+    pub fn remove(&mut self, base: &LazyNodeSetIndex, node: u32, item: u32) -> bool {
+        self.direct_items_mut(base, node).remove(&item)
+    }
+
+    /// Items held by `node` or any of its descendants, computed through the
+    /// log without touching the base cache.
+    pub fn subtree_items(
+        &self,
+        base: &LazyNodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+    ) -> U32Set {
+        tree_log
+            .descendants_with_self(tree, node)
+            .iter()
+            .flat_map(|n| self.direct_items(base, n).iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_is_dropped_along_the_changed_branch() {
+        // 1 ─┬─ 2
+        //    └─ 3
+        let tree = Tree::from_iter([(1, None), (2, Some(1)), (3, Some(1))]);
+        let mut index = LazyNodeSetIndex::new();
+        let mut log = LazyNodeSetIndexLog::new();
+
+        log.insert(&index, 2, 10);
+        log.insert(&index, 3, 20);
+        index.apply(&tree, log);
+
+        assert_eq!(
+            *index.subtree_items(&tree, 1).as_set(),
+            U32Set::from_iter([10, 20])
+        );
+        assert_eq!(
+            *index.subtree_items(&tree, 3).as_set(),
+            U32Set::from_iter([20])
+        );
+        assert_eq!(index.cached_len(), 2);
+
+        let mut log = LazyNodeSetIndexLog::new();
+        log.remove(&index, 2, 10);
+
+        let staged = log.subtree_items(&index, &tree, &TreeLog::new(), 1);
+        assert_eq!(staged, U32Set::from_iter([20]));
+
+        index.apply(&tree, log);
+        assert_eq!(index.cached_len(), 1);
+        assert_eq!(
+            *index.subtree_items(&tree, 1).as_set(),
+            U32Set::from_iter([20])
+        );
+    }
+}
@@ -1,10 +1,16 @@
 pub mod flat_set_index;
+pub mod lazy_node_set_index;
+pub mod node_count_index;
+pub mod node_set_index;
 pub mod one_index;
 pub mod tree;
 
 pub use flat_set_index::{
-    FlatSetIndex, FlatSetIndexBuilder, FlatSetIndexLog, U32FlatSetIndex, U32FlatSetIndexBuilder,
-    U32FlatSetIndexLog,
+    FlatSetIndex, FlatSetIndexBuilder, FlatSetIndexLog, KeysOf, U32FlatSetIndex,
+    U32FlatSetIndexBuilder, U32FlatSetIndexLog,
 };
+pub use lazy_node_set_index::{LazyNodeSetIndex, LazyNodeSetIndexLog};
+pub use node_count_index::{NodeCountIndex, NodeCountIndexLog};
+pub use node_set_index::{NodeSetIndex, NodeSetIndexBuilder, NodeSetIndexLog, NodeSetIndexReport};
 pub use one_index::{OneIndex, OneIndexLog};
 pub use tree::{Tree, TreeLog};
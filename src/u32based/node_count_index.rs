@@ -0,0 +1,272 @@
+use super::{Tree, TreeLog};
+use crate::{U32Set, empty_roaring};
+use intern::IU32HashSet;
+use rustc_hash::FxHashMap;
+use std::collections::hash_map::Entry;
+
+type Counts = FxHashMap<u32, u32>;
+
+/// Like [`NodeSetIndex`](super::NodeSetIndex), but each subtree aggregate
+/// counts how many nodes of the subtree hold an item directly.
+///
+/// Removing an item from one branch only drops it from an ancestor once no
+/// other branch still contributes it.
+#[derive(Clone, Default)]
+pub struct NodeCountIndex {
+    direct_items: FxHashMap<u32, IU32HashSet>,
+    subtree_counts: FxHashMap<u32, Counts>,
+}
+
+impl NodeCountIndex {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, log: NodeCountIndexLog) -> bool {
+        let mut changed = false;
+
+        for (k, b) in log.direct_items {
+            match self.direct_items.entry(k) {
+                Entry::Occupied(o) if b.is_empty() => {
+                    o.remove();
+                    changed = true;
+                }
+                Entry::Occupied(mut o) if b != *o.get().as_set() => {
+                    o.insert(b.into());
+                    changed = true;
+                }
+                Entry::Vacant(v) if !b.is_empty() => {
+                    v.insert(b.into());
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+
+        for (k, c) in log.subtree_counts {
+            if c.is_empty() {
+                changed |= self.subtree_counts.remove(&k).is_some();
+            } else if self.subtree_counts.get(&k) != Some(&c) {
+                self.subtree_counts.insert(k, c);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Number of nodes in the subtree of `node`, itself included, holding `item`.
+    #[inline]
+    pub fn count(&self, node: u32, item: u32) -> u32 {
+        self.subtree_counts
+            .get(&node)
+            .and_then(|c| c.get(&item))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn direct_items(&self, node: u32) -> &U32Set {
+        self.direct_items
+            .get(&node)
+            .map_or_else(|| empty_roaring(), IU32HashSet::as_set)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.direct_items.is_empty()
+    }
+
+    #[inline]
+    pub fn subtree_contains(&self, node: u32, item: u32) -> bool {
+        self.count(node, item) > 0
+    }
+
+    pub fn subtree_items(&self, node: u32) -> impl Iterator<Item = u32> + '_ {
+        self.subtree_counts
+            .get(&node)
+            .into_iter()
+            .flat_map(|c| c.keys().copied())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct NodeCountIndexLog {
+    direct_items: FxHashMap<u32, U32Set>,
+    subtree_counts: FxHashMap<u32, Counts>,
+}
+
+impl NodeCountIndexLog {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add_counts(
+        &mut self,
+        base: &NodeCountIndex,
+        nodes: impl Iterator<Item = u32>,
+        delta: &Counts,
+        add: bool,
+    ) {
+        for node in nodes {
+            let counts = self.counts_mut(base, node);
+
+            for (item, n) in delta {
+                match counts.entry(*item) {
+                    Entry::Occupied(mut o) if !add => {
+                        *o.get_mut() = o.get().saturating_sub(*n);
+
+                        if *o.get() == 0 {
+                            o.remove();
+                        }
+                    }
+                    Entry::Occupied(mut o) => *o.get_mut() += n,
+                    Entry::Vacant(v) if add => {
+                        v.insert(*n);
+                    }
+                    Entry::Vacant(_) => {}
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn count(&self, base: &NodeCountIndex, node: u32, item: u32) -> u32 {
+        match self.subtree_counts.get(&node) {
+            Some(c) => c.get(&item).copied().unwrap_or(0),
+            None => base.count(node, item),
+        }
+    }
+
+    fn counts_mut(&mut self, base: &NodeCountIndex, node: u32) -> &mut Counts {
+        self.subtree_counts
+            .entry(node)
+            .or_insert_with(|| base.subtree_counts.get(&node).cloned().unwrap_or_default())
+    }
+
+    pub fn direct_items<'a>(&'a self, base: &'a NodeCountIndex, node: u32) -> &'a U32Set {
+        self.direct_items
+            .get(&node)
+            .unwrap_or_else(|| base.direct_items(node))
+    }
+
+    fn direct_items_mut(&mut self, base: &NodeCountIndex, node: u32) -> &mut U32Set {
+        self.direct_items
+            .entry(node)
+            .or_insert_with(|| base.direct_items(node).clone())
+    }
+
+    pub fn insert(
+        &mut self,
+        base: &NodeCountIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+        item: u32,
+    ) {
+        if self.direct_items_mut(base, node).insert(item) {
+            let delta = Counts::from_iter([(item, 1)]);
+            self.add_counts(base, tree_log.ancestors_with_self(tree, node), &delta, true);
+        }
+    }
+
+    /// Adds the counts of `node`'s subtree to its ancestors. Call after `node`
+    /// was attached to a new parent in `tree_log`.
+    pub fn insert_subtree(
+        &mut self,
+        base: &NodeCountIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+    ) {
+        let delta = self.subtree_counts(base, node);
+        self.add_counts(base, tree_log.ancestors(tree, node), &delta, true);
+    }
+
+    pub fn remove(
+        &mut self,
+        base: &NodeCountIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+        item: u32,
+    ) {
+        if self.direct_items_mut(base, node).remove(&item) {
+            let delta = Counts::from_iter([(item, 1)]);
+            self.add_counts(
+                base,
+                tree_log.ancestors_with_self(tree, node),
+                &delta,
+                false,
+            );
+        }
+    }
+
+    /// Subtracts the counts of `node`'s subtree from its ancestors. Call before
+    /// `node` is detached from its parent in `tree_log`.
+    pub fn remove_subtree(
+        &mut self,
+        base: &NodeCountIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+    ) {
+        let delta = self.subtree_counts(base, node);
+        self.add_counts(base, tree_log.ancestors(tree, node), &delta, false);
+    }
+
+    #[inline]
+    pub fn subtree_contains(&self, base: &NodeCountIndex, node: u32, item: u32) -> bool {
+        self.count(base, node, item) > 0
+    }
+
+    fn subtree_counts(&self, base: &NodeCountIndex, node: u32) -> Counts {
+        self.subtree_counts
+            .get(&node)
+            .or_else(|| base.subtree_counts.get(&node))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_item_survives_removal_from_one_branch() {
+        // 1 ─┬─ 2
+        //    └─ 3
+        let tree = Tree::from_iter([(1, None), (2, Some(1)), (3, Some(1))]);
+        let tree_log = TreeLog::new();
+        let mut index = NodeCountIndex::new();
+        let mut log = NodeCountIndexLog::new();
+
+        log.insert(&index, &tree, &tree_log, 2, 10);
+        log.insert(&index, &tree, &tree_log, 3, 10);
+        log.insert(&index, &tree, &tree_log, 1, 10);
+        index.apply(log);
+
+        assert_eq!(index.count(1, 10), 3);
+
+        let mut log = NodeCountIndexLog::new();
+        log.remove(&index, &tree, &tree_log, 1, 10);
+        log.remove(&index, &tree, &tree_log, 2, 10);
+        index.apply(log);
+
+        assert_eq!(index.count(1, 10), 1);
+        assert!(!index.subtree_contains(2, 10));
+        assert!(index.subtree_contains(3, 10));
+
+        let mut tree_log = TreeLog::new();
+        let mut log = NodeCountIndexLog::new();
+        log.remove_subtree(&index, &tree, &tree_log, 3);
+        tree_log.insert(&tree, Some(2), 3);
+        log.insert_subtree(&index, &tree, &tree_log, 3);
+        index.apply(log);
+
+        assert_eq!(index.count(1, 10), 1);
+        assert_eq!(index.count(2, 10), 1);
+    }
+}
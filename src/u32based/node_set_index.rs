@@ -0,0 +1,1290 @@
+use super::{Tree, TreeLog, tree::empty_tree_log};
+use crate::{
+    U32Set, U32SetExt,
+    codec::{DecodeError, decode_set_map, encode_set_map, expect_end},
+    default_iu32_hashset,
+};
+use intern::IU32HashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::{collections::hash_map::Entry, error::Error, fmt};
+
+/// Items attached to the nodes of a [`Tree`], aggregated per subtree.
+///
+/// `subtree_items(node)` is the union of the direct items of `node` and of all
+/// its descendants.
+#[derive(Clone, Default)]
+pub struct NodeSetIndex {
+    pub(crate) direct_items: FxHashMap<u32, IU32HashSet>,
+    item_nodes: Option<Box<FxHashMap<u32, U32Set>>>,
+    pub(crate) subtree_items: FxHashMap<u32, IU32HashSet>,
+}
+
+impl NodeSetIndex {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every item held directly by any node.
+    pub fn all_items(&self) -> U32Set {
+        self.direct_items
+            .values()
+            .flat_map(|s| s.as_set().iter().copied())
+            .collect()
+    }
+
+    /// Applies the log, returning `true` if anything changed.
+    pub fn apply(&mut self, log: NodeSetIndexLog) -> bool {
+        let (mut direct, mut subtree) = (false, false);
+        self.apply_with(log, |_| direct = true, |_| subtree = true);
+        direct || subtree
+    }
+
+    /// Applies the log, returning the nodes whose direct or subtree items changed.
+    pub fn apply_report(&mut self, log: NodeSetIndexLog) -> NodeSetIndexReport {
+        let mut report = NodeSetIndexReport::default();
+
+        self.apply_with(
+            log,
+            |n| {
+                report.direct.insert(n);
+            },
+            |n| {
+                report.subtree.insert(n);
+            },
+        );
+
+        report
+    }
+
+    fn apply_with(
+        &mut self,
+        log: NodeSetIndexLog,
+        on_direct: impl FnMut(u32),
+        on_subtree: impl FnMut(u32),
+    ) {
+        if let Some(item_nodes) = &mut self.item_nodes {
+            for (&node, new) in &log.direct_items {
+                let old = self.direct_items.get(&node).map(IU32HashSet::as_set);
+
+                for item in old.into_iter().flatten().filter(|i| !new.contains(i)) {
+                    if let Entry::Occupied(mut o) = item_nodes.entry(*item) {
+                        o.get_mut().remove(&node);
+
+                        if o.get().is_empty() {
+                            o.remove();
+                        }
+                    }
+                }
+
+                for item in new.iter().filter(|i| !old.is_some_and(|o| o.contains(i))) {
+                    item_nodes.entry(*item).or_default().insert(node);
+                }
+            }
+        }
+
+        apply_sets(&mut self.direct_items, log.direct_items, on_direct);
+        apply_sets(&mut self.subtree_items, log.subtree_items, on_subtree);
+    }
+
+    #[inline]
+    pub fn direct_items(&self, node: u32) -> &U32Set {
+        self.direct_items_interned(node).as_set()
+    }
+
+    /// The shared handle behind [`direct_items`](Self::direct_items), the same
+    /// type [`FlatSetIndex::get`](super::FlatSetIndex::get) hands out.
+    pub fn direct_items_interned(&self, node: u32) -> &IU32HashSet {
+        self.direct_items
+            .get(&node)
+            .unwrap_or_else(|| default_iu32_hashset())
+    }
+
+    /// Maintains an item to nodes map so that
+    /// [`nodes_with_item`](Self::nodes_with_item) no longer scans every node.
+    pub fn enable_item_nodes(&mut self) {
+        if self.item_nodes.is_none() {
+            let mut map = FxHashMap::<u32, U32Set>::default();
+
+            for (node, items) in &self.direct_items {
+                for item in items.as_set() {
+                    map.entry(*item).or_default().insert(*node);
+                }
+            }
+
+            self.item_nodes = Some(Box::new(map));
+        }
+    }
+
+    #[inline]
+    pub fn has_item_nodes(&self) -> bool {
+        self.item_nodes.is_some()
+    }
+
+    /// Items common to the subtrees of every one of `nodes`, smallest first.
+    pub fn intersect_subtrees(&self, nodes: impl IntoIterator<Item = u32>) -> U32Set {
+        let mut sets = nodes
+            .into_iter()
+            .map(|n| self.subtree_items(n))
+            .collect::<Vec<_>>();
+
+        sets.sort_unstable_by_key(|s| s.len());
+
+        let mut iter = sets.into_iter();
+
+        let Some(first) = iter.next() else {
+            return U32Set::default();
+        };
+
+        let mut set = first.clone();
+
+        for other in iter {
+            if set.is_empty() {
+                break;
+            }
+
+            set.retain(|v| other.contains(v));
+        }
+
+        set
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.direct_items.is_empty()
+    }
+
+    /// Nodes holding at least one direct item, with their items.
+    pub fn iter_direct(&self) -> impl Iterator<Item = (u32, &U32Set)> + '_ {
+        self.direct_items.iter().map(|(k, s)| (*k, s.as_set()))
+    }
+
+    /// Nodes with a non-empty subtree aggregate, with their aggregate.
+    pub fn iter_subtree(&self) -> impl Iterator<Item = (u32, &U32Set)> + '_ {
+        self.subtree_items.iter().map(|(k, s)| (*k, s.as_set()))
+    }
+
+    /// Nodes holding at least one direct item.
+    #[inline]
+    pub fn nodes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.direct_items.keys().copied()
+    }
+
+    /// Nodes whose subtree items contain every one of `items`.
+    ///
+    /// Scans every subtree aggregate, with or without
+    /// [`enable_item_nodes`](Self::enable_item_nodes). For a few items, the
+    /// ancestors of [`nodes_with_item`](Self::nodes_with_item) are cheaper.
+    pub fn nodes_containing_all(&self, items: &U32Set) -> U32Set {
+        self.subtree_items
+            .iter()
+            .filter(|(_, s)| items.is_subset(s.as_set()))
+            .map(|(node, _)| *node)
+            .collect()
+    }
+
+    /// Nodes whose subtree items contain at least one of `items`. Scans every
+    /// subtree aggregate, like [`nodes_containing_all`](Self::nodes_containing_all).
+    pub fn nodes_containing_any(&self, items: &U32Set) -> U32Set {
+        self.subtree_items
+            .iter()
+            .filter(|(_, s)| !items.is_disjoint(s.as_set()))
+            .map(|(node, _)| *node)
+            .collect()
+    }
+
+    /// Nodes whose direct items contain `item`.
+    pub fn nodes_with_item(&self, item: u32) -> U32Set {
+        match &self.item_nodes {
+            Some(map) => map.get(&item).cloned().unwrap_or_default(),
+            None => self
+                .direct_items
+                .iter()
+                .filter(|(_, items)| items.as_set().contains(&item))
+                .map(|(node, _)| *node)
+                .collect(),
+        }
+    }
+
+    /// Recomputes every subtree aggregate from the direct items and `tree`.
+    #[inline]
+    pub fn rebuild(&mut self, tree: &Tree) -> bool {
+        let log = self.rebuild_log(tree, empty_tree_log());
+        self.apply(log)
+    }
+
+    /// Stages the subtree aggregates recomputed from the direct items and the
+    /// hierarchy of `tree_log`, without touching the index.
+    ///
+    /// Each aggregate is merged into its parent's in a single bottom-up pass.
+    pub fn rebuild_log(&self, tree: &Tree, tree_log: &TreeLog) -> NodeSetIndexLog {
+        let plan = RebuildPlan::new(self.direct_items.keys().copied(), tree, tree_log);
+        let mut subtree_items = FxHashMap::<u32, U32Set>::default();
+
+        for level in &plan.levels {
+            for node in level {
+                let set = self.aggregate(&plan, &subtree_items, *node);
+                subtree_items.insert(*node, set);
+            }
+        }
+
+        self.finish_rebuild(&plan, subtree_items)
+    }
+
+    /// Direct items of `node` merged with the aggregates of its children found
+    /// in `done`.
+    fn aggregate(&self, plan: &RebuildPlan, done: &FxHashMap<u32, U32Set>, node: u32) -> U32Set {
+        let mut set = self
+            .direct_items
+            .get(&node)
+            .map(|s| s.as_set().clone())
+            .unwrap_or_default();
+
+        for child in plan.children.get(&node).into_iter().flatten() {
+            if let Some(items) = done.get(child) {
+                set.extend(items.iter().copied());
+            }
+        }
+
+        set
+    }
+
+    /// Gives every member of a cycle the union of their aggregates, and clears
+    /// the nodes left without items.
+    fn finish_rebuild(
+        &self,
+        plan: &RebuildPlan,
+        mut subtree_items: FxHashMap<u32, U32Set>,
+    ) -> NodeSetIndexLog {
+        for cycle in &plan.cycles {
+            let mut set = U32Set::default();
+
+            for node in cycle {
+                set.extend(self.aggregate(plan, &subtree_items, *node));
+            }
+
+            for node in cycle {
+                subtree_items.insert(*node, set.clone());
+            }
+        }
+
+        for node in self.subtree_items.keys() {
+            subtree_items.entry(*node).or_default();
+        }
+
+        NodeSetIndexLog {
+            direct_items: Default::default(),
+            subtree_items,
+        }
+    }
+
+    /// Like [`rebuild`](Self::rebuild), with the aggregates computed on the
+    /// rayon pool.
+    #[cfg(feature = "rayon")]
+    pub fn par_rebuild(&mut self, tree: &Tree) -> bool {
+        let log = self.par_rebuild_log(tree, empty_tree_log());
+        self.apply(log)
+    }
+
+    /// Like [`rebuild_log`](Self::rebuild_log), computing the nodes of each
+    /// level of the bottom-up pass in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn par_rebuild_log(&self, tree: &Tree, tree_log: &TreeLog) -> NodeSetIndexLog {
+        use rayon::prelude::*;
+
+        let plan = RebuildPlan::new(self.direct_items.keys().copied(), tree, tree_log);
+        let mut subtree_items = FxHashMap::<u32, U32Set>::default();
+
+        for level in &plan.levels {
+            let sets = level
+                .par_iter()
+                .map(|node| (*node, self.aggregate(&plan, &subtree_items, *node)))
+                .collect::<Vec<_>>();
+
+            subtree_items.extend(sets);
+        }
+
+        self.finish_rebuild(&plan, subtree_items)
+    }
+
+    #[inline]
+    pub fn subtree_items(&self, node: u32) -> &U32Set {
+        self.subtree_items_interned(node).as_set()
+    }
+
+    /// The shared handle behind [`subtree_items`](Self::subtree_items).
+    pub fn subtree_items_interned(&self, node: u32) -> &IU32HashSet {
+        self.subtree_items
+            .get(&node)
+            .unwrap_or_else(|| default_iu32_hashset())
+    }
+
+    /// Checks that every subtree aggregate seen through `log` matches the direct
+    /// items of the subtree under the hierarchy of `tree_log`.
+    pub fn verify(
+        &self,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        log: &NodeSetIndexLog,
+    ) -> Result<(), Vec<InvariantViolation>> {
+        let mut expected = FxHashMap::<u32, U32Set>::default();
+        let nodes = self.direct_items.keys().chain(log.direct_items.keys());
+
+        for node in nodes.copied().collect::<U32Set>() {
+            let items = log.direct_items(self, node);
+
+            if !items.is_empty() {
+                for n in tree_log.ancestors_with_self(tree, node) {
+                    expected.entry(n).or_default().extend(items.iter().copied());
+                }
+            }
+        }
+
+        let nodes = expected
+            .keys()
+            .chain(self.subtree_items.keys())
+            .chain(log.subtree_items.keys())
+            .copied()
+            .collect::<U32Set>();
+
+        let mut violations = nodes
+            .into_iter()
+            .filter_map(|node| {
+                let actual = log.subtree_items(self, node);
+                let expected = expected
+                    .get(&node)
+                    .unwrap_or_else(|| default_iu32_hashset().as_set());
+
+                let violation = InvariantViolation {
+                    node,
+                    missing: expected
+                        .difference(actual)
+                        .copied()
+                        .collect::<U32Set>()
+                        .sorted(),
+                    extra: actual
+                        .difference(expected)
+                        .copied()
+                        .collect::<U32Set>()
+                        .sorted(),
+                };
+
+                (!violation.missing.is_empty() || !violation.extra.is_empty()).then_some(violation)
+            })
+            .collect::<Vec<_>>();
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            violations.sort_unstable_by_key(|v| v.node);
+            Err(violations)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for NodeSetIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use crate::IntSet;
+        use serde::ser::SerializeTuple;
+
+        fn entries(map: &FxHashMap<u32, IU32HashSet>) -> Vec<(u32, &IntSet<u32>)> {
+            let mut v = map
+                .iter()
+                .map(|(k, s)| (*k, unsafe { IntSet::from_u32set_ref(s.as_set()) }))
+                .collect::<Vec<_>>();
+
+            v.sort_unstable_by_key(|(k, _)| *k);
+            v
+        }
+
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&entries(&self.direct_items))?;
+        tuple.serialize_element(&entries(&self.subtree_items))?;
+        tuple.end()
+    }
+}
+
+/// Sets are interned on load, so identical subtree aggregates share memory.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for NodeSetIndex {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use crate::IntSet;
+
+        type Entries = Vec<(u32, IntSet<u32>)>;
+
+        fn intern(entries: Entries) -> FxHashMap<u32, IU32HashSet> {
+            entries
+                .into_iter()
+                .filter(|(_, s)| !s.is_empty())
+                .map(|(k, s)| (k, IU32HashSet::from(s.as_set())))
+                .collect()
+        }
+
+        let (direct, subtree) = <(Entries, Entries)>::deserialize(deserializer)?;
+
+        Ok(Self {
+            direct_items: intern(direct),
+            item_nodes: None,
+            subtree_items: intern(subtree),
+        })
+    }
+}
+
+/// The nodes above the holders of direct items, in levels where every node
+/// comes after its children.
+struct RebuildPlan {
+    children: FxHashMap<u32, Vec<u32>>,
+    /// Nodes on a cycle, which no level can order; one entry per cycle.
+    cycles: Vec<Vec<u32>>,
+    levels: Vec<Vec<u32>>,
+}
+
+impl RebuildPlan {
+    fn new(holders: impl Iterator<Item = u32>, tree: &Tree, tree_log: &TreeLog) -> Self {
+        let mut parents = FxHashMap::<u32, Option<u32>>::default();
+
+        for mut node in holders {
+            while let Entry::Vacant(e) = parents.entry(node) {
+                match *e.insert(tree_log.parent(tree, node)) {
+                    Some(p) => node = p,
+                    None => break,
+                }
+            }
+        }
+
+        let mut children = FxHashMap::<u32, Vec<u32>>::default();
+
+        for (node, parent) in &parents {
+            if let Some(p) = parent {
+                children.entry(*p).or_default().push(*node);
+            }
+        }
+
+        let mut pending = children
+            .iter()
+            .map(|(n, c)| (*n, c.len()))
+            .collect::<FxHashMap<_, _>>();
+
+        let mut ready = parents
+            .keys()
+            .filter(|n| !children.contains_key(n))
+            .map(|n| (*n, 0))
+            .collect::<Vec<_>>();
+
+        let mut heights = FxHashMap::<u32, usize>::default();
+        let mut levels = Vec::<Vec<u32>>::new();
+
+        while let Some((node, height)) = ready.pop() {
+            if levels.len() <= height {
+                levels.resize_with(height + 1, Vec::new);
+            }
+
+            levels[height].push(node);
+
+            let Some(p) = parents[&node] else {
+                continue;
+            };
+
+            let h = heights.entry(p).or_default();
+            *h = (*h).max(height + 1);
+
+            let h = *h;
+            let count = pending.get_mut(&p).expect("parent of a node");
+            *count -= 1;
+
+            if *count == 0 {
+                pending.remove(&p);
+                ready.push((p, h));
+            }
+        }
+
+        // What is still pending sits on a cycle: below a cycle, the children
+        // always reach a leaf.
+        let mut cycles = Vec::new();
+        let mut seen = FxHashSet::default();
+
+        for start in pending.keys() {
+            if !seen.insert(*start) {
+                continue;
+            }
+
+            let mut cycle = vec![*start];
+            let mut node = parents[start].expect("cycle member");
+
+            while seen.insert(node) {
+                cycle.push(node);
+                node = parents[&node].expect("cycle member");
+            }
+
+            cycles.push(cycle);
+        }
+
+        Self {
+            children,
+            cycles,
+            levels,
+        }
+    }
+}
+
+fn apply_sets(
+    target: &mut FxHashMap<u32, IU32HashSet>,
+    source: FxHashMap<u32, U32Set>,
+    mut on_change: impl FnMut(u32),
+) {
+    let mut changed = false;
+
+    for (k, b) in source {
+        match target.entry(k) {
+            Entry::Occupied(o) if b.is_empty() => {
+                o.remove();
+            }
+            Entry::Occupied(mut o) if b != *o.get().as_set() => {
+                o.insert(b.into());
+            }
+            Entry::Vacant(v) if !b.is_empty() => {
+                v.insert(b.into());
+            }
+            _ => continue,
+        }
+
+        on_change(k);
+        changed = true;
+    }
+
+    if changed {
+        target.shrink_to_fit();
+    }
+}
+
+/// A subtree aggregate that disagrees with the direct items below it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub node: u32,
+    /// Items held below `node` but absent from its aggregate, ascending.
+    pub missing: Vec<u32>,
+    /// Items in the aggregate that nothing below `node` holds, ascending.
+    pub extra: Vec<u32>,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {}: missing {:?}, extra {:?}",
+            self.node, self.missing, self.extra
+        )
+    }
+}
+
+impl Error for InvariantViolation {}
+
+/// Nodes touched by [`NodeSetIndex::apply_report`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodeSetIndexReport {
+    pub direct: U32Set,
+    pub subtree: U32Set,
+}
+
+impl NodeSetIndexReport {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.direct.is_empty() && self.subtree.is_empty()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct NodeSetIndexLog {
+    direct_items: FxHashMap<u32, U32Set>,
+    subtree_items: FxHashMap<u32, U32Set>,
+}
+
+impl NodeSetIndexLog {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every item held directly by any node, as seen through the log.
+    pub fn all_items(&self, base: &NodeSetIndex) -> U32Set {
+        let staged = self.direct_items.values().flatten().copied();
+        let kept = base
+            .direct_items
+            .iter()
+            .filter(|(node, _)| !self.direct_items.contains_key(node))
+            .flat_map(|(_, s)| s.as_set().iter().copied());
+
+        staged.chain(kept).collect()
+    }
+
+    /// Removes every direct item of `node`, walking the ancestors once.
+    pub fn clear_node(&mut self, base: &NodeSetIndex, tree: &Tree, tree_log: &TreeLog, node: u32) {
+        let items = std::mem::take(self.direct_items_mut(base, node));
+
+        if !items.is_empty() {
+            self.drop_upward(base, tree, tree_log, node, None, items);
+        }
+    }
+
+    pub fn direct_items<'a>(&'a self, base: &'a NodeSetIndex, node: u32) -> &'a U32Set {
+        self.direct_items
+            .get(&node)
+            .unwrap_or_else(|| base.direct_items(node))
+    }
+
+    fn direct_items_mut(&mut self, base: &NodeSetIndex, node: u32) -> &mut U32Set {
+        self.direct_items
+            .entry(node)
+            .or_insert_with(|| base.direct_items(node).clone())
+    }
+
+    /// Drops `items` from the subtree of `start` and its ancestors, stopping as
+    /// soon as every item is still reachable another way. `detached` is a child
+    /// of `start` whose contribution must be ignored.
+    fn drop_upward(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        start: u32,
+        detached: Option<u32>,
+        mut items: U32Set,
+    ) {
+        for node in tree_log.ancestors_with_self(tree, start) {
+            let direct = self.direct_items(base, node);
+            let children = tree_log.children(tree, node);
+
+            items.retain(|item| {
+                !direct.contains(item)
+                    && !children
+                        .iter()
+                        .filter(|c| Some(**c) != detached)
+                        .any(|c| self.subtree_items(base, *c).contains(item))
+            });
+
+            if items.is_empty() {
+                break;
+            }
+
+            let subtree = self.subtree_items_mut(base, node);
+            subtree.retain(|item| !items.contains(item));
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+        item: u32,
+    ) {
+        if !self.direct_items_mut(base, node).insert(item) {
+            return;
+        }
+
+        for n in tree_log.ancestors_with_self(tree, node) {
+            if !self.subtree_items_mut(base, n).insert(item) {
+                break;
+            }
+        }
+    }
+
+    /// Inserts several items into `node`, walking the ancestors once.
+    pub fn insert_many(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+        items: &U32Set,
+    ) {
+        let direct = self.direct_items_mut(base, node);
+        let mut added = items
+            .iter()
+            .copied()
+            .filter(|i| direct.insert(*i))
+            .collect::<U32Set>();
+
+        for n in tree_log.ancestors_with_self(tree, node) {
+            if added.is_empty() {
+                break;
+            }
+
+            let subtree = self.subtree_items_mut(base, n);
+            added.retain(|i| subtree.insert(*i));
+        }
+    }
+
+    /// Adds the items of `node`'s subtree to its ancestors. Call after `node`
+    /// was attached to a new parent in `tree_log`.
+    pub fn insert_subtree(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+    ) {
+        let items = self.subtree_items(base, node).clone();
+
+        if items.is_empty() {
+            return;
+        }
+
+        for n in tree_log.ancestors(tree, node) {
+            self.subtree_items_mut(base, n)
+                .extend(items.iter().copied());
+        }
+    }
+
+    /// Moves `node` under `parent` in `tree_log`, carrying its subtree items
+    /// from the old ancestors to the new ones.
+    ///
+    /// Returns `false` and changes nothing if `parent` is `node` or one of its
+    /// descendants, since the move would close a cycle.
+    pub fn reparent(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &mut TreeLog,
+        parent: Option<u32>,
+        node: u32,
+    ) -> bool {
+        if let Some(p) = parent
+            && tree_log.ancestors_with_self(tree, p).any(|a| a == node)
+        {
+            return false;
+        }
+
+        if tree_log.parent(tree, node) != parent {
+            self.remove_subtree(base, tree, tree_log, node);
+            tree_log.insert(tree, parent, node);
+            self.insert_subtree(base, tree, tree_log, node);
+        }
+
+        true
+    }
+
+    pub fn remove(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+        item: u32,
+    ) {
+        if !self.direct_items_mut(base, node).remove(&item) {
+            return;
+        }
+
+        self.drop_upward(base, tree, tree_log, node, None, U32Set::from_iter([item]));
+    }
+
+    /// Removes the items of `node`'s subtree from its ancestors. Call before
+    /// `node` is detached from its parent in `tree_log`.
+    pub fn remove_subtree(
+        &mut self,
+        base: &NodeSetIndex,
+        tree: &Tree,
+        tree_log: &TreeLog,
+        node: u32,
+    ) {
+        let items = self.subtree_items(base, node).clone();
+
+        if let Some(parent) = tree_log.parent(tree, node)
+            && !items.is_empty()
+        {
+            self.drop_upward(base, tree, tree_log, parent, Some(node), items);
+        }
+    }
+
+    pub fn subtree_items<'a>(&'a self, base: &'a NodeSetIndex, node: u32) -> &'a U32Set {
+        self.subtree_items
+            .get(&node)
+            .unwrap_or_else(|| base.subtree_items(node))
+    }
+
+    fn subtree_items_mut(&mut self, base: &NodeSetIndex, node: u32) -> &mut U32Set {
+        self.subtree_items
+            .entry(node)
+            .or_insert_with(|| base.subtree_items(node).clone())
+    }
+}
+
+impl NodeSetIndexLog {
+    /// Encodes the staged direct and subtree sets as a write-ahead log record.
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_set_map(&self.direct_items, &mut out);
+        encode_set_map(&self.subtree_items, &mut out);
+        out
+    }
+
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    pub fn from_wal_bytes(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let direct_items = decode_set_map(&mut bytes)?;
+        let subtree_items = decode_set_map(&mut bytes)?;
+
+        expect_end(bytes)?;
+
+        Ok(Self {
+            direct_items,
+            subtree_items,
+        })
+    }
+}
+
+/// Stages items against a fixed tree and produces a [`NodeSetIndex`].
+#[derive(Default)]
+pub struct NodeSetIndexBuilder {
+    base: NodeSetIndex,
+    log: NodeSetIndexLog,
+}
+
+impl NodeSetIndexBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn build(mut self) -> NodeSetIndex {
+        self.base.apply(self.log);
+        self.base
+    }
+
+    #[inline]
+    pub fn insert(&mut self, tree: &Tree, node: u32, item: u32) {
+        self.insert_with_log(tree, empty_tree_log(), node, item);
+    }
+
+    #[inline]
+    pub fn insert_many(&mut self, tree: &Tree, node: u32, items: &U32Set) {
+        self.log
+            .insert_many(&self.base, tree, empty_tree_log(), node, items);
+    }
+
+    /// Adds the items of `node`'s subtree to its ancestors in `tree_log`.
+    #[inline]
+    pub fn insert_subtree(&mut self, tree: &Tree, tree_log: &TreeLog, node: u32) {
+        self.log.insert_subtree(&self.base, tree, tree_log, node);
+    }
+
+    #[inline]
+    pub fn insert_with_log(&mut self, tree: &Tree, tree_log: &TreeLog, node: u32, item: u32) {
+        self.log.insert(&self.base, tree, tree_log, node, item);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, tree: &Tree, node: u32, item: u32) {
+        self.remove_with_log(tree, empty_tree_log(), node, item);
+    }
+
+    /// Removes the items of `node`'s subtree from its ancestors in `tree_log`.
+    #[inline]
+    pub fn remove_subtree(&mut self, tree: &Tree, tree_log: &TreeLog, node: u32) {
+        self.log.remove_subtree(&self.base, tree, tree_log, node);
+    }
+
+    #[inline]
+    pub fn remove_with_log(&mut self, tree: &Tree, tree_log: &TreeLog, node: u32, item: u32) {
+        self.log.remove(&self.base, tree, tree_log, node, item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> Tree {
+        // 1 ─┬─ 2 ── 4
+        //    └─ 3
+        Tree::from_iter([(1, None), (2, Some(1)), (3, Some(1)), (4, Some(2))])
+    }
+
+    fn sorted(set: &U32Set) -> Vec<u32> {
+        let mut v = set.iter().copied().collect::<Vec<_>>();
+        v.sort_unstable();
+        v
+    }
+
+    #[test]
+    fn insert_and_remove_propagate_to_ancestors() {
+        let tree = tree();
+        let log = TreeLog::new();
+        let mut index = NodeSetIndex::new();
+        let mut items = NodeSetIndexLog::new();
+
+        items.insert(&index, &tree, &log, 4, 10);
+        items.insert(&index, &tree, &log, 3, 10);
+        items.insert(&index, &tree, &log, 2, 20);
+        index.apply(items);
+
+        assert_eq!(sorted(index.subtree_items(1)), vec![10, 20]);
+        assert_eq!(sorted(index.subtree_items(2)), vec![10, 20]);
+
+        let mut items = NodeSetIndexLog::new();
+        items.remove(&index, &tree, &log, 4, 10);
+
+        // still reachable from 1 through 3
+        assert_eq!(sorted(items.subtree_items(&index, 1)), vec![10, 20]);
+        assert_eq!(sorted(items.subtree_items(&index, 2)), vec![20]);
+        assert!(items.subtree_items(&index, 4).is_empty());
+    }
+
+    #[test]
+    fn reparent_moves_subtree_items() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        let index = builder.build();
+
+        let mut tree_log = TreeLog::new();
+        let mut items = NodeSetIndexLog::new();
+
+        items.remove_subtree(&index, &tree, &tree_log, 4);
+        tree_log.insert(&tree, Some(3), 4);
+        items.insert_subtree(&index, &tree, &tree_log, 4);
+
+        assert!(items.subtree_items(&index, 2).is_empty());
+        assert_eq!(sorted(items.subtree_items(&index, 3)), vec![10]);
+        assert_eq!(sorted(items.subtree_items(&index, 1)), vec![10]);
+    }
+
+    #[test]
+    fn builder_follows_staged_tree_log() {
+        let tree = tree();
+        let mut tree_log = TreeLog::new();
+        tree_log.insert(&tree, Some(3), 5);
+
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert_with_log(&tree, &tree_log, 5, 10);
+        builder.remove_subtree(&tree, &tree_log, 5);
+        tree_log.insert(&tree, Some(4), 5);
+        builder.insert_subtree(&tree, &tree_log, 5);
+
+        let index = builder.build();
+
+        assert!(index.subtree_items(3).is_empty());
+        assert_eq!(sorted(index.subtree_items(2)), vec![10]);
+        assert_eq!(sorted(index.subtree_items(1)), vec![10]);
+    }
+
+    #[test]
+    fn rebuild_shares_items_around_cycles() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 5, 50);
+        builder.insert(&tree, 7, 70);
+        let mut index = builder.build();
+
+        // 5 ⇄ 6 ── 7, next to 1 ── 2 ── 4
+        let cyclic = Tree::from_iter([
+            (2, Some(1)),
+            (4, Some(2)),
+            (5, Some(6)),
+            (6, Some(5)),
+            (7, Some(6)),
+        ]);
+        index.rebuild(&cyclic);
+
+        assert_eq!(sorted(index.subtree_items(1)), vec![10]);
+        assert_eq!(sorted(index.subtree_items(5)), vec![50, 70]);
+        assert_eq!(sorted(index.subtree_items(6)), vec![50, 70]);
+        assert_eq!(sorted(index.subtree_items(7)), vec![70]);
+    }
+
+    #[test]
+    fn nodes_with_item_with_and_without_map() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 3, 10);
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 4, 20);
+
+        let mut index = builder.build();
+        assert_eq!(sorted(&index.nodes_with_item(10)), vec![3, 4]);
+
+        index.enable_item_nodes();
+        assert_eq!(sorted(&index.nodes_with_item(10)), vec![3, 4]);
+
+        let log = TreeLog::new();
+        let mut items = NodeSetIndexLog::new();
+        items.remove(&index, &tree, &log, 4, 10);
+        items.insert(&index, &tree, &log, 2, 30);
+        index.apply(items);
+
+        assert_eq!(sorted(&index.nodes_with_item(10)), vec![3]);
+        assert_eq!(sorted(&index.nodes_with_item(30)), vec![2]);
+        assert!(index.nodes_with_item(40).is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 3, 20);
+        let index = builder.build();
+
+        let json = serde_json::to_string(&index).unwrap();
+        assert_eq!(
+            json,
+            "[[[3,[20]],[4,[10]]],[[1,[10,20]],[2,[10]],[3,[20]],[4,[10]]]]"
+        );
+
+        let back = serde_json::from_str::<NodeSetIndex>(&json).unwrap();
+        assert_eq!(sorted(back.subtree_items(1)), vec![10, 20]);
+        assert_eq!(back.direct_items(4), index.direct_items(4));
+
+        let bin = bincode::serialize(&index).unwrap();
+        let back = bincode::deserialize::<NodeSetIndex>(&bin).unwrap();
+        assert_eq!(back.subtree_items(2), index.subtree_items(2));
+    }
+
+    #[test]
+    fn apply_report_lists_touched_nodes() {
+        let tree = tree();
+        let log = TreeLog::new();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 3, 10);
+        let mut index = builder.build();
+
+        let mut items = NodeSetIndexLog::new();
+        items.insert(&index, &tree, &log, 4, 10);
+        items.insert(&index, &tree, &log, 3, 10);
+
+        let report = index.apply_report(items);
+        assert_eq!(sorted(&report.direct), vec![4]);
+        assert_eq!(sorted(&report.subtree), vec![2, 4]);
+
+        assert!(index.apply_report(NodeSetIndexLog::new()).is_empty());
+    }
+
+    #[test]
+    fn rebuild_follows_the_tree() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        let mut index = builder.build();
+
+        let moved = Tree::from_iter([(1, None), (2, Some(1)), (3, Some(1)), (4, Some(3))]);
+        assert!(index.rebuild(&moved));
+        assert!(!index.rebuild(&moved));
+
+        assert!(index.subtree_items(2).is_empty());
+        assert_eq!(sorted(index.subtree_items(3)), vec![10]);
+        assert_eq!(sorted(index.subtree_items(1)), vec![10]);
+    }
+
+    #[test]
+    fn verify_reports_drift() {
+        let tree = tree();
+        let tree_log = TreeLog::new();
+        let mut index = NodeSetIndexBuilder::new();
+        index.insert(&tree, 4, 10);
+        let index = index.build();
+
+        let mut log = NodeSetIndexLog::new();
+        assert_eq!(index.verify(&tree, &tree_log, &log), Ok(()));
+
+        log.subtree_items_mut(&index, 2).remove(&10);
+        log.subtree_items_mut(&index, 3).insert(20);
+
+        assert_eq!(
+            index.verify(&tree, &tree_log, &log),
+            Err(vec![
+                InvariantViolation {
+                    node: 2,
+                    missing: vec![10],
+                    extra: vec![],
+                },
+                InvariantViolation {
+                    node: 3,
+                    missing: vec![],
+                    extra: vec![20],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn sets_compose_with_flat_set_index() {
+        use crate::u32based::FlatSetIndexBuilder;
+
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 3, 20);
+        let index = builder.build();
+
+        let mut flat = FlatSetIndexBuilder::<u32, _>::new();
+        flat.insert(1, 10);
+        flat.insert(1, 20);
+        let flat = flat.build();
+
+        assert_eq!(index.subtree_items(1), flat.get(&1).as_set());
+        assert!(index.direct_items_interned(2).as_set().is_empty());
+    }
+
+    #[test]
+    fn clear_node_keeps_items_held_elsewhere() {
+        let tree = tree();
+        let tree_log = TreeLog::new();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 2, 10);
+        builder.insert(&tree, 2, 20);
+        builder.insert(&tree, 4, 20);
+        let index = builder.build();
+
+        let mut log = NodeSetIndexLog::new();
+        log.clear_node(&index, &tree, &tree_log, 2);
+
+        assert!(log.direct_items(&index, 2).is_empty());
+        assert_eq!(sorted(log.subtree_items(&index, 2)), vec![20]);
+        assert_eq!(sorted(log.subtree_items(&index, 1)), vec![20]);
+        assert_eq!(index.verify(&tree, &tree_log, &log), Ok(()));
+    }
+
+    #[test]
+    fn all_items_merges_base_and_log() {
+        let tree = tree();
+        let tree_log = TreeLog::new();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 2, 10);
+        builder.insert(&tree, 3, 20);
+        let index = builder.build();
+
+        let mut log = NodeSetIndexLog::new();
+        log.clear_node(&index, &tree, &tree_log, 2);
+        log.insert(&index, &tree, &tree_log, 4, 30);
+
+        assert_eq!(sorted(&index.all_items()), vec![10, 20]);
+        assert_eq!(sorted(&log.all_items(&index)), vec![20, 30]);
+    }
+
+    #[test]
+    fn insert_many_stops_where_items_already_present() {
+        let tree = tree();
+        let tree_log = TreeLog::new();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 3, 10);
+        let index = builder.build();
+
+        let mut log = NodeSetIndexLog::new();
+        log.insert_many(&index, &tree, &tree_log, 4, &U32Set::from_iter([10, 20]));
+
+        assert_eq!(sorted(log.direct_items(&index, 4)), vec![10, 20]);
+        assert_eq!(sorted(log.subtree_items(&index, 2)), vec![10, 20]);
+        assert_eq!(sorted(log.subtree_items(&index, 1)), vec![10, 20]);
+        assert_eq!(index.verify(&tree, &tree_log, &log), Ok(()));
+    }
+
+    #[test]
+    fn nodes_containing_all_and_any() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 3, 20);
+        let index = builder.build();
+
+        let both = U32Set::from_iter([10, 20]);
+        assert_eq!(sorted(&index.nodes_containing_all(&both)), vec![1]);
+        assert_eq!(sorted(&index.nodes_containing_any(&both)), vec![1, 2, 3, 4]);
+        assert!(index.nodes_containing_any(&U32Set::default()).is_empty());
+    }
+
+    #[test]
+    fn intersect_subtrees_keeps_common_items() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 2, 20);
+        builder.insert(&tree, 3, 10);
+        let index = builder.build();
+
+        assert_eq!(sorted(&index.intersect_subtrees([1, 2, 3])), vec![10]);
+        assert!(index.intersect_subtrees([3, 5]).is_empty());
+        assert!(index.intersect_subtrees([]).is_empty());
+    }
+
+    #[test]
+    fn reparent_keeps_aggregates_consistent() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        builder.insert(&tree, 2, 20);
+        let index = builder.build();
+
+        let mut tree_log = TreeLog::new();
+        let mut log = NodeSetIndexLog::new();
+        log.reparent(&index, &tree, &mut tree_log, Some(3), 2);
+        log.reparent(&index, &tree, &mut tree_log, None, 4);
+
+        assert_eq!(tree_log.parent(&tree, 2), Some(3));
+        assert_eq!(sorted(log.subtree_items(&index, 3)), vec![20]);
+        assert_eq!(sorted(log.subtree_items(&index, 1)), vec![20]);
+        assert_eq!(index.verify(&tree, &tree_log, &log), Ok(()));
+    }
+
+    #[test]
+    fn reparent_refuses_cycles() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        let index = builder.build();
+
+        let mut tree_log = TreeLog::new();
+        let mut log = NodeSetIndexLog::new();
+
+        assert!(!log.reparent(&index, &tree, &mut tree_log, Some(4), 1));
+        assert!(!log.reparent(&index, &tree, &mut tree_log, Some(2), 2));
+        assert!(log.reparent(&index, &tree, &mut tree_log, Some(1), 2));
+        assert_eq!(tree_log.parent(&tree, 1), None);
+        assert_eq!(sorted(log.subtree_items(&index, 1)), vec![10]);
+        assert_eq!(index.verify(&tree, &tree_log, &log), Ok(()));
+    }
+
+    #[test]
+    fn iterate_nodes_and_sets() {
+        let tree = tree();
+        let mut builder = NodeSetIndexBuilder::new();
+        builder.insert(&tree, 4, 10);
+        let index = builder.build();
+
+        let mut nodes = index.nodes().collect::<Vec<_>>();
+        nodes.sort_unstable();
+        assert_eq!(nodes, vec![4]);
+
+        let mut subtree = index.iter_subtree().map(|(n, _)| n).collect::<Vec<_>>();
+        subtree.sort_unstable();
+        assert_eq!(subtree, vec![1, 2, 4]);
+
+        assert!(index.iter_direct().all(|(_, s)| s.contains(&10)));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_rebuild_log_matches_rebuild_log() {
+        let tree = (1..300u32).map(|n| (n, Some(n / 2))).collect::<Tree>();
+        let tree_log = TreeLog::new();
+        let mut index = NodeSetIndex::new();
+        let mut log = NodeSetIndexLog::new();
+
+        for n in (1..300).step_by(7) {
+            log.insert(&index, &tree, &tree_log, n, n % 11);
+        }
+
+        index.apply(log);
+
+        let seq = index.rebuild_log(&tree, &tree_log);
+        let par = index.par_rebuild_log(&tree, &tree_log);
+        assert_eq!(par.subtree_items, seq.subtree_items);
+
+        let mut rebuilt = index.clone();
+        assert!(!rebuilt.par_rebuild(&tree));
+    }
+}
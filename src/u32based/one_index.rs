@@ -1,9 +1,34 @@
+use crate::{
+    U32Set,
+    codec::{CodecValue, DecodeError, expect_end, read_varint, write_varint},
+};
 use rustc_hash::FxHashMap;
-use std::collections::hash_map::Entry;
+use std::{
+    collections::hash_map::Entry,
+    fmt::{self, Debug},
+    ops::{Bound, RangeBounds},
+};
 
+/// Slots a loaded index may allocate per entry read when no bound is given.
+const SLOTS_PER_ENTRY: usize = 64;
+
+/// Slots a loaded index may always allocate, however few entries are read.
+const MIN_SLOTS: usize = 1 << 16;
+
+/// Highest key accepted when loading `entries` pairs without an explicit
+/// bound, so the slot vector stays proportional to the input.
+pub(crate) fn load_max_id(entries: usize) -> u32 {
+    let slots = entries.saturating_mul(SLOTS_PER_ENTRY).max(MIN_SLOTS);
+    u32::try_from(slots - 1)
+        .unwrap_or(u32::MAX)
+        .min(u32::MAX - 1)
+}
+
+#[derive(Clone)]
 pub struct OneIndex<V> {
     data: Vec<Option<V>>,
     len: usize,
+    occupied: Option<Box<U32Set>>,
 }
 
 impl<V> OneIndex<V> {
@@ -12,6 +37,7 @@ impl<V> OneIndex<V> {
         Self {
             data: Vec::new(),
             len: 0,
+            occupied: None,
         }
     }
 
@@ -20,6 +46,7 @@ impl<V> OneIndex<V> {
         Self {
             data: Vec::with_capacity(capacity),
             len: 0,
+            occupied: None,
         }
     }
 
@@ -28,7 +55,25 @@ impl<V> OneIndex<V> {
         V: PartialEq,
     {
         let mut changes = false;
+        self.apply_with(log, |_, _| changes = true);
+        changes
+    }
 
+    /// Like [`apply`](Self::apply), but reports each effective change.
+    pub fn apply_report(&mut self, log: OneIndexLog<V>) -> Vec<(u32, OneIndexChange)>
+    where
+        V: PartialEq,
+    {
+        let mut changes = Vec::new();
+        self.apply_with(log, |k, c| changes.push((k, c)));
+        changes
+    }
+
+    fn apply_with<F>(&mut self, log: OneIndexLog<V>, mut on_change: F)
+    where
+        F: FnMut(u32, OneIndexChange),
+        V: PartialEq,
+    {
         let new_len = log
             .0
             .iter()
@@ -41,8 +86,8 @@ impl<V> OneIndex<V> {
             self.data.resize_with(new_len, || None);
         }
 
-        for (index, value) in log.0 {
-            let index = index as usize;
+        for (key, value) in log.0 {
+            let index = key as usize;
 
             match value {
                 Some(v) => {
@@ -52,10 +97,17 @@ impl<V> OneIndex<V> {
                     if *slot != new {
                         if slot.is_none() {
                             self.len += 1;
+
+                            if let Some(o) = &mut self.occupied {
+                                o.insert(key);
+                            }
+
+                            on_change(key, OneIndexChange::Inserted);
+                        } else {
+                            on_change(key, OneIndexChange::Replaced);
                         }
 
                         *slot = new;
-                        changes = true;
                     }
                 }
                 None => {
@@ -63,15 +115,86 @@ impl<V> OneIndex<V> {
                         let old = slot.take();
 
                         if old.is_some() {
-                            changes = true;
                             self.len -= 1;
+
+                            if let Some(o) = &mut self.occupied {
+                                o.remove(&key);
+                            }
+
+                            on_change(key, OneIndexChange::Removed);
                         }
                     }
                 }
             }
         }
+    }
 
-        changes
+    /// Builds the index directly, without staging through a log. Entries are
+    /// best given in ascending key order; a repeated key keeps its last value.
+    pub fn bulk_load<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, V)>,
+    {
+        let mut index = Self::new();
+
+        for (k, v) in iter {
+            index.put(k, v);
+        }
+
+        index
+    }
+
+    /// Like [`bulk_load`](Self::bulk_load), but fails on a key above `max_id`
+    /// before any slot is allocated.
+    pub(crate) fn bulk_load_checked(
+        entries: Vec<(u32, V)>,
+        max_id: u32,
+    ) -> Result<Self, crate::KeyOutOfRange> {
+        for (k, _) in &entries {
+            crate::strict::check_key(*k, max_id)?;
+        }
+
+        Ok(Self::bulk_load(entries))
+    }
+
+    fn put(&mut self, key: u32, value: V) {
+        let i = key as usize;
+
+        if i >= self.data.len() {
+            self.data.resize_with(i + 1, || None);
+        }
+
+        if self.data[i].replace(value).is_none() {
+            self.len += 1;
+
+            if let Some(o) = &mut self.occupied {
+                o.insert(key);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.len = 0;
+
+        if let Some(o) = &mut self.occupied {
+            o.clear();
+        }
+    }
+
+    /// Maintains the set of occupied indexes so that [`iter`](Self::iter) and
+    /// [`keys`](Self::keys) skip empty slots, at the cost of yielding in
+    /// arbitrary order. Worth it for large, sparse indexes.
+    pub fn enable_occupied(&mut self) {
+        if self.occupied.is_none() {
+            self.occupied = Some(Box::new(self.keys().collect()));
+        }
+    }
+
+    #[inline]
+    pub fn first_key(&self) -> Option<u32> {
+        self.data.iter().position(Option::is_some).map(|i| i as u32)
     }
 
     #[inline]
@@ -79,11 +202,20 @@ impl<V> OneIndex<V> {
         self.data.get(index as usize).and_then(|v| v.as_ref())
     }
 
+    #[inline]
+    pub fn get_mut(&mut self, index: u32) -> Option<&mut V> {
+        self.data.get_mut(index as usize).and_then(|v| v.as_mut())
+    }
+
+    #[inline]
+    pub fn has_occupied(&self) -> bool {
+        self.occupied.is_some()
+    }
+
+    /// Entries in ascending order, or arbitrary order once
+    /// [`enable_occupied`](Self::enable_occupied) was called.
     pub fn iter(&self) -> impl Iterator<Item = (u32, &V)> + '_ {
-        self.data
-            .iter()
-            .enumerate()
-            .filter_map(|(i, v)| v.as_ref().map(|v| (i as u32, v)))
+        self.keys().filter_map(|k| Some((k, self.get(k)?)))
     }
 
     #[inline]
@@ -96,11 +228,161 @@ impl<V> OneIndex<V> {
         self.len
     }
 
-    pub fn keys(&self) -> impl Iterator<Item = u32> + '_ {
+    #[inline]
+    pub fn last_key(&self) -> Option<u32> {
         self.data
+            .iter()
+            .rposition(Option::is_some)
+            .map(|i| i as u32)
+    }
+
+    pub fn memory_usage(&self) -> OneIndexMemory {
+        OneIndexMemory {
+            capacity: self.data.capacity(),
+            occupied: self.len,
+            slots: self.data.len(),
+        }
+    }
+
+    /// Keys in ascending order, or arbitrary order once
+    /// [`enable_occupied`](Self::enable_occupied) was called.
+    pub fn keys(&self) -> impl Iterator<Item = u32> + '_ {
+        let (dense, sparse) = match &self.occupied {
+            Some(o) => (None, Some(o.iter().copied())),
+            None => (Some(self.range(..).map(|(k, _)| k)), None),
+        };
+
+        dense
+            .into_iter()
+            .flatten()
+            .chain(sparse.into_iter().flatten())
+    }
+
+    /// Occupied slots over allocated ones, `1.0` when nothing is allocated.
+    pub fn occupancy(&self) -> f64 {
+        match self.data.capacity() {
+            0 => 1.0,
+            c => self.len as f64 / c as f64,
+        }
+    }
+
+    /// Entries whose index falls in `range`, in ascending order.
+    pub fn range<R: RangeBounds<u32>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = (u32, &V)> + '_ {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s as usize,
+            Bound::Excluded(&s) => s as usize + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e as usize + 1,
+            Bound::Excluded(&e) => e as usize,
+            Bound::Unbounded => usize::MAX,
+        };
+
+        let end = end.min(self.data.len());
+        let start = start.min(end);
+
+        self.data[start..end]
             .iter()
             .enumerate()
-            .filter_map(|(i, v)| v.as_ref().map(|_| i as u32))
+            .filter_map(move |(i, v)| v.as_ref().map(|v| ((start + i) as u32, v)))
+    }
+
+    /// Trims trailing empty slots and releases the spare capacity.
+    pub fn shrink_to_fit(&mut self) {
+        let used = self
+            .data
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |i| i + 1);
+        self.data.truncate(used);
+        self.data.shrink_to_fit();
+    }
+
+    /// Removes every entry at `len` or above.
+    pub fn truncate(&mut self, len: u32) {
+        let len = len as usize;
+
+        if len < self.data.len() {
+            self.len -= self.data[len..].iter().filter(|v| v.is_some()).count();
+            self.data.truncate(len);
+
+            if let Some(o) = &mut self.occupied {
+                o.retain(|k| (*k as usize) < len);
+            }
+        }
+    }
+
+    /// Mutates the value at `index` in place, bypassing the log. Returns `false`
+    /// if there is none.
+    #[inline]
+    pub fn update<F: FnOnce(&mut V)>(&mut self, index: u32, f: F) -> bool {
+        self.get_mut(index).map(f).is_some()
+    }
+
+    /// Keeps the entries for which `f` returns `true`.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(u32, &mut V) -> bool,
+    {
+        for (i, slot) in self.data.iter_mut().enumerate() {
+            if slot.as_mut().is_some_and(|v| !f(i as u32, v)) {
+                *slot = None;
+                self.len -= 1;
+
+                if let Some(o) = &mut self.occupied {
+                    o.remove(&(i as u32));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<V: Send> rayon::iter::FromParallelIterator<(u32, V)> for OneIndex<V> {
+    fn from_par_iter<I>(iter: I) -> Self
+    where
+        I: rayon::iter::IntoParallelIterator<Item = (u32, V)>,
+    {
+        use rayon::prelude::*;
+
+        let mut entries = iter.into_par_iter().collect::<Vec<_>>();
+        entries.par_sort_unstable_by_key(|(k, _)| *k);
+
+        let mut index = Self::with_capacity(entries.last().map_or(0, |(k, _)| *k as usize + 1));
+
+        for (k, v) in entries {
+            index.put(k, v);
+        }
+
+        index
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OneIndexMemory {
+    /// Allocated slots.
+    pub capacity: usize,
+    /// Slots holding a value.
+    pub occupied: usize,
+    /// Slots in use, up to the highest index ever stored.
+    pub slots: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OneIndexChange {
+    Inserted,
+    Replaced,
+    Removed,
+}
+
+impl<V: Debug> Debug for OneIndex<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
@@ -132,6 +414,52 @@ where
     }
 }
 
+/// Only occupied slots are written, as a sequence of `(index, value)` pairs.
+#[cfg(feature = "serde")]
+impl<V: serde::Serialize> serde::Serialize for OneIndex<V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+
+        for entry in self.iter() {
+            seq.serialize_element(&entry)?;
+        }
+
+        seq.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<V> OneIndex<V> {
+    /// Like [`Deserialize`](serde::Deserialize), but takes the key bound
+    /// instead of deriving it from the entry count. Use it for sparse indexes.
+    pub fn deserialize_with_max_id<'de, D>(deserializer: D, max_id: u32) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        V: serde::Deserialize<'de>,
+    {
+        use serde::Deserialize;
+
+        let entries = Vec::<(u32, V)>::deserialize(deserializer)?;
+        Self::bulk_load_checked(entries, max_id).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Keys must stay below 64 slots per entry read (and at least 65 536), so a
+/// single huge key cannot size the slot vector. Sparser indexes load through
+/// [`deserialize_with_max_id`](OneIndex::deserialize_with_max_id).
+#[cfg(feature = "serde")]
+impl<'de, V: serde::Deserialize<'de>> serde::Deserialize<'de> for OneIndex<V> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(u32, V)>::deserialize(deserializer)?;
+        let max_id = load_max_id(entries.len());
+
+        Self::bulk_load_checked(entries, max_id).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct OneIndexLog<V>(
     // Some = insert / replace,
     // None = remove
@@ -144,6 +472,19 @@ impl<V> OneIndexLog<V> {
         Self(FxHashMap::default())
     }
 
+    /// Lowest index holding a value once the log is applied to `base`.
+    pub fn first_key(&self, base: &OneIndex<V>) -> Option<u32> {
+        let staged = self
+            .0
+            .iter()
+            .filter(|(_, v)| v.is_some())
+            .map(|(k, _)| *k)
+            .min();
+        let base = base.range(..).map(|(k, _)| k).find(|k| !self.removes(*k));
+
+        staged.into_iter().chain(base).min()
+    }
+
     #[inline]
     pub fn get<'a>(&'a self, base: &'a OneIndex<V>, index: u32) -> Option<&'a V> {
         match self.0.get(&index) {
@@ -152,6 +493,93 @@ impl<V> OneIndexLog<V> {
         }
     }
 
+    /// Stages `value` only if the current value at `index` is still `expected`.
+    pub fn insert_if(
+        &mut self,
+        base: &OneIndex<V>,
+        index: u32,
+        expected: Option<&V>,
+        value: V,
+    ) -> bool
+    where
+        V: PartialEq,
+    {
+        if self.get(base, index) != expected {
+            return false;
+        }
+
+        self.insert(base, index, value);
+        true
+    }
+
+    /// Highest index holding a value once the log is applied to `base`.
+    pub fn last_key(&self, base: &OneIndex<V>) -> Option<u32> {
+        let staged = self
+            .0
+            .iter()
+            .filter(|(_, v)| v.is_some())
+            .map(|(k, _)| *k)
+            .max();
+        let base = base
+            .range(..)
+            .rev()
+            .map(|(k, _)| k)
+            .find(|k| !self.removes(*k));
+
+        staged.into_iter().chain(base).max()
+    }
+
+    /// Entries whose index falls in `range` once the log is applied to `base`,
+    /// in ascending order.
+    pub fn range<'a, R: RangeBounds<u32>>(
+        &'a self,
+        base: &'a OneIndex<V>,
+        range: R,
+    ) -> Vec<(u32, &'a V)> {
+        let mut out = base
+            .range((range.start_bound().cloned(), range.end_bound().cloned()))
+            .filter(|(k, _)| !self.0.contains_key(k))
+            .collect::<Vec<_>>();
+
+        out.extend(
+            self.0
+                .iter()
+                .filter(|(k, _)| range.contains(*k))
+                .filter_map(|(k, v)| Some((*k, v.as_ref()?))),
+        );
+
+        out.sort_unstable_by_key(|(k, _)| *k);
+        out
+    }
+
+    /// Folds `other` into this log. When both logs staged different edits for
+    /// the same index, `conflict` gets both (`None` for a removal) and picks one.
+    pub fn merge<F>(&mut self, other: Self, mut conflict: F)
+    where
+        F: FnMut(u32, Option<&V>, Option<&V>) -> Resolution,
+        V: PartialEq,
+    {
+        for (k, theirs) in other.0 {
+            match self.0.entry(k) {
+                Entry::Vacant(e) => {
+                    e.insert(theirs);
+                }
+                Entry::Occupied(mut e) => {
+                    if *e.get() != theirs
+                        && conflict(k, e.get().as_ref(), theirs.as_ref()) == Resolution::Theirs
+                    {
+                        e.insert(theirs);
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn removes(&self, index: u32) -> bool {
+        matches!(self.0.get(&index), Some(None))
+    }
+
     pub fn insert(&mut self, base: &OneIndex<V>, index: u32, value: V)
     where
         V: PartialEq,
@@ -170,6 +598,28 @@ impl<V> OneIndexLog<V> {
         }
     }
 
+    /// Stages a modified copy of the current value at `index`. Returns `false`
+    /// if there is none.
+    pub fn update<F>(&mut self, base: &OneIndex<V>, index: u32, f: F) -> bool
+    where
+        F: FnOnce(&mut V),
+        V: Clone + PartialEq,
+    {
+        let Some(mut value) = self.get(base, index).cloned() else {
+            return false;
+        };
+
+        f(&mut value);
+        self.insert(base, index, value);
+        true
+    }
+
+    /// Indexes staged for insertion, replacement or removal.
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.keys().copied()
+    }
+
     pub fn remove(&mut self, base: &OneIndex<V>, index: u32)
     where
         V: PartialEq,
@@ -187,9 +637,279 @@ impl<V> OneIndexLog<V> {
     }
 }
 
+/// Which side of a [`OneIndexLog::merge`] conflict to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+}
+
+impl<V: CodecValue> OneIndexLog<V> {
+    /// Encodes the staged inserts and removals as a write-ahead log record.
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        let mut entries = self.0.iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(k, _)| **k);
+
+        let mut out = Vec::new();
+        write_varint(&mut out, entries.len() as u32);
+
+        for (k, v) in entries {
+            write_varint(&mut out, *k);
+
+            match v {
+                Some(v) => {
+                    out.push(1);
+                    v.encode(&mut out);
+                }
+                None => out.push(0),
+            }
+        }
+
+        out
+    }
+
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    pub fn from_wal_bytes(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let b = &mut bytes;
+        let len = read_varint(b)? as usize;
+        let mut map = FxHashMap::with_capacity_and_hasher(len.min(b.len()), Default::default());
+
+        for _ in 0..len {
+            let k = read_varint(b)?;
+
+            let v = match read_varint(b)? {
+                0 => None,
+                1 => Some(V::decode(b)?),
+                _ => return Err(DecodeError::Invalid),
+            };
+
+            map.insert(k, v);
+        }
+
+        expect_end(bytes)?;
+        Ok(Self(map))
+    }
+}
+
 impl<V> Default for OneIndexLog<V> {
     #[inline]
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain_and_clear_keep_len() {
+        let mut index = OneIndex::from_iter([(1, 10), (4, 40), (7, 70)]);
+
+        index.retain(|k, v| {
+            *v += 1;
+            k != 4
+        });
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(7), Some(&71));
+        assert_eq!(index.get(4), None);
+
+        index.clear();
+        assert!(index.is_empty());
+        assert_eq!(index.keys().count(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_writes_occupied_slots_only() {
+        let index = OneIndex::from_iter([(2, "a"), (9, "b")]);
+        let json = serde_json::to_string(&index).unwrap();
+        assert_eq!(json, r#"[[2,"a"],[9,"b"]]"#);
+
+        let back = serde_json::from_str::<OneIndex<String>>(&json).unwrap();
+        assert_eq!(back.len(), 2);
+        assert_eq!(back.data.len(), 10);
+        assert_eq!(back.get(9).map(String::as_str), Some("b"));
+
+        let sentinel = format!(r#"[[1,"a"],[{},"b"]]"#, u32::MAX);
+        assert!(serde_json::from_str::<OneIndex<String>>(&sentinel).is_err());
+
+        let sparse = format!(r#"[[1,"a"],[{},"b"]]"#, u32::MAX - 1);
+        assert!(serde_json::from_str::<OneIndex<String>>(&sparse).is_err());
+
+        let json = r#"[[1,"a"],[1000000,"b"]]"#;
+        assert!(serde_json::from_str::<OneIndex<String>>(json).is_err());
+
+        let mut de = serde_json::Deserializer::from_str(json);
+        let back = OneIndex::<String>::deserialize_with_max_id(&mut de, 1_000_000).unwrap();
+        assert_eq!(back.get(1_000_000).map(String::as_str), Some("b"));
+
+        let mut de = serde_json::Deserializer::from_str(json);
+        assert!(OneIndex::<String>::deserialize_with_max_id(&mut de, 999_999).is_err());
+    }
+
+    #[test]
+    fn update_in_place_and_through_log() {
+        let mut index = OneIndex::from_iter([(1, 10)]);
+
+        assert!(index.update(1, |v| *v += 1));
+        assert!(!index.update(2, |v| *v += 1));
+        assert_eq!(index.get(1), Some(&11));
+
+        let mut log = OneIndexLog::new();
+        assert!(log.update(&index, 1, |v| *v *= 2));
+        assert!(log.update(&index, 1, |v| *v += 1));
+        assert_eq!(index.get(1), Some(&11));
+
+        index.apply(log);
+        assert_eq!(index.get(1), Some(&23));
+    }
+
+    #[test]
+    fn apply_report_lists_changes() {
+        let mut index = OneIndex::from_iter([(1, 10), (2, 20)]);
+        let mut log = OneIndexLog::new();
+
+        log.insert(&index, 1, 11);
+        log.insert(&index, 2, 20);
+        log.insert(&index, 3, 30);
+        log.remove(&index, 2);
+
+        let mut changes = index.apply_report(log);
+        changes.sort_unstable_by_key(|(k, _)| *k);
+
+        assert_eq!(
+            changes,
+            vec![
+                (1, OneIndexChange::Replaced),
+                (2, OneIndexChange::Removed),
+                (3, OneIndexChange::Inserted),
+            ]
+        );
+    }
+
+    #[test]
+    fn shrink_and_truncate_trim_slots() {
+        let mut index = OneIndex::from_iter([(1, 10), (50, 500), (90, 900)]);
+        let mut log = OneIndexLog::new();
+        log.remove(&index, 90);
+        index.apply(log);
+
+        assert_eq!(index.memory_usage().slots, 91);
+
+        index.shrink_to_fit();
+        let mem = index.memory_usage();
+        assert_eq!((mem.slots, mem.occupied), (51, 2));
+
+        index.truncate(10);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get(50), None);
+    }
+
+    #[test]
+    fn bulk_load_fills_slots() {
+        let index = OneIndex::bulk_load([(1, "a"), (3, "b"), (3, "c")]);
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(3), Some(&"c"));
+        assert_eq!(index.memory_usage().slots, 4);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn from_par_iter_matches_from_iter() {
+        use rayon::prelude::*;
+
+        let index = (0..1000u32)
+            .into_par_iter()
+            .map(|k| (k * 2, k))
+            .collect::<OneIndex<_>>();
+
+        assert_eq!(index.len(), 1000);
+        assert_eq!(index.get(1998), Some(&999));
+        assert_eq!(index.memory_usage().slots, 1999);
+    }
+
+    #[test]
+    fn ordered_scans_with_and_without_log() {
+        let index = OneIndex::from_iter([(2, 'a'), (5, 'b'), (9, 'c')]);
+
+        assert_eq!((index.first_key(), index.last_key()), (Some(2), Some(9)));
+        assert_eq!(
+            index.range(3..=9).collect::<Vec<_>>(),
+            vec![(5, &'b'), (9, &'c')]
+        );
+        assert_eq!(index.range(20..).count(), 0);
+
+        let mut log = OneIndexLog::new();
+        log.remove(&index, 2);
+        log.remove(&index, 9);
+        log.insert(&index, 6, 'd');
+        log.insert(&index, 5, 'e');
+
+        assert_eq!(
+            (log.first_key(&index), log.last_key(&index)),
+            (Some(5), Some(6))
+        );
+        assert_eq!(log.range(&index, ..), vec![(5, &'e'), (6, &'d')]);
+        assert_eq!(OneIndex::<u8>::new().first_key(), None);
+    }
+
+    #[test]
+    fn insert_if_compares_current_value() {
+        let index = OneIndex::from_iter([(1, 10)]);
+        let mut log = OneIndexLog::new();
+
+        assert!(!log.insert_if(&index, 1, Some(&9), 11));
+        assert!(log.insert_if(&index, 1, Some(&10), 11));
+        assert!(!log.insert_if(&index, 1, Some(&10), 12));
+        assert!(!log.insert_if(&index, 2, Some(&10), 20));
+        assert!(log.insert_if(&index, 2, None, 20));
+        assert_eq!(log.get(&index, 1), Some(&11));
+    }
+
+    #[test]
+    fn merge_resolves_conflicts() {
+        let index = OneIndex::from_iter([(1, 10), (2, 20)]);
+        let mut ours = OneIndexLog::new();
+        let mut theirs = OneIndexLog::new();
+
+        ours.insert(&index, 1, 11);
+        ours.insert(&index, 3, 30);
+        theirs.insert(&index, 1, 12);
+        theirs.insert(&index, 3, 30);
+        theirs.remove(&index, 2);
+
+        let mut conflicts = Vec::new();
+
+        ours.merge(theirs, |k, a, b| {
+            conflicts.push((k, a.copied(), b.copied()));
+            Resolution::Theirs
+        });
+
+        assert_eq!(conflicts, vec![(1, Some(11), Some(12))]);
+        assert_eq!(ours.get(&index, 1), Some(&12));
+        assert_eq!(ours.get(&index, 2), None);
+        assert_eq!(ours.get(&index, 3), Some(&30));
+    }
+
+    #[test]
+    fn occupied_set_follows_mutations() {
+        let mut index = OneIndex::bulk_load([(1, 'a'), (1000, 'b')]);
+        index.enable_occupied();
+
+        let mut log = OneIndexLog::new();
+        log.insert(&index, 500, 'c');
+        log.remove(&index, 1);
+        index.apply(log);
+        index.retain(|k, _| k != 1000);
+
+        assert_eq!(index.keys().collect::<Vec<_>>(), vec![500]);
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![(500, &'c')]);
+        assert!(index.occupancy() < 0.01);
+
+        index.clear();
+        assert_eq!(index.keys().count(), 0);
+    }
+}
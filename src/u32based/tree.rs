@@ -1,4 +1,11 @@
-use crate::{U32Set, empty_roaring};
+use crate::{
+    U32Set,
+    codec::{
+        DecodeError, decode_option_set, decode_option_u32, decode_set_map, encode_option_set,
+        encode_option_u32, encode_set_map, expect_end, read_varint, write_varint,
+    },
+    empty_roaring,
+};
 use intern::IU32HashSet;
 use once_cell::sync::OnceCell;
 use rustc_hash::{FxHashMap, FxHashSet};
@@ -110,6 +117,27 @@ impl Tree {
         changed
     }
 
+    /// Like [`apply`](Self::apply), but returns the nodes whose parent changed
+    /// with their new parent, sorted by node. Detached nodes get `None`.
+    pub fn apply_report(&mut self, log: TreeLog) -> Vec<(u32, Option<u32>)> {
+        let moved = self.reparented(&log);
+        self.apply(log);
+        moved
+    }
+
+    /// Nodes whose parent `log` changes, with their new parent.
+    pub(crate) fn reparented(&self, log: &TreeLog) -> Vec<(u32, Option<u32>)> {
+        let mut moved = log
+            .parents
+            .iter()
+            .filter(|(node, parent)| self.parents.get(node) != parent.as_ref())
+            .map(|(node, parent)| (*node, *parent))
+            .collect::<Vec<_>>();
+
+        moved.sort_unstable();
+        moved
+    }
+
     #[inline]
     pub fn all_nodes(&self) -> &FxHashSet<u32> {
         &self.all
@@ -192,6 +220,102 @@ impl FromIterator<(u32, Option<u32>)> for Tree {
     }
 }
 
+/// Builds the parent, child and descendant maps on the rayon pool. Inputs
+/// containing a cycle fall back to the sequential [`FromIterator`] path, which
+/// owns the cycle bookkeeping.
+#[cfg(feature = "rayon")]
+impl rayon::iter::FromParallelIterator<(u32, Option<u32>)> for Tree {
+    fn from_par_iter<I>(iter: I) -> Self
+    where
+        I: rayon::iter::IntoParallelIterator<Item = (u32, Option<u32>)>,
+    {
+        use rayon::prelude::*;
+
+        fn merge(
+            mut a: FxHashMap<u32, U32Set>,
+            b: FxHashMap<u32, U32Set>,
+        ) -> FxHashMap<u32, U32Set> {
+            for (k, v) in b {
+                a.entry(k).or_default().extend(v);
+            }
+
+            a
+        }
+
+        fn intern(map: FxHashMap<u32, U32Set>) -> FxHashMap<u32, IU32HashSet> {
+            map.into_par_iter().map(|(k, v)| (k, v.into())).collect()
+        }
+
+        let entries = iter.into_par_iter().collect::<Vec<_>>();
+        let mut parents = FxHashMap::default();
+        let mut all = FxHashSet::default();
+
+        // a repeated child keeps its last parent, as with sequential inserts
+        for (child, parent) in &entries {
+            all.insert(*child);
+            all.extend(*parent);
+
+            match parent {
+                Some(p) => parents.insert(*child, *p),
+                None => parents.remove(child),
+            };
+        }
+
+        let nodes = all.iter().copied().collect::<Vec<_>>();
+
+        let has_cycle = nodes.par_iter().any(|n| {
+            let mut cur = parents.get(n);
+
+            for _ in 0..nodes.len() {
+                match cur {
+                    Some(p) if p == n => return true,
+                    Some(p) => cur = parents.get(p),
+                    None => return false,
+                }
+            }
+
+            true
+        });
+
+        if has_cycle {
+            return entries.into_iter().collect();
+        }
+
+        let children = parents
+            .par_iter()
+            .fold(
+                FxHashMap::default,
+                |mut acc: FxHashMap<u32, U32Set>, (c, p)| {
+                    acc.entry(*p).or_default().insert(*c);
+                    acc
+                },
+            )
+            .reduce(FxHashMap::default, merge);
+
+        let descendants = nodes
+            .par_iter()
+            .fold(FxHashMap::default, |mut acc: FxHashMap<u32, U32Set>, n| {
+                let mut cur = parents.get(n);
+
+                while let Some(p) = cur {
+                    acc.entry(*p).or_default().insert(*n);
+                    cur = parents.get(p);
+                }
+
+                acc
+            })
+            .reduce(FxHashMap::default, merge);
+
+        Self {
+            all,
+            children: intern(children),
+            cycles: Set::default(),
+            descendants: intern(descendants),
+            parents,
+        }
+    }
+}
+
 pub struct ItemsView<'a> {
     node: u32,
     inner: &'a U32Set,
@@ -545,6 +669,71 @@ impl TreeLog {
     }
 }
 
+impl TreeLog {
+    /// Encodes the staged nodes and links as a write-ahead log record.
+    pub fn to_wal_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut all = self.all.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        let mut parents = self
+            .parents
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .collect::<Vec<_>>();
+
+        all.sort_unstable();
+        parents.sort_unstable();
+
+        write_varint(&mut out, all.len() as u32);
+
+        for (node, present) in all {
+            write_varint(&mut out, node);
+            write_varint(&mut out, present as u32);
+        }
+
+        write_varint(&mut out, parents.len() as u32);
+
+        for (node, parent) in parents {
+            write_varint(&mut out, node);
+            encode_option_u32(parent, &mut out);
+        }
+
+        encode_set_map(&self.children, &mut out);
+        encode_set_map(&self.descendants, &mut out);
+        encode_option_set(self.cycles.as_ref(), &mut out);
+        out
+    }
+
+    /// Reads a record written by [`to_wal_bytes`](Self::to_wal_bytes).
+    pub fn from_wal_bytes(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let b = &mut bytes;
+        let mut log = Self::new();
+
+        for _ in 0..read_varint(b)? {
+            let node = read_varint(b)?;
+
+            let present = match read_varint(b)? {
+                0 => false,
+                1 => true,
+                _ => return Err(DecodeError::Invalid),
+            };
+
+            log.all.insert(node, present);
+        }
+
+        for _ in 0..read_varint(b)? {
+            let node = read_varint(b)?;
+            log.parents.insert(node, decode_option_u32(b)?);
+        }
+
+        log.children = decode_set_map(b)?;
+        log.descendants = decode_set_map(b)?;
+        log.cycles = decode_option_set(b)?;
+
+        expect_end(bytes)?;
+        Ok(log)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct CycleError(pub u32);
 
@@ -775,7 +964,7 @@ mod tests {
         log.insert(&base, Some(3), 1); // cycle
 
         let anc: Vec<_> = log.ancestors(&base, 3).collect();
-        assert_eq!(anc, vec![]); // stops before re-entering cycle
+        assert_eq!(anc, Vec::<u32>::new()); // stops before re-entering cycle
     }
 
     #[test]
@@ -1015,4 +1204,29 @@ mod tests {
         assert!(tree2.all_nodes().contains(&100));
         assert!(tree2.all_nodes().contains(&200));
     }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_from_iter_matches_sequential() {
+        use rayon::prelude::*;
+
+        let mut entries = (1..500u32).map(|n| (n, Some(n / 3))).collect::<Vec<_>>();
+        entries.push((7, Some(8)));
+
+        let cyclic = [(1, Some(2)), (2, Some(1)), (3, Some(1))];
+
+        for entries in [entries, cyclic.to_vec()] {
+            let seq = entries.iter().copied().collect::<Tree>();
+            let par = entries.into_par_iter().collect::<Tree>();
+
+            assert_eq!(par.all_nodes(), seq.all_nodes());
+            assert_eq!(par.cycles, seq.cycles);
+
+            for n in seq.all_nodes() {
+                assert_eq!(par.parent(*n), seq.parent(*n));
+                assert_eq!(par.children(*n), seq.children(*n));
+                assert_eq!(par.descendants(*n), seq.descendants(*n));
+            }
+        }
+    }
 }
@@ -0,0 +1,176 @@
+use crate::Apply;
+use std::{
+    collections::VecDeque,
+    ops::Deref,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
+
+/// Keeps the last generations of an index so readers can pin one while
+/// writers keep committing logs.
+///
+/// Each commit clones the current generation and applies the log to the
+/// copy; the interned sets held by the indexes are shared, not copied. A
+/// pinned [`Version`] stays readable after it falls out of the retained
+/// window.
+pub struct VersionedIndex<T> {
+    generations: RwLock<VecDeque<Version<T>>>,
+    retain: usize,
+    writer: Mutex<()>,
+}
+
+impl<T> VersionedIndex<T> {
+    /// Starts at version 0 with `index`, retaining up to `retain` generations
+    /// (at least one).
+    pub fn new(index: T, retain: usize) -> Self {
+        Self {
+            generations: RwLock::new(VecDeque::from([Version {
+                index: Arc::new(index),
+                version: 0,
+            }])),
+            retain: retain.max(1),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Applies `log` to a copy of the current generation and publishes it.
+    /// Returns the current version unchanged when the log changes nothing.
+    ///
+    /// Stage `log` against [`current`](Self::current): a log staged on an older
+    /// generation overwrites whatever it touched with its stale state.
+    pub fn commit(&self, log: T::Log) -> Version<T>
+    where
+        T: Apply + Clone,
+    {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let current = self.current();
+        let mut index = T::clone(&current);
+
+        if !index.apply(log) {
+            return current;
+        }
+
+        let next = Version {
+            index: Arc::new(index),
+            version: current.version + 1,
+        };
+
+        let mut generations = self
+            .generations
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        generations.push_back(next.clone());
+
+        while generations.len() > self.retain {
+            generations.pop_front();
+        }
+
+        next
+    }
+
+    /// Pins the latest generation.
+    #[inline]
+    pub fn current(&self) -> Version<T> {
+        self.generations
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .back()
+            .cloned()
+            .expect("at least one generation")
+    }
+
+    /// Pins `version` if it is still retained.
+    pub fn get(&self, version: u64) -> Option<Version<T>> {
+        let generations = self
+            .generations
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let first = generations.front()?.version;
+        generations
+            .get(version.checked_sub(first)? as usize)
+            .cloned()
+    }
+
+    /// Number of the latest generation.
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.current().version
+    }
+
+    /// Retained versions, oldest first.
+    pub fn versions(&self) -> Vec<u64> {
+        self.generations
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|g| g.version)
+            .collect()
+    }
+}
+
+/// A pinned generation of a [`VersionedIndex`].
+pub struct Version<T> {
+    index: Arc<T>,
+    version: u64,
+}
+
+impl<T> Version<T> {
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl<T> Clone for Version<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            index: Arc::clone(&self.index),
+            version: self.version,
+        }
+    }
+}
+
+impl<T> Deref for Version<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FastSetTrx, FlatSetIndex};
+
+    #[test]
+    fn pinned_versions_survive_commits() {
+        let store = VersionedIndex::new(FlatSetIndex::<u32, u32>::new(), 2);
+        let pinned = store.current();
+
+        for v in 1..=3 {
+            let current = store.current();
+            let mut trx = FastSetTrx::new(&*current);
+            let (base, log) = trx.parts_mut();
+            log.insert(base, 1, v);
+
+            assert_eq!(store.commit(trx.into_log()).version(), u64::from(v));
+        }
+
+        assert_eq!(store.version(), 3);
+        assert_eq!(store.versions(), vec![2, 3]);
+        assert!(store.get(1).is_none());
+        assert!(store.get(2).unwrap().contains(1, 2));
+        assert!(!store.get(2).unwrap().contains(1, 3));
+        assert!(store.current().contains(1, 3));
+
+        assert_eq!(pinned.version(), 0);
+        assert!(pinned.get(1).is_empty());
+
+        let unchanged = store.commit(Default::default());
+        assert_eq!(unchanged.version(), 3);
+    }
+}
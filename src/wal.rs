@@ -0,0 +1,214 @@
+use crate::{
+    Apply, FlatSetIndexLog, NodeSetIndexLog, TreeIndexLog,
+    codec::{CodecValue, DecodeError, expect_end, read_varint, write_varint},
+    one_index::OneIndexLog,
+};
+
+/// A log that round-trips through a write-ahead log record.
+pub trait WalLog: Sized {
+    fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError>;
+    fn to_wal_bytes(&self) -> Vec<u8>;
+}
+
+impl<K, V> WalLog for FlatSetIndexLog<K, V> {
+    #[inline]
+    fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        FlatSetIndexLog::from_wal_bytes(bytes)
+    }
+
+    #[inline]
+    fn to_wal_bytes(&self) -> Vec<u8> {
+        FlatSetIndexLog::to_wal_bytes(self)
+    }
+}
+
+impl<N, I> WalLog for NodeSetIndexLog<N, I> {
+    #[inline]
+    fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        NodeSetIndexLog::from_wal_bytes(bytes)
+    }
+
+    #[inline]
+    fn to_wal_bytes(&self) -> Vec<u8> {
+        NodeSetIndexLog::to_wal_bytes(self)
+    }
+}
+
+impl<K, V: CodecValue> WalLog for OneIndexLog<K, V> {
+    #[inline]
+    fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        OneIndexLog::from_wal_bytes(bytes)
+    }
+
+    #[inline]
+    fn to_wal_bytes(&self) -> Vec<u8> {
+        OneIndexLog::to_wal_bytes(self)
+    }
+}
+
+impl<K> WalLog for TreeIndexLog<K> {
+    #[inline]
+    fn from_wal_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        TreeIndexLog::from_wal_bytes(bytes)
+    }
+
+    #[inline]
+    fn to_wal_bytes(&self) -> Vec<u8> {
+        TreeIndexLog::to_wal_bytes(self)
+    }
+}
+
+/// Each part is written with its length first, so a tuple of logs staged by
+/// one [`FastSetTrx`](crate::FastSetTrx) becomes a single record.
+macro_rules! wal_tuple {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: WalLog),+> WalLog for ($($t,)+) {
+            fn from_wal_bytes(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+                let log = ($($t::from_wal_bytes(read_part(&mut bytes)?)?,)+);
+                expect_end(bytes)?;
+                Ok(log)
+            }
+
+            fn to_wal_bytes(&self) -> Vec<u8> {
+                let mut out = Vec::new();
+
+                $(
+                    let part = self.$i.to_wal_bytes();
+                    write_varint(&mut out, part.len() as u32);
+                    out.extend_from_slice(&part);
+                )+
+
+                out
+            }
+        }
+    };
+}
+
+wal_tuple!(A 0, B 1);
+wal_tuple!(A 0, B 1, C 2);
+wal_tuple!(A 0, B 1, C 2, D 3);
+wal_tuple!(A 0, B 1, C 2, D 3, E 4);
+wal_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+fn read_part<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+    let len = read_varint(bytes)? as usize;
+
+    if bytes.len() < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+
+    let (part, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(part)
+}
+
+/// Decodes and applies `records` to `target` in order, returning `true` if
+/// anything changed.
+///
+/// Logs hold the resulting state of what they touch, so records must be
+/// replayed against the base they were staged on. Records before a malformed
+/// one stay applied.
+pub fn replay<T, R>(target: &mut T, records: R) -> Result<bool, DecodeError>
+where
+    T: Apply,
+    T::Log: WalLog,
+    R: IntoIterator,
+    R::Item: AsRef<[u8]>,
+{
+    let mut changed = false;
+
+    for record in records {
+        changed |= target.apply(T::Log::from_wal_bytes(record.as_ref())?);
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlatSetIndex, NodeSetIndex, Tree, one_index::OneIndex};
+
+    type Logs = (
+        TreeIndexLog<u32>,
+        NodeSetIndexLog<u32, u32>,
+        FlatSetIndexLog<u32, u32>,
+        OneIndexLog<u32, u32>,
+    );
+
+    #[test]
+    fn replays_records_in_order() {
+        let mut primary = (
+            Tree::<u32>::from_iter([(1, None)]),
+            NodeSetIndex::<u32, u32>::new(),
+            FlatSetIndex::<u32, u32>::new(),
+            OneIndex::<u32, u32>::new(),
+        );
+        let mut replica = primary.clone();
+        let mut records = Vec::new();
+
+        for (node, item) in [(2, 20), (3, 30)] {
+            let mut log = Logs::default();
+
+            log.0.insert(&primary.0, Some(1), node);
+            log.1.insert(&primary.1, &primary.0, &log.0, node, item);
+            log.2.insert(&primary.2, node, item);
+            log.3.insert(&primary.3, node, item);
+
+            records.push(log.to_wal_bytes());
+            primary.apply(log);
+        }
+
+        let mut log = Logs::default();
+        log.3.remove(&primary.3, 2);
+        records.push(log.to_wal_bytes());
+        primary.apply(log);
+
+        assert_eq!(replay(&mut replica, &records), Ok(true));
+        assert_eq!(replica.0.parent(3), Some(1));
+        assert!(replica.1.subtree_items(1).contains(20));
+        assert!(replica.1.subtree_items(1).contains(30));
+        assert!(replica.2.contains(3, 30));
+        assert_eq!(replica.3.get(2), None);
+        assert_eq!(replica.3.get(3), Some(&30));
+        assert_eq!(replay(&mut replica, &records[2..]), Ok(false));
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        let mut tree = Tree::<u32>::new();
+        let mut log = TreeIndexLog::new();
+        log.insert(&tree, None, 1);
+
+        let mut bytes = log.to_wal_bytes();
+        bytes.push(0);
+
+        assert_eq!(replay(&mut tree, [&bytes]), Err(DecodeError::TrailingBytes));
+        assert_eq!(
+            replay(&mut tree, [&bytes[..2]]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        let mut flag = log.to_wal_bytes();
+        flag[2] = 2;
+        assert_eq!(replay(&mut tree, [&flag]), Err(DecodeError::Invalid));
+        assert!(tree.all_nodes().next().is_none());
+
+        assert_eq!(
+            crate::u32based::OneIndexLog::<u32>::from_wal_bytes(&[1, 5, 2]).map(|_| ()),
+            Err(DecodeError::Invalid)
+        );
+    }
+
+    #[test]
+    fn round_trips_max_ids() {
+        let mut tree = Tree::<u32>::new();
+        let mut log = TreeIndexLog::new();
+        log.insert(&tree, None, u32::MAX);
+        log.insert(&tree, Some(u32::MAX), 1);
+
+        assert_eq!(replay(&mut tree, [log.to_wal_bytes()]), Ok(true));
+        assert_eq!(tree.parent(1), Some(u32::MAX));
+        assert_eq!(tree.parent(u32::MAX), None);
+    }
+}